@@ -0,0 +1,138 @@
+// Замер аналитики цен по HTTP на тестовых данных (POST /dev/seed: 500 товаров × 10 магазинов).
+// Ходит только в публичные GET, поэтому одним прогоном можно сравнить две сборки сервера: прежняя
+// на BEFORE_URL, новая на BASE_URL, у каждой своя база со своими индексами и тестовыми данными
+// (id берутся у каждой сборки). Запросы к ним чередуются, в конце — таблица «до/после» в markdown
+// для docs/database.md. Кэш ответов у новой сборки выключается
+// CACHE_TTL_SECS=0, иначе замеряется он, а не запросы к базе.
+//   BEFORE_URL=http://localhost:8081 cargo run --example bench_insights
+// Переменные: BASE_URL (http://localhost:8080), BEFORE_URL (нет — замер одной сборки), RUNS —
+// повторов на каждый запрос (5), PRODUCTS — сколько товаров взять для /products/:id/insights (50).
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+}
+
+// _id из JSON API: {"$oid": "..."} или строка
+fn ids(page: &Value) -> Vec<String> {
+    page["items"].as_array().map(|items| items.iter().filter_map(|v| {
+        let id = &v["_id"];
+        id.get("$oid").unwrap_or(id).as_str().map(|s| s.to_string())
+    }).collect()).unwrap_or_default()
+}
+
+async fn list(client: &reqwest::Client, url: String, query: &[(&str, &str)]) -> Result<Vec<String>> {
+    let page: Value = client.get(&url).query(query).send().await?.error_for_status()?.json().await?;
+    Ok(ids(&page))
+}
+
+#[derive(Default)]
+struct Timings {
+    samples: Vec<Duration>,
+}
+
+impl Timings {
+    fn ms(d: Duration) -> f64 { d.as_secs_f64() * 1000.0 }
+
+    fn mean(&self) -> f64 { self.samples.iter().map(|d| Self::ms(*d)).sum::<f64>() / self.samples.len().max(1) as f64 }
+
+    fn pct(&self, p: f64) -> f64 {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        sorted.get(((sorted.len().max(1) - 1) as f64 * p).round() as usize).map(|d| Self::ms(*d)).unwrap_or_default()
+    }
+
+    fn report(&self, name: &str) {
+        println!("{:<48} n={:<5} mean={:>8.1} p50={:>8.1} p95={:>8.1} max={:>8.1} ms", name, self.samples.len(), self.mean(), self.pct(0.5), self.pct(0.95), self.pct(1.0));
+    }
+}
+
+async fn get(client: &reqwest::Client, url: &str) -> Result<Duration> {
+    let started = Instant::now();
+    let res = client.get(url).send().await?.error_for_status().with_context(|| url.to_string())?;
+    res.bytes().await?;
+    Ok(started.elapsed())
+}
+
+// urls[i] — запросы к i-й сборке, поровну; запросы к сборкам чередуются
+async fn measure(client: &reqwest::Client, urls: &[Vec<String>], runs: usize) -> Result<Vec<Timings>> {
+    let mut out: Vec<Timings> = urls.iter().map(|_| Timings::default()).collect();
+    // прогрев: кэш WiredTiger и соединения
+    for u in urls { if let Some(url) = u.first() { get(client, url).await?; } }
+    let n = urls.iter().map(|u| u.len()).min().unwrap_or(0);
+    for run in 0..runs {
+        for k in 0..n {
+            for i in 0..urls.len() {
+                // первой идёт то одна, то другая сборка
+                let i = (i + run) % urls.len();
+                out[i].samples.push(get(client, &urls[i][k]).await?);
+            }
+        }
+    }
+    Ok(out)
+}
+
+struct Target {
+    base: String,
+    store_ids: Vec<String>,
+    product_ids: Vec<String>,
+}
+
+impl Target {
+    fn urls(&self, stores: bool, query: &str) -> Vec<String> {
+        match stores {
+            true => self.store_ids.iter().map(|id| format!("{}/stores/{id}/products/insights{query}", self.base)).collect(),
+            false => self.product_ids.iter().map(|id| format!("{}/products/{id}/insights{query}", self.base)).collect(),
+        }
+    }
+}
+
+async fn target(client: &reqwest::Client, base: String, products: usize) -> Result<Target> {
+    let store_ids = list(client, format!("{base}/stores"), &[("name", "Тест-магазин"), ("limit", "200")]).await?;
+    let mut product_ids = list(client, format!("{base}/products"), &[("title", "Тест-товар"), ("limit", "200")]).await?;
+    if store_ids.is_empty() || product_ids.is_empty() { bail!("{base}: нет тестовых данных, сначала POST /dev/seed"); }
+    product_ids.truncate(products);
+    Ok(Target { base, store_ids, product_ids })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let base = std::env::var("BASE_URL").unwrap_or_else(|_| "http://localhost:8080".into());
+    let before = std::env::var("BEFORE_URL").ok().filter(|s| !s.is_empty());
+    let runs: usize = env_or("RUNS", 5);
+    let products: usize = env_or("PRODUCTS", 50);
+    let client = reqwest::Client::new();
+
+    let mut targets: Vec<Target> = Vec::new();
+    if let Some(before) = before { targets.push(target(&client, before, products).await?); }
+    targets.push(target(&client, base, products).await?);
+    for t in &targets { println!("{}: {} магазинов, {} товаров в выборке, {runs} повторов", t.base, t.store_ids.len(), t.product_ids.len()); }
+
+    // середина истории тестовых данных (seed пишет её за последние 60 дней)
+    let as_of = format!("as_of={}", chrono::Utc::now().timestamp_millis() - 20 * 24 * 3600 * 1000);
+    let cases = [
+        ("GET /products/:id/insights", false, String::new()),
+        ("GET /products/:id/insights?as_of", false, format!("?{as_of}")),
+        ("GET /stores/:id/products/insights", true, String::new()),
+        ("GET /stores/:id/products/insights?as_of", true, format!("?{as_of}")),
+        ("GET /stores/:id/products/insights?outliers", true, "?outliers=mad".to_string()),
+    ];
+    let mut rows: Vec<(&str, Vec<Timings>)> = Vec::new();
+    for (name, stores, query) in cases {
+        let urls: Vec<Vec<String>> = targets.iter().map(|t| t.urls(stores, &query)).collect();
+        let t = measure(&client, &urls, runs).await?;
+        for (target, t) in targets.iter().zip(&t) { t.report(&format!("{name} [{}]", target.base)); }
+        rows.push((name, t));
+    }
+    if targets.len() == 2 {
+        println!("\n| Запрос | до, mean | до, p95 | после, mean | после, p95 |\n|---|---:|---:|---:|---:|");
+        for (name, t) in &rows {
+            println!("| `{}` | {:.1} | {:.1} | {:.1} | {:.1} |", name.trim_start_matches("GET "), t[0].mean(), t[0].pct(0.95), t[1].mean(), t[1].pct(0.95));
+        }
+    }
+    Ok(())
+}
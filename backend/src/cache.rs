@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+
+use crate::state::AppState;
+
+// Кэш ответов публичных GET, которые фронтенд и мини-приложение Telegram запрашивают чаще, чем
+// меняются данные: список товаров, аналитика магазина, рейтинг пользователей. Ответ хранится с
+// TTL (CACHE_TTL_SECS, по умолчанию 30; 0 — не кэшировать) в пределах CACHE_MAX_ENTRIES записей и
+// CACHE_MAX_BYTES байт, лишние вытесняются по давности использования. У каждой записи — коллекции,
+// из которых собран ответ; запись (POST/PUT/DELETE) сбрасывает только ответы, зависящие от
+// изменённых ею коллекций, фоновые задачи сбрасывают их сами (invalidate). На ответы ставится
+// ETag: If-None-Match с тем же значением получает 304 без тела.
+
+const DEFAULT_TTL_SECS: u64 = 30;
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+const DEFAULT_MAX_BYTES: usize = 32 << 20;
+
+// все коллекции, которые читают кэшируемые ответы
+const ALL: &[&str] = &["products", "product_price_stats", "stores", "regions", "store_items", "store_activities", "users"];
+
+// шаблон маршрута -> коллекции, из которых собран ответ; None — не кэшируется
+fn sources(route: &str) -> Option<&'static [&'static str]> {
+    match route {
+        "/products" => Some(&["products", "product_price_stats"]),
+        "/stores/:id/products/insights" => Some(&["stores", "regions", "products", "store_items", "store_activities"]),
+        "/ratings/users" => Some(&["users"]),
+        _ => None,
+    }
+}
+
+// коллекции, которые может изменить запись по этому маршруту (из тех, что читают кэшируемые ответы)
+fn touched(route: &str) -> &'static [&'static str] {
+    const PRICES: &[&str] = &["products", "stores", "store_items", "store_activities", "product_price_stats"];
+    match route.split('/').nth(1) {
+        // удаление и корзина товаров и магазинов затрагивают позиции, историю и сводки цен
+        Some("products") | Some("stores") | Some("prices") | Some("operations") => PRICES,
+        Some("categories") => &["products"],
+        Some("regions") | Some("chains") => &["regions", "stores"],
+        Some("users") | Some("ratings") | Some("telegram") => &["users"],
+        Some("auth") | Some("upload") | Some("settings") | Some("receipts") | Some("fns") => &[],
+        // корзина, починка базы, импорт, тестовые данные и всё неизвестное
+        _ => ALL,
+    }
+}
+
+#[derive(Clone)]
+struct Cached {
+    body: Bytes,
+    content_type: Option<HeaderValue>,
+    etag: String,
+}
+
+struct Entry {
+    value: Cached,
+    sources: &'static [&'static str],
+    expires: Instant,
+    used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    bytes: usize,
+    tick: u64,
+    // счётчик сбросов по коллекции: ответ, собранный до записи, не попадает в кэш после неё
+    versions: HashMap<&'static str, u64>,
+}
+
+pub struct ResponseCache {
+    inner: Mutex<Inner>,
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self { ResponseCache::new(Duration::from_secs(DEFAULT_TTL_SECS), DEFAULT_MAX_ENTRIES, DEFAULT_MAX_BYTES) }
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, max_entries: usize, max_bytes: usize) -> Self {
+        ResponseCache { inner: Mutex::new(Inner::default()), ttl, max_entries, max_bytes }
+    }
+
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: u64| std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default);
+        ResponseCache::new(
+            Duration::from_secs(var("CACHE_TTL_SECS", DEFAULT_TTL_SECS)),
+            var("CACHE_MAX_ENTRIES", DEFAULT_MAX_ENTRIES as u64) as usize,
+            var("CACHE_MAX_BYTES", DEFAULT_MAX_BYTES as u64) as usize,
+        )
+    }
+
+    fn enabled(&self) -> bool { !self.ttl.is_zero() && self.max_entries > 0 }
+
+    fn get(&self, key: &str) -> Option<Cached> {
+        let mut inner = self.inner.lock().ok()?;
+        inner.tick += 1;
+        let tick = inner.tick;
+        let entry = inner.entries.get_mut(key)?;
+        if entry.expires > Instant::now() {
+            entry.used = tick;
+            return Some(entry.value.clone());
+        }
+        if let Some(old) = inner.entries.remove(key) { inner.bytes -= old.value.body.len(); }
+        None
+    }
+
+    fn versions(&self, sources: &[&'static str]) -> Vec<u64> {
+        let Ok(inner) = self.inner.lock() else { return Vec::new() };
+        sources.iter().map(|s| inner.versions.get(s).copied().unwrap_or(0)).collect()
+    }
+
+    fn put(&self, key: String, sources: &'static [&'static str], seen: &[u64], value: Cached) {
+        let size = value.body.len();
+        if !self.enabled() || size > self.max_bytes { return; }
+        let Ok(mut inner) = self.inner.lock() else { return };
+        // данные поменялись, пока ответ собирался
+        if sources.iter().zip(seen).any(|(s, v)| inner.versions.get(s).copied().unwrap_or(0) != *v) { return; }
+        if let Some(old) = inner.entries.remove(&key) { inner.bytes -= old.value.body.len(); }
+        let now = Instant::now();
+        let expired: Vec<String> = inner.entries.iter().filter(|(_, e)| e.expires <= now).map(|(k, _)| k.clone()).collect();
+        for k in expired { if let Some(old) = inner.entries.remove(&k) { inner.bytes -= old.value.body.len(); } }
+        while inner.entries.len() >= self.max_entries || inner.bytes + size > self.max_bytes {
+            let Some(lru) = inner.entries.iter().min_by_key(|(_, e)| e.used).map(|(k, _)| k.clone()) else { break };
+            if let Some(old) = inner.entries.remove(&lru) { inner.bytes -= old.value.body.len(); }
+        }
+        inner.tick += 1;
+        let used = inner.tick;
+        inner.bytes += size;
+        inner.entries.insert(key, Entry { value, sources, expires: now + self.ttl, used });
+    }
+
+    // сбросить ответы, собранные из этих коллекций
+    pub fn invalidate(&self, collections: &[&str]) {
+        let Ok(mut inner) = self.inner.lock() else { return };
+        for c in collections {
+            if let Some(name) = ALL.iter().find(|a| *a == c) { *inner.versions.entry(name).or_default() += 1; }
+        }
+        let stale: Vec<String> = inner.entries.iter().filter(|(_, e)| e.sources.iter().any(|s| collections.contains(s))).map(|(k, _)| k.clone()).collect();
+        for k in stale { if let Some(old) = inner.entries.remove(&k) { inner.bytes -= old.value.body.len(); } }
+    }
+
+    pub fn clear(&self) { self.invalidate(ALL); }
+}
+
+fn etag(body: &[u8]) -> String {
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    body.hash(&mut h);
+    format!("\"{:016x}-{:x}\"", h.finish(), body.len())
+}
+
+// If-None-Match: список тегов через запятую или *, слабые (W/) сравниваются без префикса
+fn not_modified(if_none_match: Option<&HeaderValue>, etag: &str) -> bool {
+    let Some(v) = if_none_match.and_then(|v| v.to_str().ok()) else { return false };
+    v.split(',').map(|t| t.trim()).any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+fn respond(value: Cached, if_none_match: Option<&HeaderValue>, hit: bool) -> Response {
+    let mut res = if not_modified(if_none_match, &value.etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mut res = Response::new(Body::from(value.body));
+        if let Some(ct) = value.content_type { res.headers_mut().insert(header::CONTENT_TYPE, ct); }
+        res
+    };
+    let headers = res.headers_mut();
+    if let Ok(tag) = HeaderValue::from_str(&value.etag) { headers.insert(header::ETAG, tag); }
+    // браузер хранит ответ, но каждый раз сверяет ETag
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    headers.insert("x-cache", HeaderValue::from_static(if hit { "hit" } else { "miss" }));
+    res
+}
+
+// Middleware на весь роутер: GET кэшируемых маршрутов — из кэша или с сохранением ответа,
+// остальные методы после выполнения сбрасывают зависящие от маршрута ответы
+pub async fn http_cache(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map(|m| m.as_str().to_string()).unwrap_or_default();
+    if matches!(*req.method(), Method::HEAD | Method::OPTIONS) { return next.run(req).await; }
+    if *req.method() != Method::GET {
+        let res = next.run(req).await;
+        // 4xx ничего не записал
+        if !res.status().is_client_error() { state.cache.invalidate(touched(&route)); }
+        return res;
+    }
+    let Some(sources) = sources(&route) else { return next.run(req).await };
+    let key = req.uri().to_string();
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+    if let Some(hit) = state.cache.get(&key) { return respond(hit, if_none_match.as_ref(), true); }
+
+    let seen = state.cache.versions(sources);
+    let res = next.run(req).await;
+    if res.status() != StatusCode::OK { return res; }
+    let (parts, body) = res.into_parts();
+    let body = match to_bytes(body, usize::MAX).await { Ok(b) => b, Err(e) => { error!(?e, "read response body failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let value = Cached { etag: etag(&body), content_type: parts.headers.get(header::CONTENT_TYPE).cloned(), body };
+    state.cache.put(key, sources, &seen, value.clone());
+    respond(value, if_none_match.as_ref(), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached(body: &'static str) -> Cached {
+        Cached { body: Bytes::from_static(body.as_bytes()), content_type: None, etag: etag(body.as_bytes()) }
+    }
+
+    fn put(cache: &ResponseCache, key: &str, sources: &'static [&'static str], body: &'static str) {
+        let seen = cache.versions(sources);
+        cache.put(key.to_string(), sources, &seen, cached(body));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 2, 1 << 20);
+        put(&cache, "/a", &["products"], "a");
+        put(&cache, "/b", &["products"], "b");
+        assert!(cache.get("/a").is_some());
+        put(&cache, "/c", &["products"], "c");
+        assert!(cache.get("/b").is_none());
+        assert!(cache.get("/a").is_some());
+        assert!(cache.get("/c").is_some());
+    }
+
+    #[test]
+    fn evicts_by_size() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 10, 8);
+        put(&cache, "/a", &["users"], "aaaa");
+        put(&cache, "/b", &["users"], "bbbb");
+        put(&cache, "/c", &["users"], "cccc");
+        assert!(cache.get("/a").is_none());
+        assert!(cache.get("/b").is_some() && cache.get("/c").is_some());
+        // больше лимита целиком — не кэшируется
+        put(&cache, "/d", &["users"], "ddddddddd");
+        assert!(cache.get("/d").is_none());
+    }
+
+    #[test]
+    fn invalidates_only_dependent_responses() {
+        let cache = ResponseCache::default();
+        put(&cache, "/products", sources("/products").unwrap(), "p");
+        put(&cache, "/ratings/users", sources("/ratings/users").unwrap(), "u");
+        cache.invalidate(touched("/users/:id"));
+        assert!(cache.get("/ratings/users").is_none());
+        assert!(cache.get("/products").is_some());
+        cache.invalidate(touched("/categories/:id"));
+        assert!(cache.get("/products").is_none());
+    }
+
+    #[test]
+    fn response_built_before_write_is_not_stored() {
+        let cache = ResponseCache::default();
+        let sources: &'static [&'static str] = &["users"];
+        let seen = cache.versions(sources);
+        // запись пришла, пока ответ собирался
+        cache.invalidate(&["users"]);
+        cache.put("/ratings/users".into(), sources, &seen, cached("old"));
+        assert!(cache.get("/ratings/users").is_none());
+        // сброс другой коллекции не мешает
+        let seen = cache.versions(sources);
+        cache.invalidate(&["stores"]);
+        cache.put("/ratings/users".into(), sources, &seen, cached("new"));
+        assert!(cache.get("/ratings/users").is_some());
+    }
+
+    #[test]
+    fn disabled_with_zero_ttl() {
+        let cache = ResponseCache::new(Duration::ZERO, 10, 1 << 20);
+        put(&cache, "/a", &["users"], "a");
+        assert!(cache.get("/a").is_none());
+    }
+
+    #[test]
+    fn if_none_match() {
+        let tag = etag(b"body");
+        let check = |v: &str| not_modified(Some(&HeaderValue::from_str(v).unwrap()), &tag);
+        assert!(check(&tag));
+        assert!(check("*"));
+        assert!(check(&format!("\"other\", {}", tag)));
+        assert!(check(&format!("\"other\",W/{}", tag)));
+        assert!(!check("\"other\", \"another\""));
+        assert!(!not_modified(None, &tag));
+    }
+}
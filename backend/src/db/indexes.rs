@@ -0,0 +1,84 @@
+use anyhow::Result;
+use bson::doc;
+use mongodb::{options::IndexOptions, Database, IndexModel};
+use tracing::{info, warn};
+
+// Индексы, без которых не работают запросы (гео-поиск и т.п.), создаём при старте.
+// create_index идемпотентен: повторный вызов с тем же ключом/именем ничего не меняет.
+pub async fn ensure_indexes(db: &Database) -> Result<()> {
+    let stores = db.collection::<bson::Document>("stores");
+    let idx_location = IndexModel::builder()
+        .keys(doc!{"location": "2dsphere"})
+        .options(IndexOptions::builder().name(Some("location_2dsphere".to_string())).build())
+        .build();
+    stores.create_index(idx_location, None).await?;
+    let idx_osm = IndexModel::builder()
+        .keys(doc!{"osm_id": 1})
+        .options(IndexOptions::builder().name(Some("osm_id".to_string())).sparse(true).build())
+        .build();
+    stores.create_index(idx_osm, None).await?;
+    let idx_chain = IndexModel::builder()
+        .keys(doc!{"chain_id": 1})
+        .options(IndexOptions::builder().name(Some("chain_id".to_string())).build())
+        .build();
+    stores.create_index(idx_chain, None).await?;
+    let idx_region = IndexModel::builder()
+        .keys(doc!{"region_id": 1})
+        .options(IndexOptions::builder().name(Some("region_id".to_string())).build())
+        .build();
+    stores.create_index(idx_region, None).await?;
+
+    let products = db.collection::<bson::Document>("products");
+    let idx_barcodes = IndexModel::builder()
+        .keys(doc!{"barcodes": 1})
+        .options(IndexOptions::builder().name(Some("barcodes_unique".to_string())).unique(true).sparse(true).build())
+        .build();
+    products.create_index(idx_barcodes, None).await?;
+
+    // логины уникальны; пока в базе есть дубли, индекс не создаётся — их показывает GET /integrity
+    let users = db.collection::<bson::Document>("users");
+    let idx_username = IndexModel::builder()
+        .keys(doc!{"username": 1})
+        .options(IndexOptions::builder().name(Some("username_unique".to_string())).unique(true).build())
+        .build();
+    if let Err(e) = users.create_index(idx_username, None).await { warn!(?e, "username_unique index not created: duplicate usernames"); }
+
+    // цены и история для аналитики: позиция одна на пару магазин/товар; пока в базе есть дубли,
+    // уникальный индекс не создаётся — их показывает и убирает GET /integrity, POST /integrity/repair
+    let store_items = db.collection::<bson::Document>("store_items");
+    let idx_store_product = IndexModel::builder()
+        .keys(doc!{"store_id": 1, "product_id": 1})
+        .options(IndexOptions::builder().name(Some("store_product_unique".to_string())).unique(true).build())
+        .build();
+    if let Err(e) = store_items.create_index(idx_store_product, None).await { warn!(?e, "store_product_unique index not created: duplicate store items"); }
+    let idx_product = IndexModel::builder()
+        .keys(doc!{"product_id": 1})
+        .options(IndexOptions::builder().name(Some("product_id".to_string())).build())
+        .build();
+    store_items.create_index(idx_product, None).await?;
+    let activities = db.collection::<bson::Document>("store_activities");
+    let idx_product_ts = IndexModel::builder()
+        .keys(doc!{"product_id": 1, "ts_ms": 1})
+        .options(IndexOptions::builder().name(Some("product_ts".to_string())).build())
+        .build();
+    activities.create_index(idx_product_ts, None).await?;
+
+    // импорт одного файла идёт одним заданием: второй запуск (или resume) на том же файле получает
+    // ошибку дубликата, а не начинает параллельный проход
+    let import_jobs = db.collection::<bson::Document>("import_jobs");
+    let idx_running = IndexModel::builder()
+        .keys(doc!{"file": 1})
+        .options(IndexOptions::builder().name(Some("file_running_unique".to_string())).unique(true).partial_filter_expression(doc!{"status": "running"}).build())
+        .build();
+    import_jobs.create_index(idx_running, None).await?;
+
+    // журнал админских изменений: свежие записи, по автору и по документу
+    let audit = db.collection::<bson::Document>("audit_log");
+    for (name, keys) in [("ts_ms", doc!{"ts_ms": -1}), ("actor_ts", doc!{"actor": 1, "ts_ms": -1}), ("entity_ts", doc!{"entity": 1, "entity_id": 1, "ts_ms": -1})] {
+        let idx = IndexModel::builder().keys(keys).options(IndexOptions::builder().name(Some(name.to_string())).build()).build();
+        audit.create_index(idx, None).await?;
+    }
+
+    info!("indexes ensured");
+    Ok(())
+}
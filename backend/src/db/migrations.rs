@@ -0,0 +1,98 @@
+use anyhow::Result;
+use bson::{doc, Bson, Document};
+use mongodb::Database;
+use tracing::info;
+
+// Цены и суммы раньше хранились как double в рублях, теперь — Int64 в копейках
+// (количество в чеке и размер упаковки — Int64 в тысячных). Переводим только значения типа double,
+// поэтому повторный запуск ничего не меняет. Вызывается при старте и после импорта дампа: /export
+// отдаёт Money/Quantity числами в рублях и кг/л, и /import кладёт их в базу как double.
+
+// (коллекция, поле верхнего уровня, минимальных единиц в одной)
+const DOUBLE_FIELDS: [(&str, &str, i64); 3] = [
+    ("store_items", "price", 100),
+    ("store_activities", "price", 100),
+    ("products", "pack_size", 1000),
+];
+
+pub async fn migrate_money(db: &Database) -> Result<()> {
+    for (name, field, scale) in DOUBLE_FIELDS {
+        let col = db.collection::<Document>(name);
+        let res = col.update_many(doc!{field: {"$type": "double"}}, vec![doc!{"$set": {field: to_minor(&format!("${field}"), scale)}}], None).await?;
+        if res.modified_count > 0 { info!(collection = name, field, migrated = res.modified_count, "values converted to minor units"); }
+    }
+
+    let ops = db.collection::<Document>("operations");
+    let filter = doc!{"$or": [
+        {"amount": {"$type": "double"}},
+        {"items.price": {"$type": "double"}},
+        {"items.quantity": {"$type": "double"}},
+    ]};
+    let items = doc!{"$map": {"input": "$items", "as": "it", "in": {"$mergeObjects": ["$$it", {
+        "price": if_double("$$it.price", 100),
+        "quantity": if_double("$$it.quantity", 1000),
+    }]}}};
+    let pipeline = vec![doc!{"$set": {"amount": if_double("$amount", 100), "items": items}}];
+    let res = ops.update_many(filter, pipeline, None).await?;
+    if res.modified_count > 0 { info!(collection = "operations", migrated = res.modified_count, "amounts converted to kopecks"); }
+    Ok(())
+}
+
+fn to_minor(field: &str, scale: i64) -> Bson {
+    Bson::Document(doc!{"$toLong": {"$round": [{"$multiply": [field, scale]}, 0]}})
+}
+
+// внутри одного документа часть полей может быть уже целой
+fn if_double(field: &str, scale: i64) -> Bson {
+    Bson::Document(doc!{"$cond": [{"$eq": [{"$type": field}, "double"]}, to_minor(field, scale), field]})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Product, StoreItem, Unit};
+    use crate::money::{Money, Quantity};
+    use bson::oid::ObjectId;
+
+    // /export -> JSON -> /import: документ в том виде, в каком import_all вставляет его в базу
+    fn round_trip<T: serde::Serialize>(value: &T) -> Document {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    // то же, что делает migrate_money в базе: double * scale, округление, Int64
+    fn migrate(collection: &str, d: &mut Document) {
+        for (name, field, scale) in DOUBLE_FIELDS {
+            if name != collection { continue; }
+            if let Some(Bson::Double(v)) = d.get(field) { let v = (v * scale as f64).round() as i64; d.insert(field, v); }
+        }
+    }
+
+    #[test]
+    fn pack_size_survives_export_import() {
+        let product = Product {
+            id: Some(ObjectId::new()), title: "Молоко".into(), desc: String::new(), image_url: None, category_ids: vec![ObjectId::new()],
+            unit: Some(Unit::L), pack_size: Some(Quantity::from_minor(930)), barcodes: vec![], brand: None, category_hints: vec![],
+            imported_fields: vec![], aliases: vec![], deleted_at: None, deleted_by: None,
+        };
+        let mut d = round_trip(&product);
+        assert_eq!(d.get("pack_size"), Some(&Bson::Double(0.93)));
+        migrate("products", &mut d);
+        assert_eq!(d.get("pack_size"), Some(&Bson::Int64(930)));
+        let back: Product = crate::money::from_document_raw(&d).unwrap();
+        assert_eq!((back.id, back.pack_size), (product.id, product.pack_size));
+        // цена за литр считается так же, как до выгрузки
+        assert_eq!(back.unit_price(Money::from_f64(89.9)), Some(Money::from_f64(96.67)));
+        // повторный запуск ничего не меняет
+        migrate("products", &mut d);
+        assert_eq!(d.get("pack_size"), Some(&Bson::Int64(930)));
+    }
+
+    #[test]
+    fn prices_survive_export_import() {
+        let item = StoreItem { id: Some(ObjectId::new()), store_id: ObjectId::new(), product_id: ObjectId::new(), price: Money::from_f64(89.9) };
+        let mut d = round_trip(&item);
+        migrate("store_items", &mut d);
+        let back: StoreItem = crate::money::from_document_raw(&d).unwrap();
+        assert_eq!((back.store_id, back.price), (item.store_id, item.price));
+    }
+}
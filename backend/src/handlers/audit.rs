@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use axum::{body::{to_bytes, Body}, extract::{MatchedPath, Request, State}, http::{Method, StatusCode}, middleware::Next, response::{IntoResponse, Response}};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use tracing::error;
+
+use crate::handlers::auth::Principal;
+use crate::handlers::listing::{FilterKind, ListQuery, ListSpec};
+use crate::models::AuditEntry;
+use crate::state::AppState;
+
+// Журнал админских изменений. Middleware на админском роутере (внутри require_admin) пишет в
+// audit_log каждый успешный POST/PUT/DELETE: кто, какое действие, над каким документом и что в
+// нём поменялось — снимок документа до и после запроса сравнивается по полям верхнего уровня.
+// Действия над многими документами сразу (импорт, dev, очистка корзины, починка базы) пишутся без diff.
+// Пробные запуски (dry_run в ответе) ничего не меняют и в журнал не попадают.
+
+fn audit_log(state: &AppState) -> mongodb::Collection<AuditEntry> { state.db.collection::<AuditEntry>("audit_log") }
+
+// как найти изменённый документ
+enum Locate {
+    None,
+    // _id из параметра :id
+    Id,
+    // _id из ответа на создание
+    Created,
+    // позиция store_items по :id магазина и product_id (из пути или тела)
+    StoreItem,
+    Fixed(&'static str),
+}
+
+// (действие, коллекция, как найти документ) по методу и шаблону маршрута из routes.rs
+fn classify(method: &Method, route: &str) -> (&'static str, Option<&'static str>, Locate) {
+    let m = method.as_str();
+    match (m, route) {
+        ("POST", "/stores/:id/products") => ("add_item", Some("store_items"), Locate::StoreItem),
+        ("PUT", "/stores/:id/products/:product_id") => ("update_item", Some("store_items"), Locate::StoreItem),
+        ("DELETE", "/stores/:id/products/:product_id") => ("remove_item", Some("store_items"), Locate::StoreItem),
+        ("POST", "/products/:id/categories/:cat_id") => ("add_category", Some("products"), Locate::Id),
+        ("DELETE", "/products/:id/categories/:cat_id") => ("remove_category", Some("products"), Locate::Id),
+        ("POST", "/products/:id/merge") => ("merge", Some("products"), Locate::Id),
+        ("PUT", "/operations/:id/status") => ("update_status", Some("operations"), Locate::Id),
+        ("PUT", "/settings/telegram") => ("update", Some("settings"), Locate::Fixed("telegram")),
+        ("POST", "/chains/assign") => ("assign_chain", Some("stores"), Locate::None),
+        ("POST", "/ratings/grant") => ("grant_points", Some("users"), Locate::None),
+        ("POST", "/trash/purge") => ("purge_trash", None, Locate::None),
+        ("POST", "/integrity/repair") => ("integrity_repair", None, Locate::None),
+        ("POST", "/prices/rebuild") => ("rebuild_prices", Some("store_items"), Locate::None),
+        ("POST", "/prices/stats/rebuild") => ("rebuild_price_stats", Some("product_price_stats"), Locate::None),
+        ("POST", "/dev/clear") => ("dev_clear", None, Locate::None),
+        ("POST", "/dev/seed") => ("dev_seed", None, Locate::None),
+        ("POST", "/import") => ("import_dump", None, Locate::None),
+        ("POST", "/import/osm") => ("import_osm", Some("stores"), Locate::None),
+        ("POST", "/import/off") => ("import_off", Some("products"), Locate::None),
+        ("POST", "/import/jobs/:id/resume") => ("resume_import", Some("import_jobs"), Locate::Id),
+        ("POST", "/upload") => ("upload", None, Locate::None),
+        _ => {
+            let collection = match route.split('/').nth(1) {
+                Some("products") => Some("products"),
+                Some("stores") => Some("stores"),
+                Some("categories") => Some("categories"),
+                Some("regions") => Some("regions"),
+                Some("chains") => Some("store_chains"),
+                Some("users") => Some("users"),
+                Some("operations") => Some("operations"),
+                _ => None,
+            };
+            match m {
+                "POST" if route.ends_with("/restore") => ("restore", collection, Locate::Id),
+                "POST" if !route.contains(':') => ("create", collection, Locate::Created),
+                "PUT" if route.ends_with("/:id") => ("update", collection, Locate::Id),
+                "DELETE" if route.ends_with("/:id") => ("delete", collection, Locate::Id),
+                _ => ("other", collection, Locate::None),
+            }
+        }
+    }
+}
+
+// "/stores/:id/products/:product_id" + "/stores/1/products/2" -> {id: 1, product_id: 2}
+fn path_params(route: &str, path: &str) -> HashMap<String, String> {
+    route.split('/').zip(path.split('/')).filter_map(|(r, p)| r.strip_prefix(':').map(|name| (name.to_string(), p.to_string()))).collect()
+}
+
+// _id в JSON: {"$oid": "..."} или строка
+fn json_oid(v: &serde_json::Value) -> Option<ObjectId> {
+    v.get("$oid").unwrap_or(v).as_str().and_then(|s| ObjectId::from_str(s).ok())
+}
+
+fn id_filter(raw: &str) -> Document {
+    match ObjectId::from_str(raw) { Ok(oid) => doc!{"_id": oid}, Err(_) => doc!{"_id": raw} }
+}
+
+async fn snapshot(state: &AppState, collection: &str, filter: &Document) -> Option<Document> {
+    match state.db.collection::<Document>(collection).find_one(filter.clone(), None).await {
+        Ok(d) => d,
+        Err(e) => { error!(?e, collection, "audit snapshot failed"); None }
+    }
+}
+
+// хэш пароля в журнал не попадает — только факт изменения
+fn redact(field: &str, v: &Bson) -> Bson {
+    if field == "password_hash" { Bson::String("***".into()) } else { v.clone() }
+}
+
+// {field: {from, to}} по полям верхнего уровня; отсутствующая сторона не пишется
+pub fn diff(before: Option<&Document>, after: Option<&Document>) -> Document {
+    let empty = Document::new();
+    let (b, a) = (before.unwrap_or(&empty), after.unwrap_or(&empty));
+    let mut out = Document::new();
+    for key in b.keys().chain(a.keys().filter(|k| !b.contains_key(k.as_str()))) {
+        if key == "_id" { continue; }
+        let (from, to) = (b.get(key), a.get(key));
+        if from == to { continue; }
+        let mut change = Document::new();
+        if let Some(v) = from { change.insert("from", redact(key, v)); }
+        if let Some(v) = to { change.insert("to", redact(key, v)); }
+        out.insert(key.clone(), change);
+    }
+    out
+}
+
+// ответ с "dry_run": true — только предпросмотр (импорт OSM по умолчанию)
+fn is_dry_run(body: &serde_json::Value) -> bool { body["dry_run"].as_bool() == Some(true) }
+
+// id товаров, влитых при объединении, — из поля "merged" ответа
+fn merged_ids(body: &serde_json::Value) -> Option<Vec<String>> {
+    body["merged"].as_array().map(|ids| ids.iter().filter_map(json_oid).map(|id| id.to_hex()).collect())
+}
+
+pub async fn audit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if !matches!(*req.method(), Method::POST | Method::PUT | Method::DELETE) { return next.run(req).await; }
+    let Some(principal) = req.extensions().get::<Principal>().cloned() else { return next.run(req).await };
+    let route = req.extensions().get::<MatchedPath>().map(|m| m.as_str().to_string()).unwrap_or_default();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let (action, entity, locate) = classify(&method, &route);
+    let params = path_params(&route, &path);
+
+    // product_id новой позиции приходит в теле — читаем его и собираем запрос обратно
+    let mut req = req;
+    let mut item_product = params.get("product_id").cloned();
+    if matches!(locate, Locate::StoreItem) && item_product.is_none() {
+        let (parts, body) = req.into_parts();
+        let bytes = match to_bytes(body, 1 << 20).await { Ok(b) => b, Err(e) => { error!(?e, "read request body failed"); return StatusCode::BAD_REQUEST.into_response(); } };
+        item_product = serde_json::from_slice::<serde_json::Value>(&bytes).ok().and_then(|v| v["product_id"].as_str().map(|s| s.to_string()));
+        req = Request::from_parts(parts, Body::from(bytes));
+    }
+    let filter: Option<Document> = match &locate {
+        Locate::Id => params.get("id").map(|id| id_filter(id)),
+        Locate::Fixed(id) => Some(doc!{"_id": *id}),
+        Locate::StoreItem => match (params.get("id").and_then(|s| ObjectId::from_str(s).ok()), item_product.as_deref().and_then(|s| ObjectId::from_str(s).ok())) {
+            (Some(store_id), Some(product_id)) => Some(doc!{"store_id": store_id, "product_id": product_id}),
+            _ => None,
+        },
+        Locate::None | Locate::Created => None,
+    };
+    let before = match (entity, &filter) { (Some(c), Some(f)) => snapshot(&state, c, f).await, _ => None };
+
+    let mut res = next.run(req).await;
+    if !res.status().is_success() { return res; }
+
+    // id созданного документа, влитые товары и признак dry_run — из тела ответа
+    let mut filter = filter;
+    let mut merged = None;
+    if matches!(locate, Locate::Created) || matches!(action, "merge" | "import_osm") {
+        let (parts, body) = res.into_parts();
+        let bytes = match to_bytes(body, usize::MAX).await { Ok(b) => b, Err(e) => { error!(?e, "read response body failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        let json = serde_json::from_slice::<serde_json::Value>(&bytes).unwrap_or_default();
+        res = Response::from_parts(parts, Body::from(bytes));
+        if is_dry_run(&json) { return res; }
+        if matches!(locate, Locate::Created) { filter = json_oid(&json["_id"]).map(|oid| doc!{"_id": oid}); }
+        if action == "merge" { merged = merged_ids(&json); }
+    }
+    let after = match (entity, &filter) { (Some(c), Some(f)) => snapshot(&state, c, f).await, _ => None };
+
+    let entity_id = match &locate {
+        Locate::Id => params.get("id").cloned(),
+        Locate::Fixed(id) => Some(id.to_string()),
+        Locate::Created | Locate::StoreItem => after.as_ref().or(before.as_ref()).and_then(|d| d.get_object_id("_id").ok()).map(|id| id.to_hex()),
+        Locate::None => None,
+    };
+    let changes = (before.is_some() || after.is_some()).then(|| diff(before.as_ref(), after.as_ref()));
+    let entry = AuditEntry {
+        id: None,
+        ts_ms: chrono::Utc::now().timestamp_millis(),
+        actor: principal.username,
+        action: action.to_string(),
+        entity: entity.map(|e| e.to_string()),
+        entity_id,
+        method: method.to_string(),
+        path,
+        status: res.status().as_u16(),
+        changes,
+        merged_ids: merged,
+    };
+    if let Err(e) = audit_log(&state).insert_one(entry, None).await { error!(?e, "write audit entry failed"); }
+    res
+}
+
+const AUDIT_LIST: ListSpec = ListSpec {
+    filters: &[
+        ("actor", "actor", FilterKind::Exact),
+        ("action", "action", FilterKind::Exact),
+        ("entity", "entity", FilterKind::Exact),
+        ("entity_id", "entity_id", FilterKind::Exact),
+        ("ts_ms", "ts_ms", FilterKind::IntRange),
+    ],
+    sortable: &["ts_ms"],
+    default_sort: "-ts_ms",
+    legacy_limit: Some(100),
+};
+
+// GET /audit?actor=&entity=&entity_id=&action=&ts_ms_gte=&ts_ms_lte= — контракт списков (handlers::listing)
+pub async fn list_audit(State(state): State<AppState>, q: ListQuery) -> impl IntoResponse {
+    match q.fetch(&audit_log(&state), doc!{}, &AUDIT_LIST).await { Ok(page)=> page.into_response(), Err(e)=> e.into_response() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_routes() {
+        let (action, entity, locate) = classify(&Method::POST, "/products/:id/merge");
+        assert_eq!((action, entity), ("merge", Some("products")));
+        assert!(matches!(locate, Locate::Id));
+        let (action, entity, locate) = classify(&Method::POST, "/stores/:id/products");
+        assert_eq!((action, entity), ("add_item", Some("store_items")));
+        assert!(matches!(locate, Locate::StoreItem));
+        let (action, entity, locate) = classify(&Method::POST, "/categories");
+        assert_eq!((action, entity), ("create", Some("categories")));
+        assert!(matches!(locate, Locate::Created));
+        let (action, entity, _) = classify(&Method::PUT, "/chains/:id");
+        assert_eq!((action, entity), ("update", Some("store_chains")));
+        let (action, entity, _) = classify(&Method::DELETE, "/users/:id");
+        assert_eq!((action, entity), ("delete", Some("users")));
+        let (action, _, _) = classify(&Method::POST, "/stores/:id/restore");
+        assert_eq!(action, "restore");
+        let (action, entity, locate) = classify(&Method::POST, "/import/osm");
+        assert_eq!((action, entity), ("import_osm", Some("stores")));
+        assert!(matches!(locate, Locate::None));
+        let (action, entity, _) = classify(&Method::POST, "/integrity/repair");
+        assert_eq!((action, entity), ("integrity_repair", None));
+    }
+
+    #[test]
+    fn path_params_by_route() {
+        let p = path_params("/stores/:id/products/:product_id", "/stores/1/products/2");
+        assert_eq!(p.get("id").map(String::as_str), Some("1"));
+        assert_eq!(p.get("product_id").map(String::as_str), Some("2"));
+        assert_eq!(p.len(), 2);
+    }
+
+    #[test]
+    fn diff_top_level_fields() {
+        let before = doc!{"_id": 1, "title": "Молоко", "price": 8990i64, "gone": true};
+        let after = doc!{"_id": 1, "title": "Молоко", "price": 9990i64, "added": "x"};
+        let d = diff(Some(&before), Some(&after));
+        assert_eq!(d, doc!{
+            "price": {"from": 8990i64, "to": 9990i64},
+            "gone": {"from": true},
+            "added": {"to": "x"},
+        });
+        assert_eq!(diff(None, Some(&doc!{"_id": 1, "name": "a"})), doc!{"name": {"to": "a"}});
+        assert_eq!(diff(Some(&before), Some(&before)), Document::new());
+    }
+
+    #[test]
+    fn password_hash_is_redacted() {
+        let d = diff(Some(&doc!{"password_hash": "$argon2$old"}), Some(&doc!{"password_hash": "$argon2$new"}));
+        assert_eq!(d, doc!{"password_hash": {"from": "***", "to": "***"}});
+        assert_eq!(redact("username", &Bson::String("admin".into())), Bson::String("admin".into()));
+    }
+
+    #[test]
+    fn dry_run_and_merged_ids_from_response() {
+        assert!(is_dry_run(&serde_json::json!({"dry_run": true, "created": 3})));
+        assert!(!is_dry_run(&serde_json::json!({"dry_run": false})));
+        assert!(!is_dry_run(&serde_json::json!({"_id": "x"})));
+        let (a, b) = (ObjectId::new(), ObjectId::new());
+        let body = serde_json::json!({"product": {}, "merged": [a, b.to_hex()]});
+        assert_eq!(merged_ids(&body), Some(vec![a.to_hex(), b.to_hex()]));
+        assert_eq!(merged_ids(&serde_json::json!({})), None);
+    }
+}
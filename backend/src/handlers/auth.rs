@@ -1,4 +1,4 @@
-use axum::{extract::{State, Request as AxumRequest}, http::StatusCode, response::IntoResponse, Json};
+use axum::{async_trait, extract::{FromRequestParts, State, Request as AxumRequest}, http::{request::Parts, StatusCode}, response::IntoResponse, Json};
 use axum::middleware::Next;
 use bson::doc;
 use chrono::{Duration, Utc};
@@ -28,7 +28,7 @@ pub async fn login(State(state): State<AppState>, Json(body): Json<LoginRequest>
     Json(LoginResponse { token, username: user.username, role: user.role }).into_response()
 }
 
-pub async fn require_admin(State(state): State<AppState>, req: AxumRequest, next: Next) -> impl IntoResponse {
+pub async fn require_admin(State(state): State<AppState>, mut req: AxumRequest, next: Next) -> impl IntoResponse {
     let Some(auth) = req.headers().get(axum::http::header::AUTHORIZATION).and_then(|v| v.to_str().ok()) else {
         return StatusCode::UNAUTHORIZED.into_response();
     };
@@ -37,5 +37,22 @@ pub async fn require_admin(State(state): State<AppState>, req: AxumRequest, next
     let decoded = decode::<Claims>(token, &DecodingKey::from_secret(state.jwt_secret.as_bytes()), &validation);
     let Ok(data) = decoded else { return StatusCode::UNAUTHORIZED.into_response(); };
     if data.claims.role != "admin" { return StatusCode::FORBIDDEN.into_response(); }
+    req.extensions_mut().insert(Principal { username: data.claims.sub });
     next.run(req).await
 }
+
+// Authenticated admin from the JWT; require_admin puts it into the request extensions, so handlers
+// and middleware behind the guard can take it as an extractor
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub username: String,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Principal>().cloned().ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
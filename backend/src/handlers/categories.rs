@@ -1,23 +1,35 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::str::FromStr;
 
-use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
-use bson::{doc, oid::ObjectId, Bson};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId, Bson, Document};
 use futures::stream::StreamExt;
 use tracing::error;
 
-use crate::models::{Category, CategoryCreate, CategoryUpdate};
+use crate::handlers::auth::Principal;
+use crate::handlers::listing::{FilterKind, ListQuery, ListSpec};
+use crate::handlers::references::{delete_entity, DeleteQuery, Entity};
+use crate::handlers::trash::{self, live};
+use crate::models::{CategoryCreate, CategoryUpdate, Category};
 use crate::state::AppState;
 
-pub async fn list_categories(State(state): State<AppState>) -> impl IntoResponse {
-    let mut cursor = match state.categories.find(None, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query categories failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut items: Vec<Category> = Vec::new();
-    while let Some(res) = cursor.next().await { match res { Ok(doc)=> items.push(doc), Err(e)=> { error!(?e, "cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
-    (StatusCode::OK, Json(items)).into_response()
+const CATEGORIES_LIST: ListSpec = ListSpec {
+    filters: &[
+        ("name", "name", FilterKind::Contains),
+        ("parent_id", "parent_ids", FilterKind::ObjectId),
+    ],
+    sortable: &["name"],
+    default_sort: "name",
+    legacy_limit: None,
+};
+
+pub async fn list_categories(State(state): State<AppState>, q: ListQuery) -> impl IntoResponse {
+    match q.fetch(&state.categories, live(doc! {}), &CATEGORIES_LIST).await { Ok(page)=> page.into_response(), Err(e)=> e.into_response() }
 }
 
 pub async fn get_category(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
     let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    let filter = doc! {"_id": oid};
+    let filter = live(doc! {"_id": oid});
     match state.categories.find_one(filter, None).await {
         Ok(Some(doc)) => (StatusCode::OK, Json(doc)).into_response(),
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
@@ -25,15 +37,82 @@ pub async fn get_category(State(state): State<AppState>, Path(id): Path<String>)
     }
 }
 
+// Категории с несколькими родителями образуют DAG; граф целиком читается в память — их сотни, не больше
+pub struct CategoryGraph {
+    pub by_id: HashMap<ObjectId, Category>,
+    children: HashMap<ObjectId, Vec<ObjectId>>,
+}
+
+impl CategoryGraph {
+    pub async fn load(state: &AppState) -> mongodb::error::Result<Self> {
+        // категорий из корзины в графе нет; их подкатегории без других родителей становятся корнями
+        let mut cursor = state.categories.find(live(doc!{}), None).await?;
+        let mut all: Vec<Category> = Vec::new();
+        while let Some(c) = cursor.next().await { all.push(c?); }
+        Ok(Self::build(all))
+    }
+
+    fn build(categories: Vec<Category>) -> Self {
+        let by_id: HashMap<ObjectId, Category> = categories.into_iter().filter_map(|c| c.id.map(|id| (id, c))).collect();
+        let mut children: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+        for (id, c) in by_id.iter() {
+            for p in c.parent_ids.iter().filter(|p| by_id.contains_key(p)) { children.entry(*p).or_default().push(*id); }
+        }
+        for list in children.values_mut() { list.sort_by(|a, b| by_id[a].name.cmp(&by_id[b].name)); }
+        CategoryGraph { by_id, children }
+    }
+
+    // обход в ширину без самой категории; посещённые отмечаются, так что старые циклы в данных не страшны
+    fn walk(&self, id: ObjectId, next: impl Fn(&ObjectId) -> Vec<ObjectId>) -> Vec<ObjectId> {
+        let mut seen: HashSet<ObjectId> = HashSet::from([id]);
+        let mut out: Vec<ObjectId> = Vec::new();
+        let mut queue: VecDeque<ObjectId> = VecDeque::from([id]);
+        while let Some(cur) = queue.pop_front() {
+            for n in next(&cur) {
+                if seen.insert(n) { out.push(n); queue.push_back(n); }
+            }
+        }
+        out
+    }
+
+    pub fn descendants(&self, id: ObjectId) -> Vec<ObjectId> {
+        self.walk(id, |c| self.children.get(c).cloned().unwrap_or_default())
+    }
+
+    // ближайшие предки первыми
+    pub fn ancestors(&self, id: ObjectId) -> Vec<ObjectId> {
+        self.walk(id, |c| self.by_id.get(c).map(|cat| cat.parent_ids.iter().filter(|p| self.by_id.contains_key(p)).copied().collect()).unwrap_or_default())
+    }
+
+    // Родители из запроса: все id должны разбираться и существовать, и категория не может
+    // оказаться собственным предком. Err — код ошибки и id, на котором она найдена.
+    fn validate_parents(&self, category: Option<ObjectId>, raw: &[String]) -> Result<Vec<ObjectId>, (&'static str, String)> {
+        let below: HashSet<ObjectId> = category.map(|id| self.descendants(id).into_iter().chain([id]).collect()).unwrap_or_default();
+        let mut out: Vec<ObjectId> = Vec::new();
+        for s in raw {
+            let Ok(oid) = ObjectId::from_str(s.trim()) else { return Err(("invalid_parent_id", s.clone())) };
+            if !self.by_id.contains_key(&oid) { return Err(("parent_not_found", s.clone())); }
+            if below.contains(&oid) { return Err(("category_cycle", s.clone())); }
+            if !out.contains(&oid) { out.push(oid); }
+        }
+        Ok(out)
+    }
+}
+
+fn parents_error((code, id): (&'static str, String)) -> axum::response::Response {
+    let status = if code == "category_cycle" { StatusCode::CONFLICT } else { StatusCode::BAD_REQUEST };
+    (status, Json(serde_json::json!({"error": code, "parent_id": id}))).into_response()
+}
+
 pub async fn create_category(State(state): State<AppState>, Json(payload): Json<CategoryCreate>) -> impl IntoResponse {
-    let mut parents: Vec<ObjectId> = Vec::new();
-    for s in payload.parent_ids.iter() { if let Ok(oid) = ObjectId::from_str(s) { parents.push(oid); } }
-    let cat = Category { id: None, name: payload.name, desc: payload.desc, parent_ids: parents };
+    let graph = match CategoryGraph::load(&state).await { Ok(g)=>g, Err(e)=> { error!(?e, "load categories failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let parents = match graph.validate_parents(None, &payload.parent_ids) { Ok(p)=>p, Err(e)=> return parents_error(e) };
+    let cat = Category { id: None, name: payload.name, desc: payload.desc, parent_ids: parents, deleted_at: None, deleted_by: None };
     match state.categories.insert_one(cat, None).await {
         Ok(result) => {
             let id = match result.inserted_id { Bson::ObjectId(oid) => oid, _ => ObjectId::new() };
             let filter = doc! {"_id": id};
-            match state.categories.find_one(filter, None).await { Ok(Some(created)) => (StatusCode::CREATED, Json(created)).into_response(), _ => StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+            match state.categories.find_one(filter, None).await { Ok(Some(created)) => { state.suggest.upsert_category(&created); (StatusCode::CREATED, Json(created)).into_response() }, _ => StatusCode::INTERNAL_SERVER_ERROR.into_response() }
         }
         Err(e) => { error!(?e, "insert category failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
     }
@@ -44,24 +123,168 @@ pub async fn update_category(State(state): State<AppState>, Path(id): Path<Strin
     let mut set = doc! {};
     if let Some(n) = patch.name { set.insert("name", n); }
     if let Some(d) = patch.desc { set.insert("desc", d); }
-    if let Some(pids) = patch.parent_ids { let mut v: Vec<ObjectId> = Vec::new(); for s in pids.iter(){ if let Ok(oo)=ObjectId::from_str(s){ v.push(oo);} } set.insert("parent_ids", v);}    
+    if let Some(pids) = patch.parent_ids {
+        let graph = match CategoryGraph::load(&state).await { Ok(g)=>g, Err(e)=> { error!(?e, "load categories failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        match graph.validate_parents(Some(oid), &pids) { Ok(v)=> { set.insert("parent_ids", v); }, Err(e)=> return parents_error(e) }
+    }
     if set.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
-    let filter = doc! {"_id": oid};
+    let filter = live(doc! {"_id": oid});
     let update = doc! {"$set": set};
-    match state.categories.find_one_and_update(filter, update, None).await {
-        Ok(Some(updated)) => (StatusCode::OK, Json(updated)).into_response(),
+    let opts = mongodb::options::FindOneAndUpdateOptions::builder().return_document(mongodb::options::ReturnDocument::After).build();
+    match state.categories.find_one_and_update(filter, update, opts).await {
+        Ok(Some(updated)) => { state.suggest.upsert_category(&updated); (StatusCode::OK, Json(updated)).into_response() }
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
         Err(e) => { error!(?e, "update category failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
     }
 }
 
-pub async fn delete_category(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+// moves to the trash; with ?policy=restrict|cascade|reassign&reassign_to=<id> deletes right away, see handlers::references
+pub async fn delete_category(State(state): State<AppState>, Path(id): Path<String>, Query(q): Query<DeleteQuery>, principal: Principal) -> impl IntoResponse {
+    delete_entity(&state, Entity::Category, &id, &q, &principal.username).await
+}
+
+pub async fn restore_category(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    trash::restore(&state, Entity::Category, &id).await
+}
+
+
+// Счётчики товаров: напрямую в категории и во всём поддереве (товар считается один раз,
+// даже если лежит в нескольких категориях поддерева)
+async fn product_counts(state: &AppState, graph: &CategoryGraph) -> mongodb::error::Result<(HashMap<ObjectId, u64>, HashMap<ObjectId, u64>)> {
+    let mut direct: HashMap<ObjectId, u64> = HashMap::new();
+    let mut subtree: HashMap<ObjectId, u64> = HashMap::new();
+    let mut closure: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+    let opts = mongodb::options::FindOptions::builder().projection(doc!{"category_ids": 1}).build();
+    let mut cursor = state.db.collection::<Document>("products").find(live(doc!{"category_ids.0": {"$exists": true}}), opts).await?;
+    while let Some(d) = cursor.next().await {
+        let d = d?;
+        let cats: HashSet<ObjectId> = d.get_array("category_ids").map(|a| a.iter().filter_map(|v| v.as_object_id()).collect()).unwrap_or_default();
+        let mut up: HashSet<ObjectId> = HashSet::new();
+        for c in cats.iter().filter(|c| graph.by_id.contains_key(c)) {
+            *direct.entry(*c).or_insert(0) += 1;
+            let anc = closure.entry(*c).or_insert_with(|| graph.ancestors(*c));
+            up.insert(*c);
+            up.extend(anc.iter().copied());
+        }
+        for c in up { *subtree.entry(c).or_insert(0) += 1; }
+    }
+    Ok((direct, subtree))
+}
+
+fn tree_node(graph: &CategoryGraph, id: ObjectId, path: &mut Vec<ObjectId>, counts: &(HashMap<ObjectId, u64>, HashMap<ObjectId, u64>)) -> serde_json::Value {
+    path.push(id);
+    let mut children: Vec<serde_json::Value> = Vec::new();
+    for c in graph.children.get(&id).into_iter().flatten() {
+        if !path.contains(c) { children.push(tree_node(graph, *c, path, counts)); }
+    }
+    path.pop();
+    let c = &graph.by_id[&id];
+    serde_json::json!({
+        "_id": id,
+        "name": c.name,
+        "desc": c.desc,
+        "parent_ids": c.parent_ids,
+        "product_count": counts.0.get(&id).copied().unwrap_or(0),
+        "subtree_product_count": counts.1.get(&id).copied().unwrap_or(0),
+        "children": children,
+    })
+}
+
+// GET /categories/tree — корни и вложенные children; категория с несколькими родителями
+// встречается под каждым из них
+pub async fn get_category_tree(State(state): State<AppState>) -> impl IntoResponse {
+    let graph = match CategoryGraph::load(&state).await { Ok(g)=>g, Err(e)=> { error!(?e, "load categories failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let counts = match product_counts(&state, &graph).await { Ok(c)=>c, Err(e)=> { error!(?e, "count category products failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let mut roots: Vec<&Category> = graph.by_id.values().filter(|c| !c.parent_ids.iter().any(|p| graph.by_id.contains_key(p))).collect();
+    roots.sort_by(|a, b| a.name.cmp(&b.name));
+    let tree: Vec<serde_json::Value> = roots.iter().filter_map(|c| c.id).map(|id| tree_node(&graph, id, &mut Vec::new(), &counts)).collect();
+    (StatusCode::OK, Json(tree)).into_response()
+}
+
+async fn related(state: AppState, id: String, up: bool) -> axum::response::Response {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let graph = match CategoryGraph::load(&state).await { Ok(g)=>g, Err(e)=> { error!(?e, "load categories failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    if !graph.by_id.contains_key(&oid) { return StatusCode::NOT_FOUND.into_response(); }
+    let counts = match product_counts(&state, &graph).await { Ok(c)=>c, Err(e)=> { error!(?e, "count category products failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let ids = if up { graph.ancestors(oid) } else { graph.descendants(oid) };
+    let items: Vec<serde_json::Value> = ids.iter().map(|i| {
+        let mut v = serde_json::to_value(&graph.by_id[i]).unwrap_or_default();
+        v["product_count"] = counts.0.get(i).copied().unwrap_or(0).into();
+        v["subtree_product_count"] = counts.1.get(i).copied().unwrap_or(0).into();
+        v
+    }).collect();
+    (StatusCode::OK, Json(items)).into_response()
+}
+
+// GET /categories/:id/ancestors — от ближайшего родителя к корням
+pub async fn list_category_ancestors(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    related(state, id, true).await
+}
+
+// GET /categories/:id/descendants — в ширину, без повторов
+pub async fn list_category_descendants(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    related(state, id, false).await
+}
+
+#[derive(serde::Deserialize)]
+pub struct CategoryProductsQuery {
+    #[serde(default)]
+    pub include_descendants: bool,
+}
+
+// GET /categories/:id/products?include_descendants=true — список товаров с общими параметрами списков
+pub async fn list_category_products(State(state): State<AppState>, Path(id): Path<String>, Query(cq): Query<CategoryProductsQuery>, q: ListQuery) -> impl IntoResponse {
     let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    let filter = doc! {"_id": oid};
-    match state.categories.delete_one(filter, None).await {
-        Ok(res) if res.deleted_count == 1 => StatusCode::NO_CONTENT.into_response(),
-        Ok(_) => StatusCode::NOT_FOUND.into_response(),
-        Err(e) => { error!(?e, "delete category failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    let mut ids = vec![oid];
+    if cq.include_descendants {
+        match CategoryGraph::load(&state).await { Ok(g)=> ids.extend(g.descendants(oid)), Err(e)=> { error!(?e, "load categories failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } }
     }
+    match q.fetch(&state.products, live(doc!{"category_ids": {"$in": ids}}), &crate::handlers::products::PRODUCTS_LIST).await { Ok(page)=> page.into_response(), Err(e)=> e.into_response() }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(name: &str, parent_ids: Vec<ObjectId>) -> Category {
+        Category { id: Some(ObjectId::new()), name: name.into(), desc: String::new(), parent_ids, deleted_at: None, deleted_by: None }
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let a = category("A", vec![]);
+        let (a_id, b) = (a.id.unwrap(), category("B", vec![a.id.unwrap()]));
+        let b_id = b.id.unwrap();
+        let c = category("C", vec![b_id]);
+        let c_id = c.id.unwrap();
+        let graph = CategoryGraph::build(vec![a, b, c]);
+
+        // A -> B -> A и через внука
+        assert_eq!(graph.validate_parents(Some(a_id), &[b_id.to_hex()]), Err(("category_cycle", b_id.to_hex())));
+        assert_eq!(graph.validate_parents(Some(a_id), &[c_id.to_hex()]), Err(("category_cycle", c_id.to_hex())));
+        // сама себе родитель
+        assert_eq!(graph.validate_parents(Some(a_id), &[a_id.to_hex()]), Err(("category_cycle", a_id.to_hex())));
+        // перенос вниз по другой ветке и новая категория — можно
+        assert_eq!(graph.validate_parents(Some(c_id), &[a_id.to_hex(), a_id.to_hex()]), Ok(vec![a_id]));
+        assert_eq!(graph.validate_parents(None, &[c_id.to_hex()]), Ok(vec![c_id]));
+    }
+
+    #[test]
+    fn rejects_unknown_parents() {
+        let graph = CategoryGraph::build(vec![category("A", vec![])]);
+        let missing = ObjectId::new().to_hex();
+        assert_eq!(graph.validate_parents(None, std::slice::from_ref(&missing)), Err(("parent_not_found", missing)));
+        assert_eq!(graph.validate_parents(None, &["x".into()]), Err(("invalid_parent_id", "x".into())));
+    }
+
+    #[test]
+    fn walks_ancestors_and_descendants() {
+        let a = category("A", vec![]);
+        let b = category("B", vec![a.id.unwrap()]);
+        let c = category("C", vec![b.id.unwrap(), a.id.unwrap()]);
+        let (a_id, b_id, c_id) = (a.id.unwrap(), b.id.unwrap(), c.id.unwrap());
+        let graph = CategoryGraph::build(vec![a, b, c]);
+        assert_eq!(graph.descendants(a_id), vec![b_id, c_id]);
+        assert_eq!(graph.ancestors(c_id), vec![b_id, a_id]);
+    }
+}
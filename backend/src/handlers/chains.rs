@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId, Bson};
+use futures::stream::StreamExt;
+use tracing::error;
+
+use crate::handlers::trash::{live, Hidden};
+use crate::money::Money;
+use crate::models::{Store, StoreChain, StoreChainCreate, StoreChainUpdate};
+use crate::state::AppState;
+
+pub async fn list_chains(State(state): State<AppState>) -> impl IntoResponse {
+    let mut cursor = match state.store_chains.find(None, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query chains failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let mut items: Vec<StoreChain> = Vec::new();
+    while let Some(res) = cursor.next().await { match res { Ok(doc)=> items.push(doc), Err(e)=> { error!(?e, "cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    (StatusCode::OK, Json(items)).into_response()
+}
+
+pub async fn get_chain(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    match state.store_chains.find_one(doc!{"_id": oid}, None).await {
+        Ok(Some(doc)) => (StatusCode::OK, Json(doc)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "find chain failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub async fn create_chain(State(state): State<AppState>, Json(payload): Json<StoreChainCreate>) -> impl IntoResponse {
+    if payload.name.trim().is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
+    let chain = StoreChain { id: None, name: payload.name, desc: payload.desc, image_url: payload.image_url };
+    match state.store_chains.insert_one(chain, None).await {
+        Ok(result) => {
+            let id = match result.inserted_id { Bson::ObjectId(oid) => oid, _ => ObjectId::new() };
+            match state.store_chains.find_one(doc!{"_id": id}, None).await { Ok(Some(created)) => (StatusCode::CREATED, Json(created)).into_response(), _ => StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+        }
+        Err(e) => { error!(?e, "insert chain failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub async fn update_chain(State(state): State<AppState>, Path(id): Path<String>, Json(patch): Json<StoreChainUpdate>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let mut set = doc!{};
+    if let Some(v) = patch.name { set.insert("name", v); }
+    if let Some(v) = patch.desc { set.insert("desc", v); }
+    if let Some(v) = patch.image_url { set.insert("image_url", v); }
+    if set.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
+    let opts = mongodb::options::FindOneAndUpdateOptions::builder().return_document(mongodb::options::ReturnDocument::After).build();
+    match state.store_chains.find_one_and_update(doc!{"_id": oid}, doc!{"$set": set}, opts).await {
+        Ok(Some(updated)) => (StatusCode::OK, Json(updated)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "update chain failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub async fn delete_chain(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    match state.store_chains.delete_one(doc!{"_id": oid}, None).await {
+        Ok(res) if res.deleted_count == 1 => {
+            // stores stay, they just no longer belong to a chain
+            if let Err(e) = state.stores.update_many(doc!{"chain_id": oid}, doc!{"$unset": {"chain_id": ""}}, None).await { error!(?e, "detach stores from chain failed"); }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "delete chain failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+async fn chain_stores(state: &AppState, chain_oid: ObjectId) -> mongodb::error::Result<Vec<Store>> {
+    let mut cursor = state.stores.find(live(doc!{"chain_id": chain_oid}), None).await?;
+    let mut out = Vec::new();
+    while let Some(res) = cursor.next().await { out.push(res?); }
+    Ok(out)
+}
+
+// product -> [(store, price)] over the branches of a chain
+async fn chain_prices(state: &AppState, store_ids: &[ObjectId], product_ids: Option<&[ObjectId]>) -> mongodb::error::Result<HashMap<ObjectId, Vec<(ObjectId, Money)>>> {
+    let mut filter = doc!{"store_id": {"$in": store_ids}};
+    if let Some(pids) = product_ids { filter.insert("product_id", doc!{"$in": pids}); }
+    let hidden = Hidden::load(state).await?;
+    let mut cursor = state.store_items.find(hidden.items(filter), None).await?;
+    let mut out: HashMap<ObjectId, Vec<(ObjectId, Money)>> = HashMap::new();
+    while let Some(res) = cursor.next().await {
+        let it = res?;
+        out.entry(it.product_id).or_default().push((it.store_id, it.price));
+    }
+    Ok(out)
+}
+
+async fn product_titles(state: &AppState, ids: &[ObjectId]) -> mongodb::error::Result<HashMap<ObjectId, String>> {
+    let mut out = HashMap::new();
+    if ids.is_empty() { return Ok(out); }
+    let mut cursor = state.products.find(doc!{"_id": {"$in": ids}}, None).await?;
+    while let Some(res) = cursor.next().await { let p = res?; if let Some(id) = p.id { out.insert(id, p.title); } }
+    Ok(out)
+}
+
+pub async fn list_chain_stores(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    match chain_stores(&state, oid).await {
+        Ok(items) => (StatusCode::OK, Json(items)).into_response(),
+        Err(e) => { error!(?e, "query chain stores failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+// Average chain price per product across its branches
+pub async fn list_chain_prices(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let stores = match chain_stores(&state, oid).await { Ok(s)=>s, Err(e)=> { error!(?e, "query chain stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let store_ids: Vec<ObjectId> = stores.iter().filter_map(|s| s.id).collect();
+    if store_ids.is_empty() { return (StatusCode::OK, Json(Vec::<serde_json::Value>::new())).into_response(); }
+    let prices = match chain_prices(&state, &store_ids, None).await { Ok(p)=>p, Err(e)=> { error!(?e, "query chain prices failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let pids: Vec<ObjectId> = prices.keys().cloned().collect();
+    let titles = match product_titles(&state, &pids).await { Ok(t)=>t, Err(e)=> { error!(?e, "query products failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let mut out: Vec<serde_json::Value> = prices.iter().map(|(pid, list)| {
+        let n = list.len();
+        let avg = list.iter().map(|(_, p)| p).sum::<Money>().div_count(n as u64);
+        let min = list.iter().map(|(_, p)| *p).min();
+        let max = list.iter().map(|(_, p)| *p).max();
+        serde_json::json!({"product_id": pid, "product_title": titles.get(pid), "avg": avg, "min": min, "max": max, "stores": n})
+    }).collect();
+    out.sort_by(|a, b| a["product_title"].as_str().unwrap_or("").cmp(b["product_title"].as_str().unwrap_or("")));
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+#[derive(serde::Deserialize)]
+pub struct UniformityQuery {
+    // relative spread (max/min - 1) still considered "the same price"
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+}
+
+fn default_tolerance() -> f64 { 0.01 }
+
+// Are prices the same in all branches? Per product spread plus overall share of uniform products.
+pub async fn get_chain_uniformity(State(state): State<AppState>, Path(id): Path<String>, Query(q): Query<UniformityQuery>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    if !(q.tolerance >= 0.0 && q.tolerance.is_finite()) { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_tolerance"}))).into_response(); }
+    let stores = match chain_stores(&state, oid).await { Ok(s)=>s, Err(e)=> { error!(?e, "query chain stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let store_ids: Vec<ObjectId> = stores.iter().filter_map(|s| s.id).collect();
+    let prices = if store_ids.is_empty() { HashMap::new() } else {
+        match chain_prices(&state, &store_ids, None).await { Ok(p)=>p, Err(e)=> { error!(?e, "query chain prices failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } }
+    };
+    let pids: Vec<ObjectId> = prices.keys().cloned().collect();
+    let titles = match product_titles(&state, &pids).await { Ok(t)=>t, Err(e)=> { error!(?e, "query products failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let mut products_out: Vec<serde_json::Value> = Vec::new();
+    let (mut compared, mut uniform) = (0usize, 0usize);
+    for (pid, list) in prices.iter() {
+        // a product sold in a single branch says nothing about uniformity
+        if list.len() < 2 { continue; }
+        let min = list.iter().map(|(_, p)| *p).min().unwrap_or(Money::ZERO);
+        let max = list.iter().map(|(_, p)| *p).max().unwrap_or(Money::ZERO);
+        let spread = max.ratio(min).filter(|_| min > Money::ZERO).map(|r| r - 1.0).unwrap_or(0.0);
+        let is_uniform = spread <= q.tolerance;
+        compared += 1;
+        if is_uniform { uniform += 1; }
+        products_out.push(serde_json::json!({
+            "product_id": pid,
+            "product_title": titles.get(pid),
+            "stores": list.len(),
+            "min": min,
+            "max": max,
+            "spread": spread,
+            "uniform": is_uniform,
+        }));
+    }
+    products_out.sort_by(|a, b| b["spread"].as_f64().unwrap_or(0.0).total_cmp(&a["spread"].as_f64().unwrap_or(0.0)));
+    let out = serde_json::json!({
+        "chain_id": oid,
+        "stores": store_ids.len(),
+        "products_compared": compared,
+        "uniform_share": if compared > 0 { Some(uniform as f64 / compared as f64) } else { None },
+        "products": products_out,
+    });
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+#[derive(serde::Deserialize)]
+pub struct RankQuery {
+    // comma-separated product ids forming the basket
+    pub product_ids: String,
+}
+
+// Chains ranked by price level for a basket: each chain's average price per product is compared
+// with the median across chains; chains carrying only part of the basket are ranked on what they have.
+pub async fn rank_chains(State(state): State<AppState>, Query(q): Query<RankQuery>) -> impl IntoResponse {
+    let mut basket: Vec<ObjectId> = Vec::new();
+    for s in q.product_ids.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        match ObjectId::from_str(s) { Ok(oid) => basket.push(oid), Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_product_id"}))).into_response() }
+    }
+    if basket.is_empty() { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "empty_basket"}))).into_response(); }
+
+    let mut chains: HashMap<ObjectId, String> = HashMap::new();
+    let mut cc = match state.store_chains.find(None, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query chains failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = cc.next().await { match res { Ok(c)=> { if let Some(id) = c.id { chains.insert(id, c.name); } }, Err(e)=> { error!(?e, "chains cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    let mut store_chain: HashMap<ObjectId, ObjectId> = HashMap::new();
+    let mut sc = match state.stores.find(live(doc!{"chain_id": {"$in": chains.keys().collect::<Vec<_>>()}}), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query chain stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = sc.next().await { match res { Ok(s)=> { if let (Some(sid), Some(cid)) = (s.id, s.chain_id) { store_chain.insert(sid, cid); } }, Err(e)=> { error!(?e, "stores cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    let store_ids: Vec<ObjectId> = store_chain.keys().cloned().collect();
+    if store_ids.is_empty() { return (StatusCode::OK, Json(Vec::<serde_json::Value>::new())).into_response(); }
+    let prices = match chain_prices(&state, &store_ids, Some(&basket)).await { Ok(p)=>p, Err(e)=> { error!(?e, "query basket prices failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+
+    // chain -> product -> average price over branches
+    let mut chain_avg: HashMap<ObjectId, HashMap<ObjectId, Money>> = HashMap::new();
+    let mut product_median: HashMap<ObjectId, Money> = HashMap::new();
+    for (pid, list) in prices.iter() {
+        let mut by_chain: HashMap<ObjectId, Vec<Money>> = HashMap::new();
+        for (sid, price) in list {
+            let Some(cid) = store_chain.get(sid) else { continue };
+            by_chain.entry(*cid).or_default().push(*price);
+        }
+        let mut avgs: Vec<Money> = Vec::new();
+        for (cid, list) in by_chain {
+            let Some(avg) = Money::avg(&list) else { continue };
+            avgs.push(avg);
+            chain_avg.entry(cid).or_default().insert(*pid, avg);
+        }
+        if let Some(m) = Money::median(&mut avgs) { product_median.insert(*pid, m); }
+    }
+
+    let chain_ids: HashSet<ObjectId> = chain_avg.keys().cloned().collect();
+    let mut out: Vec<serde_json::Value> = chain_ids.into_iter().map(|cid| {
+        let per_product = &chain_avg[&cid];
+        let devs: Vec<f64> = per_product.iter().filter_map(|(pid, avg)| product_median.get(pid).and_then(|m| avg.ratio(*m)).map(|r| r - 1.0)).collect();
+        let level = if devs.is_empty() { None } else { Some(devs.iter().sum::<f64>() / devs.len() as f64) };
+        serde_json::json!({
+            "chain_id": cid,
+            "chain_name": chains.get(&cid),
+            "basket_total": per_product.values().sum::<Money>(),
+            "coverage": per_product.len() as f64 / basket.len() as f64,
+            "products": per_product.len(),
+            "price_level": level,
+        })
+    }).collect();
+    out.sort_by(|a, b| a["price_level"].as_f64().unwrap_or(f64::MAX).total_cmp(&b["price_level"].as_f64().unwrap_or(f64::MAX)));
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+#[derive(serde::Deserialize)]
+pub struct AssignChainBody {
+    // null/empty — detach the stores from their chain
+    #[serde(default)]
+    pub chain_id: Option<String>,
+    #[serde(default)]
+    pub store_ids: Vec<String>,
+    // alternatively: every store whose name or brand contains this text (case-insensitive)
+    #[serde(default)]
+    pub name_contains: Option<String>,
+}
+
+// Bulk chain assignment for stores
+pub async fn assign_chain(State(state): State<AppState>, Json(body): Json<AssignChainBody>) -> impl IntoResponse {
+    let chain_oid = match body.chain_id.as_deref().filter(|s| !s.is_empty()) {
+        None => None,
+        Some(s) => match ObjectId::from_str(s) { Ok(oid) => Some(oid), Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_chain_id"}))).into_response() },
+    };
+    if let Some(cid) = chain_oid {
+        match state.store_chains.find_one(doc!{"_id": cid}, None).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => { error!(?e, "find chain failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+        }
+    }
+    let mut ors: Vec<bson::Document> = Vec::new();
+    if !body.store_ids.is_empty() {
+        let mut ids: Vec<ObjectId> = Vec::new();
+        for s in body.store_ids.iter() {
+            match ObjectId::from_str(s) { Ok(oid) => ids.push(oid), Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_store_id"}))).into_response() }
+        }
+        ors.push(doc!{"_id": {"$in": ids}});
+    }
+    if let Some(text) = body.name_contains.as_deref().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let re = bson::Regex { pattern: regex_escape(text), options: "i".into() };
+        ors.push(doc!{"name": re.clone()});
+        ors.push(doc!{"brand": re});
+    }
+    if ors.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
+    let update = match chain_oid { Some(cid) => doc!{"$set": {"chain_id": cid}}, None => doc!{"$unset": {"chain_id": ""}} };
+    match state.stores.update_many(doc!{"$or": ors}, update, None).await {
+        Ok(r) => (StatusCode::OK, Json(serde_json::json!({"matched": r.matched_count, "modified": r.modified_count}))).into_response(),
+        Err(e) => { error!(?e, "assign chain failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub fn regex_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) { out.push('\\'); }
+        out.push(c);
+    }
+    out
+}
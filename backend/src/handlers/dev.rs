@@ -1,13 +1,16 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse};
-use bson::{doc, oid::ObjectId};
+use bson::{doc, oid::ObjectId, Bson};
 use rand::Rng;
 use tracing::{error, info};
 
 use crate::{
     models::{Category, Product, Store},
+    money::{Money, Quantity},
     state::AppState,
 };
 
+const DAY_MS: i64 = 24 * 3600 * 1000;
+
 pub async fn clear_test_data(State(state): State<AppState>) -> impl IntoResponse {
     // Danger: admin-guarded in routes.rs
     let db = &state.db;
@@ -32,6 +35,8 @@ pub async fn clear_test_data(State(state): State<AppState>) -> impl IntoResponse
     wipe_coll!("store_items");
     wipe_coll!("store_activities");
     // Do NOT wipe users/settings/receipts/events by default
+    if let Err(e) = crate::search::rebuild_all(&state).await { error!(?e, "search index rebuild failed"); ok = false; }
+    crate::handlers::price_stats::prices_changed(&state).await;
     info!("Finished clearing test data");
 
     if ok {
@@ -55,6 +60,8 @@ pub async fn seed(State(state): State<AppState>) -> impl IntoResponse {
                 name: format!("Тест-категория {}", i),
                 desc: "".to_string(),
                 parent_ids: vec![],
+                deleted_at: None,
+                deleted_by: None,
             })
             .unwrap();
             doc.insert("is_test", true);
@@ -76,6 +83,13 @@ pub async fn seed(State(state): State<AppState>) -> impl IntoResponse {
                 addr: format!("Тестовый город, ул. Тестовая {}", i),
                 desc: "".to_string(),
                 image_url: None,
+                location: None,
+                brand: None,
+                osm_id: None,
+                chain_id: None,
+                region_id: None,
+                deleted_at: None,
+                deleted_by: None,
             })
             .unwrap();
             doc.insert("is_test", true);
@@ -104,9 +118,25 @@ pub async fn seed(State(state): State<AppState>) -> impl IntoResponse {
                 desc: "Описание тестового товара".to_string(),
                 image_url: None,
                 category_ids: cat_id,
+                unit: None,
+                pack_size: None,
+                barcodes: vec![],
+                brand: None,
+                category_hints: vec![],
+                imported_fields: vec![],
+                aliases: vec![],
+                deleted_at: None,
+                deleted_by: None,
             })
             .unwrap();
             doc.insert("is_test", true);
+            // половина товаров — с единицей измерения: фасованные (кг, л) и штучные, для цены за единицу
+            match rand::thread_rng().gen_range(0..6) {
+                0 => { doc.insert("unit", "kg"); doc.insert("pack_size", Bson::from(Quantity::from_minor(rand::thread_rng().gen_range(2..=10) * 100))); }
+                1 => { doc.insert("unit", "l"); doc.insert("pack_size", Bson::from(Quantity::from_minor(rand::thread_rng().gen_range(1..=4) * 500))); }
+                2 => { doc.insert("unit", "pcs"); }
+                _ => {}
+            }
             if let Ok(res) = prod_coll.insert_one(doc, None).await {
                 if let Some(id) = res.inserted_id.as_object_id() {
                     product_ids.push(id.clone());
@@ -114,20 +144,22 @@ pub async fn seed(State(state): State<AppState>) -> impl IntoResponse {
             }
         }
         info!("Created {} test products", product_ids.len());
+        if let Err(e) = crate::search::rebuild_all(&state).await { error!(?e, "search index rebuild failed"); }
 
         // Create store items
         let store_item_coll = db.collection::<bson::Document>("store_items");
+        let activity_coll = db.collection::<bson::Document>("store_activities");
         let mut count = 0;
+        let mut activities = 0;
+        let now = chrono::Utc::now().timestamp_millis();
         if !store_ids.is_empty() && !product_ids.is_empty() {
             for store_id in &store_ids {
                 // Not all products in all stores
                 let products_in_store: Vec<&ObjectId> = product_ids.iter().filter(|_| rand::thread_rng().gen_bool(0.7)).collect();
+                let mut history = vec![];
                 for product_id in products_in_store {
-                    let price = {
-                        let mut rng = rand::thread_rng();
-                        let price_float: f64 = rng.gen_range(50.0..1000.0);
-                        price_float.trunc() / 100.0 * 100.0 - 0.1 // xx.9
-                    };
+                    // целые рубли минус 10 копеек: xx.90
+                    let price = Money::from_minor(rand::thread_rng().gen_range(50..1000) * 100 - 10);
                     let doc = doc! {
                         "store_id": store_id,
                         "product_id": product_id,
@@ -137,10 +169,21 @@ pub async fn seed(State(state): State<AppState>) -> impl IntoResponse {
                     if store_item_coll.insert_one(doc, None).await.is_ok() {
                         count += 1;
                     }
+                    // история: товар появился 30–60 дней назад по старой цене, текущая — за последние 30 дней
+                    let added = now - rand::thread_rng().gen_range(30..60) * DAY_MS;
+                    let old_price = Money::from_minor(price.minor() + rand::thread_rng().gen_range(-20..=20) * 100);
+                    history.push(doc! {"store_id": store_id, "product_id": product_id, "kind": "item_added", "ts_ms": added, "price": old_price, "is_test": true});
+                    history.push(doc! {"store_id": store_id, "product_id": product_id, "kind": "price_updated", "ts_ms": now - rand::thread_rng().gen_range(0..30 * DAY_MS), "price": price, "is_test": true});
+                }
+                match activity_coll.insert_many(history, None).await {
+                    Ok(res) => activities += res.inserted_ids.len(),
+                    Err(e) => error!(?e, "insert test activities failed"),
                 }
             }
         }
-        info!("Created {} test store items", count);
+        info!("Created {} test store items, {} activities", count, activities);
+        crate::handlers::price_stats::prices_changed(&state).await;
+        state.cache.clear();
         info!("Finished seeding test data");
     });
 
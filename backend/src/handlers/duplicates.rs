@@ -0,0 +1,399 @@
+use std::str::FromStr;
+
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId, Bson};
+use futures::stream::StreamExt;
+use tracing::{error, info};
+
+use crate::handlers::trash::live;
+use crate::models::{Product, ProductRedirect, StoreItem};
+use crate::state::AppState;
+
+fn redirects(state: &AppState) -> mongodb::Collection<ProductRedirect> { state.db.collection::<ProductRedirect>("product_redirects") }
+
+// Product an old (merged) id points to
+pub async fn resolve_redirect(state: &AppState, id: ObjectId) -> mongodb::error::Result<Option<Product>> {
+    let Some(r) = redirects(state).find_one(doc!{"_id": id}, None).await? else { return Ok(None) };
+    state.products.find_one(live(doc!{"_id": r.to}), None).await
+}
+
+#[derive(serde::Deserialize)]
+pub struct MergeBody {
+    // products to fold into the one in the path
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Default, serde::Serialize)]
+pub struct MergeStats {
+    pub store_items_moved: u64,
+    pub store_items_conflicts: u64,
+    pub activities: u64,
+    pub operations: u64,
+}
+
+// ts of the latest price activity for a (store, product) pair
+async fn last_price_ts(state: &AppState, store_id: ObjectId, product_id: ObjectId) -> mongodb::error::Result<Option<i64>> {
+    let opts = mongodb::options::FindOneOptions::builder().sort(doc!{"ts_ms": -1}).build();
+    Ok(state.store_activities.find_one(doc!{"store_id": store_id, "product_id": product_id, "price": {"$exists": true}}, opts).await?.map(|a| a.ts_ms))
+}
+
+// Rewrites every reference from `dup` to `keep`. Store items present in both are resolved by the
+// most recent price (by store_activities); the duplicate's item is removed.
+async fn rewrite_references(state: &AppState, keep: &Product, dup: &Product) -> mongodb::error::Result<MergeStats> {
+    let (Some(keep_id), Some(dup_id)) = (keep.id, dup.id) else { return Ok(MergeStats::default()) };
+    let mut stats = MergeStats::default();
+
+    let mut dup_items: Vec<StoreItem> = Vec::new();
+    let mut cursor = state.store_items.find(doc!{"product_id": dup_id}, None).await?;
+    while let Some(it) = cursor.next().await { dup_items.push(it?); }
+    for it in dup_items {
+        match state.store_items.find_one(doc!{"store_id": it.store_id, "product_id": keep_id}, None).await? {
+            Some(existing) => {
+                stats.store_items_conflicts += 1;
+                let dup_ts = last_price_ts(state, it.store_id, dup_id).await?;
+                let keep_ts = last_price_ts(state, it.store_id, keep_id).await?;
+                if dup_ts > keep_ts && existing.price != it.price {
+                    state.store_items.update_one(doc!{"_id": existing.id}, doc!{"$set": {"price": it.price}}, None).await?;
+                }
+                state.store_items.delete_one(doc!{"_id": it.id}, None).await?;
+            }
+            None => {
+                state.store_items.update_one(doc!{"_id": it.id}, doc!{"$set": {"product_id": keep_id}}, None).await?;
+                stats.store_items_moved += 1;
+            }
+        }
+    }
+
+    let res = state.store_activities.update_many(doc!{"product_id": dup_id}, doc!{"$set": {"product_id": keep_id, "product_name": &keep.title}}, None).await?;
+    stats.activities = res.modified_count;
+
+    // operation items may hold the id as ObjectId or, in older documents, as a hex string
+    let ops = state.db.collection::<bson::Document>("operations");
+    for old in [Bson::ObjectId(dup_id), Bson::String(dup_id.to_hex())] {
+        let opts = mongodb::options::UpdateOptions::builder().array_filters(vec![doc!{"it.product_id": old.clone()}]).build();
+        let res = ops.update_many(doc!{"items.product_id": old}, doc!{"$set": {"items.$[it].product_id": keep_id}}, opts).await?;
+        stats.operations += res.modified_count;
+    }
+    Ok(stats)
+}
+
+// Fields of the kept product after absorbing `dup`: unions for lists, empty scalars filled in
+fn merged_fields(keep: &Product, dup: &Product) -> bson::Document {
+    let mut set = doc!{};
+    let mut aliases = keep.aliases.clone();
+    for a in std::iter::once(&dup.title).chain(dup.aliases.iter()) {
+        if a != &keep.title && !aliases.contains(a) { aliases.push(a.clone()); }
+    }
+    if aliases != keep.aliases { set.insert("aliases", aliases); }
+    let mut barcodes = keep.barcodes.clone();
+    for b in dup.barcodes.iter() { if !barcodes.contains(b) { barcodes.push(b.clone()); } }
+    if barcodes != keep.barcodes { set.insert("barcodes", barcodes); }
+    let mut cats = keep.category_ids.clone();
+    for c in dup.category_ids.iter() { if !cats.contains(c) { cats.push(*c); } }
+    if cats != keep.category_ids { set.insert("category_ids", cats); }
+    if keep.desc.trim().is_empty() && !dup.desc.trim().is_empty() { set.insert("desc", &dup.desc); }
+    if keep.image_url.as_deref().unwrap_or("").is_empty() { if let Some(img) = dup.image_url.as_ref().filter(|s| !s.is_empty()) { set.insert("image_url", img); } }
+    if keep.brand.is_none() { if let Some(b) = &dup.brand { set.insert("brand", b); } }
+    if keep.unit.is_none() { if let Some(u) = dup.unit { set.insert("unit", bson::to_bson(&u).unwrap_or(Bson::Null)); } }
+    if keep.pack_size.is_none() { if let Some(s) = dup.pack_size { set.insert("pack_size", s); } }
+    set
+}
+
+// Folds duplicates into the product in the path. Old ids keep resolving through product_redirects.
+pub async fn merge_products(State(state): State<AppState>, Path(id): Path<String>, Json(body): Json<MergeBody>) -> impl IntoResponse {
+    let Ok(keep_id) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let mut dup_ids: Vec<ObjectId> = Vec::new();
+    for s in body.product_ids.iter() {
+        match ObjectId::from_str(s) {
+            Ok(oid) if oid == keep_id => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "merge_into_self"}))).into_response(),
+            Ok(oid) => { if !dup_ids.contains(&oid) { dup_ids.push(oid); } }
+            Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_product_id"}))).into_response(),
+        }
+    }
+    if dup_ids.is_empty() { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "empty_product_ids"}))).into_response(); }
+    let mut keep = match state.products.find_one(live(doc!{"_id": keep_id}), None).await { Ok(Some(p))=>p, Ok(None)=> return StatusCode::NOT_FOUND.into_response(), Err(e)=> { error!(?e, "find product failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let mut dups: Vec<Product> = Vec::new();
+    for did in dup_ids.iter() {
+        match state.products.find_one(doc!{"_id": did}, None).await {
+            Ok(Some(p)) => dups.push(p),
+            Ok(None) => return (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "product_not_found", "product_id": did}))).into_response(),
+            Err(e) => { error!(?e, "find product failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+        }
+    }
+
+    let mut total = MergeStats::default();
+    for dup in dups.iter() {
+        let Some(dup_id) = dup.id else { continue };
+        let stats = match rewrite_references(&state, &keep, dup).await { Ok(s)=>s, Err(e)=> { error!(?e, "rewrite product references failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        total.store_items_moved += stats.store_items_moved;
+        total.store_items_conflicts += stats.store_items_conflicts;
+        total.activities += stats.activities;
+        total.operations += stats.operations;
+        // the duplicate goes first: its barcodes are unique and move to the kept product below
+        if let Err(e) = state.products.delete_one(doc!{"_id": dup_id}, None).await { error!(?e, "delete merged product failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+        state.search.remove(&dup_id);
+        state.suggest.remove(crate::search::SuggestKind::Product, dup_id);
+        let now = chrono::Utc::now().timestamp_millis();
+        let upsert = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+        if let Err(e) = redirects(&state).replace_one(doc!{"_id": dup_id}, ProductRedirect { id: dup_id, to: keep_id, ts_ms: now }, upsert).await { error!(?e, "insert product redirect failed"); }
+        // ids merged into the duplicate earlier now point straight to the kept product
+        if let Err(e) = redirects(&state).update_many(doc!{"to": dup_id}, doc!{"$set": {"to": keep_id}}, None).await { error!(?e, "update product redirects failed"); }
+        let set = merged_fields(&keep, dup);
+        if !set.is_empty() {
+            let opts = mongodb::options::FindOneAndUpdateOptions::builder().return_document(mongodb::options::ReturnDocument::After).build();
+            match state.products.find_one_and_update(doc!{"_id": keep_id}, doc!{"$set": set}, opts).await {
+                Ok(Some(p)) => keep = p,
+                Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+                Err(e) => { error!(?e, "update merged product failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+            }
+        }
+    }
+    state.search.upsert(&keep);
+    state.suggest.upsert_product(&keep);
+    crate::handlers::price_stats::price_changed(&state, std::iter::once(keep_id).chain(dup_ids.iter().copied())).await;
+    info!(keep = %keep_id, merged = dup_ids.len(), "products merged");
+    crate::handlers::events::log_event(&state, "products_merged", &format!("Товары объединены в «{}»: {}", keep.title, dups.iter().map(|d| d.title.as_str()).collect::<Vec<_>>().join(", ")), None).await;
+    (StatusCode::OK, Json(serde_json::json!({"product": keep, "merged": dup_ids, "stats": total}))).into_response()
+}
+
+// --- duplicate detection ---
+
+#[derive(serde::Deserialize)]
+pub struct DuplicatesQuery {
+    #[serde(default = "default_min_score")]
+    pub min_score: f64,
+    pub category_id: Option<String>,
+    #[serde(default = "default_groups_limit")]
+    pub limit: usize,
+}
+
+fn default_min_score() -> f64 { 0.6 }
+fn default_groups_limit() -> usize { 100 }
+const MAX_GROUPS_LIMIT: usize = 500;
+
+// tokens so that "Молоко Простоквашино 3.2%" and "Простоквашино молоко 3,2" are the same set
+pub fn title_tokens(title: &str) -> Vec<String> {
+    let mut s = String::new();
+    let mut prev: Option<char> = None;
+    // "930мл" -> "930 мл"
+    for c in title.to_lowercase().replace('ё', "е").replace(',', ".").chars() {
+        if let Some(p) = prev { if p.is_ascii_digit() != c.is_ascii_digit() && p.is_alphanumeric() && c.is_alphanumeric() { s.push(' '); } }
+        s.push(c);
+        prev = Some(c);
+    }
+    let mut out: Vec<String> = s
+        .split(|c: char| !(c.is_alphanumeric() || c == '.'))
+        .map(|t| t.trim_matches('.').to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    out.sort();
+    out.dedup();
+    out
+}
+
+// Russian endings differ a lot ("простоквашино"/"простоквашина"): long words match on a 5-char prefix
+fn tokens_match(a: &str, b: &str) -> bool {
+    if a == b { return true; }
+    let (pa, pb): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    pa.len() >= 5 && pb.len() >= 5 && !pa[0].is_ascii_digit() && pa[..5] == pb[..5]
+}
+
+// soft Jaccard over token sets
+pub fn title_similarity(a: &[String], b: &[String]) -> f64 {
+    if a.is_empty() || b.is_empty() { return 0.0; }
+    let matched = a.iter().filter(|t| b.iter().any(|u| tokens_match(t, u))).count();
+    matched as f64 / (a.len() + b.len() - matched) as f64
+}
+
+// Content hash for uploaded images (uploads get random names, so equal files differ by URL);
+// external images are compared by URL.
+fn image_key(url: &str, uploads_dir: &str) -> Option<String> {
+    use std::hash::{Hash, Hasher};
+    if url.trim().is_empty() { return None; }
+    if let Some(name) = url.strip_prefix("/uploads/").filter(|n| !n.contains('/') && !n.contains("..")) {
+        let bytes = std::fs::read(std::path::Path::new(uploads_dir).join(name)).ok()?;
+        let mut h = std::collections::hash_map::DefaultHasher::new();
+        bytes.hash(&mut h);
+        return Some(format!("{}:{:016x}", bytes.len(), h.finish()));
+    }
+    Some(url.to_string())
+}
+
+#[derive(Default, Clone, serde::Serialize)]
+struct PairSignals {
+    title: f64,
+    same_barcode: bool,
+    same_image: bool,
+    // share of common stores where prices differ by at most 2%, same category only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price_match: Option<f64>,
+    score: f64,
+}
+
+impl PairSignals {
+    fn combine(&mut self) {
+        let title = if self.title >= 0.5 { 0.85 * self.title } else { 0.0 };
+        // equal prices alone are common for unrelated products of one category: they only back up another signal
+        let backed = title > 0.0 || self.same_barcode || self.same_image;
+        let parts = [
+            title,
+            if self.same_barcode { 1.0 } else { 0.0 },
+            if self.same_image { 0.9 } else { 0.0 },
+            self.price_match.filter(|_| backed).map(|p| 0.6 * p).unwrap_or(0.0),
+        ];
+        self.score = 1.0 - parts.iter().map(|p| 1.0 - p).product::<f64>();
+    }
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut r = i;
+    while parent[r] != r { r = parent[r]; }
+    let mut c = i;
+    while parent[c] != r { let n = parent[c]; parent[c] = r; c = n; }
+    r
+}
+
+// Groups of likely duplicates with a score, strongest first. Candidate pairs come from shared
+// title tokens, barcodes, images and categories; pairs above min_score are clustered transitively.
+pub async fn list_duplicate_candidates(State(state): State<AppState>, axum::extract::Query(q): axum::extract::Query<DuplicatesQuery>) -> impl IntoResponse {
+    if !(q.min_score > 0.0 && q.min_score <= 1.0) { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_min_score"}))).into_response(); }
+    if q.limit < 1 { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_limit"}))).into_response(); }
+    let limit = q.limit.min(MAX_GROUPS_LIMIT);
+    let filter = match q.category_id.as_deref().filter(|s| !s.is_empty()) {
+        Some(cid) => match ObjectId::from_str(cid) { Ok(oid) => live(doc!{"category_ids": oid}), Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_category_id"}))).into_response() },
+        None => live(doc!{}),
+    };
+    let mut products: Vec<Product> = Vec::new();
+    let mut cursor = match state.products.find(filter, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query products failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = cursor.next().await { match res { Ok(p)=> { if p.id.is_some() { products.push(p); } }, Err(e)=> { error!(?e, "products cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    let index: std::collections::HashMap<ObjectId, usize> = products.iter().enumerate().filter_map(|(i, p)| p.id.map(|id| (id, i))).collect();
+
+    // current prices: product idx -> store -> price
+    let mut prices: Vec<std::collections::HashMap<ObjectId, crate::money::Money>> = vec![Default::default(); products.len()];
+    let mut ic = match state.store_items.find(doc!{"product_id": {"$in": index.keys().collect::<Vec<_>>()}}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query store_items failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = ic.next().await { match res { Ok(it)=> { if let Some(i) = index.get(&it.product_id) { prices[*i].insert(it.store_id, it.price); } }, Err(e)=> { error!(?e, "store_items cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+
+    let uploads_dir = std::env::var("UPLOADS_DIR").unwrap_or_else(|_| "uploads".into());
+    let urls: Vec<Option<String>> = products.iter().map(|p| p.image_url.clone()).collect();
+    let image_keys: Vec<Option<String>> = match tokio::task::spawn_blocking(move || urls.iter().map(|u| u.as_deref().and_then(|u| image_key(u, &uploads_dir))).collect()).await {
+        Ok(k) => k,
+        Err(e) => { error!(?e, "image hashing failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    };
+    let tokens: Vec<Vec<String>> = products.iter().map(|p| title_tokens(&p.title)).collect();
+
+    // blocking: bucket products by each key, pair up within buckets; very common tokens
+    // ("молоко") and huge categories would produce mostly noise and are skipped
+    const MAX_BUCKET: usize = 300;
+    let mut buckets: std::collections::HashMap<String, Vec<usize>> = std::collections::HashMap::new();
+    for (i, p) in products.iter().enumerate() {
+        for t in tokens[i].iter().filter(|t| t.chars().count() >= 3) { buckets.entry(format!("t:{}", t.chars().take(5).collect::<String>())).or_default().push(i); }
+        for b in p.barcodes.iter() { buckets.entry(format!("b:{}", b)).or_default().push(i); }
+        if let Some(k) = &image_keys[i] { buckets.entry(format!("i:{}", k)).or_default().push(i); }
+        for c in p.category_ids.iter() { buckets.entry(format!("c:{}", c)).or_default().push(i); }
+    }
+    let mut pairs: std::collections::HashMap<(usize, usize), PairSignals> = std::collections::HashMap::new();
+    for (key, members) in buckets.iter() {
+        if members.len() < 2 || (members.len() > MAX_BUCKET && !key.starts_with("b:") && !key.starts_with("i:")) { continue; }
+        for (n, a) in members.iter().enumerate() {
+            for b in members[n + 1..].iter() {
+                let (a, b) = if a < b { (*a, *b) } else { (*b, *a) };
+                if a == b || pairs.contains_key(&(a, b)) { continue; }
+                let (pa, pb) = (&products[a], &products[b]);
+                let mut sig = PairSignals { title: title_similarity(&tokens[a], &tokens[b]), ..Default::default() };
+                sig.same_barcode = pa.barcodes.iter().any(|c| pb.barcodes.contains(c));
+                sig.same_image = image_keys[a].is_some() && image_keys[a] == image_keys[b];
+                if pa.category_ids.iter().any(|c| pb.category_ids.contains(c)) {
+                    let common: Vec<(crate::money::Money, crate::money::Money)> = prices[a].iter().filter_map(|(s, pa)| prices[b].get(s).map(|pb| (*pa, *pb))).collect();
+                    if common.len() >= 2 {
+                        let close = common.iter().filter(|(x, y)| x.ratio(*y).is_some_and(|r| (r - 1.0).abs() <= 0.02)).count();
+                        sig.price_match = Some(close as f64 / common.len() as f64);
+                    }
+                }
+                sig.combine();
+                pairs.insert((a, b), sig);
+            }
+        }
+    }
+    pairs.retain(|_, s| s.score >= q.min_score);
+
+    // transitive clusters
+    let mut parent: Vec<usize> = (0..products.len()).collect();
+    for (a, b) in pairs.keys() { let (ra, rb) = (find(&mut parent, *a), find(&mut parent, *b)); if ra != rb { parent[ra] = rb; } }
+    let mut groups: std::collections::HashMap<usize, Vec<usize>> = std::collections::HashMap::new();
+    for i in pairs.keys().flat_map(|(a, b)| [*a, *b]) { let r = find(&mut parent, i); let g = groups.entry(r).or_default(); if !g.contains(&i) { g.push(i); } }
+    // maps iterate in random order: members, pairs and equal scores are ordered by product id
+    let mut groups: Vec<(f64, Vec<usize>)> = groups.into_values().map(|mut members| {
+        members.sort_by_key(|i| products[*i].id);
+        let score = pairs.iter().filter(|((a, _), _)| members.contains(a)).map(|(_, s)| s.score).fold(0.0, f64::max);
+        (score, members)
+    }).collect();
+    groups.sort_by(|(sa, ma), (sb, mb)| sb.total_cmp(sa).then_with(|| products[ma[0]].id.cmp(&products[mb[0]].id)));
+    groups.truncate(limit);
+    let out: Vec<serde_json::Value> = groups.into_iter().map(|(score, members)| {
+        let mut group_pairs: Vec<(&(usize, usize), &PairSignals)> = pairs.iter().filter(|((a, _), _)| members.contains(a)).collect();
+        group_pairs.sort_by_key(|((a, b), _)| (products[*a].id, products[*b].id));
+        serde_json::json!({
+            "score": score,
+            "products": members.iter().map(|i| { let p = &products[*i]; serde_json::json!({"_id": p.id, "title": p.title, "barcodes": p.barcodes, "image_url": p.image_url, "stores": prices[*i].len()}) }).collect::<Vec<_>>(),
+            "pairs": group_pairs.into_iter().map(|((a, b), s)| serde_json::json!({"a": products[*a].id, "b": products[*b].id, "signals": s})).collect::<Vec<_>>(),
+        })
+    }).collect();
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(s: &[&str]) -> Vec<String> { s.iter().map(|t| t.to_string()).collect() }
+
+    fn score(sig: PairSignals) -> f64 {
+        let mut sig = sig;
+        sig.combine();
+        sig.score
+    }
+
+    #[test]
+    fn tokenizes_titles() {
+        assert_eq!(title_tokens("Молоко Простоквашино 3,2% 930мл"), tokens(&["3.2", "930", "мл", "молоко", "простоквашино"]));
+        assert_eq!(title_tokens("Простоквашино молоко 3.2"), tokens(&["3.2", "молоко", "простоквашино"]));
+        assert_eq!(title_tokens("Ёжик, ёжик..."), tokens(&["ежик"]));
+        assert!(title_tokens(" - ").is_empty());
+    }
+
+    #[test]
+    fn long_tokens_match_by_prefix() {
+        assert!(tokens_match("простоквашино", "простоквашина"));
+        assert!(tokens_match("мл", "мл"));
+        assert!(!tokens_match("молоко", "молочный"));
+        // короткие — только точно
+        assert!(!tokens_match("сыр", "сыры"));
+        // числа — только точно
+        assert!(!tokens_match("12345", "123456"));
+    }
+
+    #[test]
+    fn similarity_is_soft_jaccard() {
+        let a = title_tokens("Молоко Простоквашино 3,2% 930мл");
+        let b = title_tokens("Простоквашина молоко 3.2 930 мл");
+        assert_eq!(title_similarity(&a, &b), 1.0);
+        let c = title_tokens("Молоко Домик в деревне 3,2%");
+        let s = title_similarity(&a, &c);
+        assert!(s > 0.0 && s < 0.5, "{}", s);
+        assert_eq!(title_similarity(&a, &[]), 0.0);
+    }
+
+    #[test]
+    fn price_alone_is_not_a_duplicate() {
+        let price_only = score(PairSignals { price_match: Some(1.0), ..Default::default() });
+        assert_eq!(price_only, 0.0);
+        assert!(price_only < default_min_score());
+        let weak_title = score(PairSignals { title: 0.4, price_match: Some(1.0), ..Default::default() });
+        assert_eq!(weak_title, 0.0);
+        // цена усиливает другой признак
+        let title = score(PairSignals { title: 0.6, ..Default::default() });
+        let title_price = score(PairSignals { title: 0.6, price_match: Some(1.0), ..Default::default() });
+        assert!(title_price > title && title_price >= default_min_score());
+        assert_eq!(score(PairSignals { same_barcode: true, ..Default::default() }), 1.0);
+    }
+}
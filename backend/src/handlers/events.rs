@@ -1,22 +1,29 @@
-use axum::{extract::State, response::IntoResponse, http::StatusCode, Json};
-use futures::stream::StreamExt;
+use axum::{extract::State, response::IntoResponse};
 use bson::doc;
-use tracing::error;
 
 use crate::{state::AppState, models::EventDoc};
+use crate::handlers::listing::{FilterKind, ListQuery, ListSpec};
 
 #[derive(serde::Serialize)]
 struct PublicEvent { ts_ms: i64, kind: String, message: String, user: Option<String> }
 
-pub async fn list_events(State(state): State<AppState>) -> impl IntoResponse {
+const EVENTS_LIST: ListSpec = ListSpec {
+    filters: &[
+        ("kind", "kind", FilterKind::Exact),
+        ("user", "user", FilterKind::Exact),
+        ("ts_ms", "ts_ms", FilterKind::IntRange),
+    ],
+    sortable: &["ts_ms"],
+    default_sort: "-ts_ms",
+    legacy_limit: Some(100),
+};
+
+pub async fn list_events(State(state): State<AppState>, q: ListQuery) -> impl IntoResponse {
     let col = state.db.collection::<EventDoc>("events");
-    let mut cur = match col.find(None, mongodb::options::FindOptions::builder().sort(doc!{"ts_ms": -1}).limit(100).build()).await { Ok(c)=>c, Err(e)=> { error!(?e, "events.find failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut out: Vec<PublicEvent> = Vec::new();
-    while let Some(item) = cur.next().await { match item {
-        Ok(ev) => out.push(PublicEvent { ts_ms: ev.ts_ms, kind: ev.kind, message: ev.message, user: ev.user }),
-        Err(e) => { error!(?e, "events cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
-    }}
-    Json(out).into_response()
+    match q.fetch(&col, doc!{}, &EVENTS_LIST).await {
+        Ok(page) => page.map(|ev| PublicEvent { ts_ms: ev.ts_ms, kind: ev.kind, message: ev.message, user: ev.user }).into_response(),
+        Err(e) => e.into_response(),
+    }
 }
 
 pub async fn log_event(state: &AppState, kind: &str, message: &str, user: Option<String>) {
@@ -3,7 +3,7 @@ use futures::stream::StreamExt;
 use serde::Serialize;
 use mongodb::bson::{doc, Document};
 
-use crate::{state::AppState, models::{User, Receipt, EventDoc, Operation}};
+use crate::{state::AppState, models::{User, Receipt, EventDoc, Operation, ProductRedirect}};
 
 async fn collect_all<T: Unpin + Send + for<'de> serde::Deserialize<'de> + Serialize + Clone>(
     col: &mongodb::Collection<T>,
@@ -20,6 +20,8 @@ pub async fn export_all(State(state): State<AppState>) -> impl IntoResponse {
     // typed collections present in AppState
     let products = collect_all(&state.products).await;
     let stores = collect_all(&state.stores).await;
+    let store_chains = collect_all(&state.store_chains).await;
+    let regions = collect_all(&state.regions).await;
     let categories = collect_all(&state.categories).await;
     let store_items = collect_all(&state.store_items).await;
     let store_activities = collect_all(&state.store_activities).await;
@@ -35,6 +37,8 @@ pub async fn export_all(State(state): State<AppState>) -> impl IntoResponse {
     let events = collect_all(&events_col).await;
     let operations_col = state.db.collection::<Operation>("operations");
     let operations = collect_all(&operations_col).await;
+    let product_redirects_col = state.db.collection::<ProductRedirect>("product_redirects");
+    let product_redirects = collect_all(&product_redirects_col).await;
 
     let payload = serde_json::json!({
         "meta": {
@@ -42,6 +46,8 @@ pub async fn export_all(State(state): State<AppState>) -> impl IntoResponse {
             "collections": {
                 "products": products.len(),
                 "stores": stores.len(),
+                "store_chains": store_chains.len(),
+                "regions": regions.len(),
                 "categories": categories.len(),
                 "store_items": store_items.len(),
                 "store_activities": store_activities.len(),
@@ -51,10 +57,13 @@ pub async fn export_all(State(state): State<AppState>) -> impl IntoResponse {
                 "receipts": receipts.len(),
                 "events": events.len(),
                 "operations": operations.len(),
+                "product_redirects": product_redirects.len(),
             }
         },
         "products": products,
         "stores": stores,
+        "store_chains": store_chains,
+        "regions": regions,
         "categories": categories,
         "store_items": store_items,
         "store_activities": store_activities,
@@ -64,6 +73,7 @@ pub async fn export_all(State(state): State<AppState>) -> impl IntoResponse {
         "receipts": receipts,
         "events": events,
         "operations": operations,
+        "product_redirects": product_redirects,
     });
 
     // Suggest download with filename
@@ -80,6 +90,8 @@ pub async fn export_all(State(state): State<AppState>) -> impl IntoResponse {
 pub struct ImportDump {
     #[serde(default)] products: Vec<Document>,
     #[serde(default)] stores: Vec<Document>,
+    #[serde(default)] store_chains: Vec<Document>,
+    #[serde(default)] regions: Vec<Document>,
     #[serde(default)] categories: Vec<Document>,
     #[serde(default)] store_items: Vec<Document>,
     #[serde(default)] store_activities: Vec<Document>,
@@ -89,6 +101,7 @@ pub struct ImportDump {
     #[serde(default)] receipts: Vec<Document>,
     #[serde(default)] events: Vec<Document>,
     #[serde(default)] operations: Vec<Document>,
+    #[serde(default)] product_redirects: Vec<Document>,
 }
 
 async fn replace_collection(col: &mongodb::Collection<Document>, docs: &[Document]) -> mongodb::error::Result<u64> {
@@ -102,6 +115,8 @@ pub async fn import_all(State(state): State<AppState>, Json(payload): Json<Impor
     // Use raw Document to preserve _id/ObjectId data; assumes export format
     let products_col = state.db.collection::<Document>("products");
     let stores_col = state.db.collection::<Document>("stores");
+    let store_chains_col = state.db.collection::<Document>("store_chains");
+    let regions_col = state.db.collection::<Document>("regions");
     let categories_col = state.db.collection::<Document>("categories");
     let store_items_col = state.db.collection::<Document>("store_items");
     let store_activities_col = state.db.collection::<Document>("store_activities");
@@ -111,6 +126,7 @@ pub async fn import_all(State(state): State<AppState>, Json(payload): Json<Impor
     let receipts_col = state.db.collection::<Document>("receipts");
     let events_col = state.db.collection::<Document>("events");
     let operations_col = state.db.collection::<Document>("operations");
+    let product_redirects_col = state.db.collection::<Document>("product_redirects");
 
     let mut applied: Vec<(&str, u64)> = Vec::new();
     macro_rules! apply {
@@ -124,6 +140,8 @@ pub async fn import_all(State(state): State<AppState>, Json(payload): Json<Impor
 
     apply!("products", products_col, payload.products);
     apply!("stores", stores_col, payload.stores);
+    apply!("store_chains", store_chains_col, payload.store_chains);
+    apply!("regions", regions_col, payload.regions);
     apply!("categories", categories_col, payload.categories);
     apply!("store_items", store_items_col, payload.store_items);
     apply!("store_activities", store_activities_col, payload.store_activities);
@@ -133,6 +151,18 @@ pub async fn import_all(State(state): State<AppState>, Json(payload): Json<Impor
     apply!("receipts", receipts_col, payload.receipts);
     apply!("events", events_col, payload.events);
     apply!("operations", operations_col, payload.operations);
+    apply!("product_redirects", product_redirects_col, payload.product_redirects);
+
+    // старые дампы содержат цены в рублях (double)
+    if let Err(e) = crate::db::migrations::migrate_money(&state.db).await {
+        return axum::response::Json(doc!{"status": "error", "message": format!("money migration: {}", e)}).into_response();
+    }
+    if let Err(e) = crate::search::rebuild_all(&state).await {
+        return axum::response::Json(doc!{"status": "error", "message": format!("search index: {}", e)}).into_response();
+    }
+    if let Err(e) = crate::handlers::price_stats::rebuild_all(&state).await {
+        return axum::response::Json(doc!{"status": "error", "message": format!("price stats: {}", e)}).into_response();
+    }
 
     axum::response::Json(doc!{
         "status": "ok",
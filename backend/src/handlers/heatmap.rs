@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use axum::{extract::{Query, State}, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId, Document};
+use futures::stream::StreamExt;
+use tracing::error;
+
+use crate::handlers::trash::{live, Hidden};
+use crate::models::Product;
+use crate::money::Money;
+use crate::state::AppState;
+
+#[derive(serde::Deserialize)]
+pub struct HeatmapQuery {
+    // comma-separated product ids; takes precedence over category_id
+    pub product_ids: Option<String>,
+    pub category_id: Option<String>,
+    // product | category — columns of the matrix
+    #[serde(default = "default_group_by")]
+    pub by: String,
+    // medians are computed within the region's stores
+    pub region_id: Option<String>,
+}
+
+fn default_group_by() -> String { "product".into() }
+
+// Store × product (or store × category) matrix of relative deviation from the median price.
+// deviation = price / median - 1, so 0.1 means "10% more expensive than the median store".
+pub async fn get_heatmap(State(state): State<AppState>, Query(q): Query<HeatmapQuery>) -> impl IntoResponse {
+    if q.by != "product" && q.by != "category" {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_group_by"}))).into_response();
+    }
+    // resolve product set
+    let filter: Document = if let Some(list) = q.product_ids.as_deref().filter(|s| !s.trim().is_empty()) {
+        let mut ids: Vec<ObjectId> = Vec::new();
+        for s in list.split(',') {
+            match ObjectId::parse_str(s.trim()) { Ok(oid) => ids.push(oid), Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_product_id"}))).into_response() }
+        }
+        doc!{"_id": {"$in": ids}}
+    } else if let Some(cid) = q.category_id.as_deref() {
+        let Ok(cid) = ObjectId::parse_str(cid) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_category_id"}))).into_response(); };
+        doc!{"category_ids": cid}
+    } else {
+        doc!{}
+    };
+    let mut products: HashMap<ObjectId, Product> = HashMap::new();
+    let mut pcursor = match state.products.find(live(filter), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query products for heatmap failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = pcursor.next().await {
+        match res {
+            Ok(p) => { if let Some(id) = p.id { products.insert(id, p); } }
+            Err(e) => { error!(?e, "products cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+        }
+    }
+    let pids: Vec<ObjectId> = products.keys().cloned().collect();
+    let mut items_filter = doc!{"product_id": {"$in": &pids}};
+    if let Some(rid) = q.region_id.as_deref().filter(|s| !s.is_empty()) {
+        let Ok(rid) = ObjectId::parse_str(rid) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_region_id"}))).into_response(); };
+        match crate::handlers::insights::region_store_ids(&state, rid).await {
+            Ok(ids) => { items_filter.insert("store_id", doc!{"$in": ids.into_iter().collect::<Vec<_>>()}); }
+            Err(e) => { error!(?e, "query region stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+        }
+    }
+
+    // current prices: product -> store -> price; ordered maps keep the response stable between requests
+    let mut prices: BTreeMap<ObjectId, BTreeMap<ObjectId, Money>> = BTreeMap::new();
+    let mut store_ids: BTreeSet<ObjectId> = BTreeSet::new();
+    if !pids.is_empty() {
+        let hidden = match Hidden::load(&state).await { Ok(h)=>h, Err(e)=> { error!(?e, "load trash failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        let mut cursor = match state.store_items.find(hidden.items(items_filter), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query store_items for heatmap failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        while let Some(res) = cursor.next().await {
+            match res {
+                Ok(it) => { prices.entry(it.product_id).or_default().insert(it.store_id, it.price); store_ids.insert(it.store_id); }
+                Err(e) => { error!(?e, "cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+            }
+        }
+    }
+
+    // per-product median and per-(store, product) deviation
+    let mut deviations: BTreeMap<ObjectId, Vec<(ObjectId, Money, Money, f64)>> = BTreeMap::new(); // store -> (product, price, median, deviation)
+    for (pid, by_store) in prices.iter() {
+        let mut vals: Vec<Money> = by_store.values().cloned().collect();
+        let Some(med) = Money::median(&mut vals) else { continue };
+        if med <= Money::ZERO { continue; }
+        for (sid, price) in by_store.iter() {
+            let Some(r) = price.ratio(med) else { continue };
+            deviations.entry(*sid).or_default().push((*pid, *price, med, r - 1.0));
+        }
+    }
+
+    // stores with names/coordinates
+    let mut stores_out: Vec<serde_json::Value> = Vec::new();
+    if !store_ids.is_empty() {
+        let mut scursor = match state.stores.find(doc!{"_id": {"$in": store_ids.iter().collect::<Vec<_>>() }}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query stores for heatmap failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        while let Some(res) = scursor.next().await {
+            match res {
+                Ok(s) => {
+                    let Some(sid) = s.id else { continue };
+                    let devs = deviations.get(&sid).map(|v| v.as_slice()).unwrap_or(&[]);
+                    let level = if devs.is_empty() { None } else { Some(devs.iter().map(|d| d.3).sum::<f64>() / devs.len() as f64) };
+                    stores_out.push(serde_json::json!({
+                        "store_id": sid,
+                        "store_name": s.name,
+                        "lat": s.location.as_ref().map(|l| l.lat()),
+                        "lon": s.location.as_ref().map(|l| l.lon()),
+                        "items": devs.len(),
+                        // mean deviation over all products of the store; index 100 = median level
+                        "price_level": level,
+                        "price_index": level.map(|l| ((1.0 + l) * 1000.0).round() / 10.0),
+                    }));
+                }
+                Err(e) => { error!(?e, "stores cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+            }
+        }
+    }
+    let level = |v: &serde_json::Value| v["price_level"].as_f64().unwrap_or(f64::MAX);
+    stores_out.sort_by(|a, b| level(a).total_cmp(&level(b)).then_with(|| a["store_id"].to_string().cmp(&b["store_id"].to_string())));
+
+    // matrix columns and cells
+    let mut columns: Vec<serde_json::Value> = Vec::new();
+    let mut cells: Vec<serde_json::Value> = Vec::new();
+    if q.by == "product" {
+        for pid in prices.keys() {
+            if let Some(p) = products.get(pid) { columns.push(serde_json::json!({"id": pid, "title": p.title})); }
+        }
+        for (sid, devs) in deviations.iter() {
+            for (pid, price, med, dev) in devs {
+                cells.push(serde_json::json!({"store_id": sid, "column_id": pid, "price": price, "median": med, "deviation": dev}));
+            }
+        }
+    } else {
+        // store × category: average deviation of the store's products in each category
+        let mut cat_ids: HashSet<ObjectId> = HashSet::new();
+        for (sid, devs) in deviations.iter() {
+            let mut by_cat: BTreeMap<ObjectId, (f64, usize)> = BTreeMap::new();
+            for (pid, _, _, dev) in devs {
+                let Some(p) = products.get(pid) else { continue };
+                for cid in p.category_ids.iter() {
+                    let e = by_cat.entry(*cid).or_insert((0.0, 0));
+                    e.0 += dev;
+                    e.1 += 1;
+                }
+            }
+            for (cid, (sum, n)) in by_cat {
+                cat_ids.insert(cid);
+                cells.push(serde_json::json!({"store_id": sid, "column_id": cid, "items": n, "deviation": sum / n as f64}));
+            }
+        }
+        if !cat_ids.is_empty() {
+            let mut ccursor = match state.categories.find(live(doc!{"_id": {"$in": cat_ids.iter().collect::<Vec<_>>() }}), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query categories for heatmap failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+            while let Some(res) = ccursor.next().await {
+                match res {
+                    Ok(c) => { if let Some(cid) = c.id { columns.push(serde_json::json!({"id": cid, "title": c.name})); } }
+                    Err(e) => { error!(?e, "categories cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+                }
+            }
+            // categories in the trash get no column
+            cells.retain(|c| columns.iter().any(|col| col["id"] == c["column_id"]));
+        }
+    }
+
+    // columns by title, then id; cells row by row in column order
+    let key = |v: &serde_json::Value| (v["title"].as_str().unwrap_or_default().to_lowercase(), v["id"].to_string());
+    columns.sort_by_key(key);
+    let position: HashMap<String, usize> = columns.iter().enumerate().map(|(i, c)| (c["id"].to_string(), i)).collect();
+    cells.sort_by_key(|c| (c["store_id"].to_string(), position.get(&c["column_id"].to_string()).copied().unwrap_or(usize::MAX)));
+
+    let out = serde_json::json!({
+        "by": q.by,
+        "stores": stores_out,
+        "columns": columns,
+        "cells": cells,
+    });
+    (StatusCode::OK, Json(out)).into_response()
+}
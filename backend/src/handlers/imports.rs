@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId};
+use futures::stream::StreamExt;
+use tracing::{error, info};
+
+use crate::handlers::trash::live;
+use crate::import::off;
+use crate::import::osm::{self, OsmShop};
+use crate::models::{GeoPoint, ImportJob, Product, Store};
+use crate::state::AppState;
+
+#[derive(serde::Deserialize)]
+pub struct OsmImportBody {
+    // file name inside IMPORT_DIR (.osm.pbf or .geojson)
+    pub file: String,
+    // by default only the diff is returned; pass false to write it
+    #[serde(default = "default_true")]
+    pub dry_run: bool,
+    // max distance between an OSM node and an existing store to consider them the same
+    #[serde(default = "default_match_radius_m")]
+    pub match_radius_m: f64,
+}
+
+fn default_true() -> bool { true }
+fn default_match_radius_m() -> f64 { 150.0 }
+
+// Existing store for an OSM shop: same osm_id, otherwise a nearby store with a matching
+// name/brand, otherwise a store without coordinates with the same name and address.
+async fn match_store(state: &AppState, shop: &OsmShop, radius_m: f64, taken: &HashSet<ObjectId>, unlocated: &[Store]) -> mongodb::error::Result<Option<Store>> {
+    // stores in the trash and stores already matched in this run are skipped
+    if let Some(s) = state.stores.find_one(live(doc!{"osm_id": &shop.osm_id}), None).await? {
+        if s.id.is_some_and(|id| !taken.contains(&id)) { return Ok(Some(s)); }
+    }
+    let Some(point) = GeoPoint::new(shop.lat, shop.lon) else { return Ok(None) };
+    let near = crate::handlers::stores::stores_near(state, &point, radius_m, Some(10)).await?;
+    let brand_matches = |s: &Store| match (&s.brand, &shop.brand) { (Some(a), Some(b)) => osm::names_match(a, b), _ => false };
+    if let Some((s, _)) = near.into_iter().find(|(s, _)| s.id.is_some_and(|id| !taken.contains(&id)) && (osm::names_match(&s.name, &shop.name) || brand_matches(s))) {
+        return Ok(Some(s));
+    }
+    let addr = osm::normalize_name(&shop.addr);
+    Ok(unlocated.iter().find(|s| {
+        s.id.is_some_and(|id| !taken.contains(&id)) && !addr.is_empty() && osm::names_match(&s.name, &shop.name) && osm::normalize_name(&s.addr) == addr
+    }).cloned())
+}
+
+pub async fn import_osm_stores(State(state): State<AppState>, Json(body): Json<OsmImportBody>) -> impl IntoResponse {
+    let Some(path) = crate::import::resolve_import_path(&body.file) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "file_not_found"}))).into_response(); };
+    let shops = match tokio::task::spawn_blocking(move || osm::read_shops(&path)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "parse_failed", "message": e.to_string()}))).into_response(),
+        Err(e) => { error!(?e, "osm parse task failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    };
+
+    let mut unlocated: Vec<Store> = Vec::new();
+    let mut cursor = match state.stores.find(live(doc!{"location": {"$exists": false}}), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query stores without location failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = cursor.next().await { match res { Ok(s)=> unlocated.push(s), Err(e)=> { error!(?e, "stores cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+
+    let mut to_create: Vec<&OsmShop> = Vec::new();
+    let mut to_update: Vec<(ObjectId, String, bson::Document, serde_json::Value)> = Vec::new(); // (store, name, $set, diff)
+    let mut unchanged = 0usize;
+    let mut taken: HashSet<ObjectId> = HashSet::new();
+    for shop in shops.iter() {
+        let existing = match match_store(&state, shop, body.match_radius_m, &taken, &unlocated).await { Ok(s)=>s, Err(e)=> { error!(?e, "match osm shop failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        let Some(store) = existing else { to_create.push(shop); continue };
+        let Some(sid) = store.id else { continue };
+        taken.insert(sid);
+        // OSM is trusted for coordinates and the object id; brand/addr are only filled in when empty
+        let mut set = doc!{};
+        let mut diff = serde_json::Map::new();
+        let moved = store.location.as_ref().map(|l| (l.lat() - shop.lat).abs() > 1e-6 || (l.lon() - shop.lon).abs() > 1e-6).unwrap_or(true);
+        if moved {
+            if let Some(p) = GeoPoint::new(shop.lat, shop.lon) {
+                set.insert("location", bson::to_bson(&p).unwrap_or(bson::Bson::Null));
+                diff.insert("location".into(), serde_json::json!({"from": store.location.as_ref().map(|l| [l.lat(), l.lon()]), "to": [shop.lat, shop.lon]}));
+            }
+        }
+        if store.osm_id.as_deref() != Some(shop.osm_id.as_str()) {
+            set.insert("osm_id", &shop.osm_id);
+            diff.insert("osm_id".into(), serde_json::json!({"from": store.osm_id, "to": shop.osm_id}));
+        }
+        if store.brand.is_none() {
+            if let Some(b) = &shop.brand {
+                set.insert("brand", b);
+                diff.insert("brand".into(), serde_json::json!({"from": null, "to": b}));
+            }
+        }
+        if store.addr.trim().is_empty() && !shop.addr.is_empty() {
+            set.insert("addr", &shop.addr);
+            diff.insert("addr".into(), serde_json::json!({"from": store.addr, "to": shop.addr}));
+        }
+        if set.is_empty() { unchanged += 1; } else { to_update.push((sid, store.name, set, serde_json::Value::Object(diff))); }
+    }
+
+    let mut created = 0u64;
+    let mut updated = 0u64;
+    if !body.dry_run {
+        for shop in to_create.iter() {
+            let store = Store { id: None, name: shop.name.clone(), addr: shop.addr.clone(), desc: String::new(), image_url: None, location: GeoPoint::new(shop.lat, shop.lon), brand: shop.brand.clone(), osm_id: Some(shop.osm_id.clone()), chain_id: None, region_id: None, deleted_at: None, deleted_by: None };
+            match state.stores.insert_one(store, None).await { Ok(_) => created += 1, Err(e) => { error!(?e, "insert osm store failed"); } }
+        }
+        for (sid, _, set, _) in to_update.iter() {
+            match state.stores.update_one(doc!{"_id": sid}, doc!{"$set": set.clone()}, None).await { Ok(r) => updated += r.modified_count, Err(e) => { error!(?e, "update osm store failed"); } }
+        }
+        info!(created, updated, "osm store import applied");
+        if let Err(e) = crate::search::rebuild_all(&state).await { error!(?e, "search index rebuild failed"); }
+        crate::handlers::events::log_event(&state, "osm_import", &format!("Импорт магазинов OSM: создано {}, обновлено {}", created, updated), None).await;
+    }
+
+    let out = serde_json::json!({
+        "dry_run": body.dry_run,
+        "found": shops.len(),
+        "create": to_create,
+        "update": to_update.iter().map(|(sid, name, _, diff)| serde_json::json!({"store_id": sid, "name": name, "changes": diff})).collect::<Vec<_>>(),
+        "unchanged": unchanged,
+        "created": created,
+        "updated": updated,
+    });
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+// --- Open Food Facts ---
+
+#[derive(serde::Deserialize)]
+pub struct OffImportBody {
+    // file name inside IMPORT_DIR (.jsonl / .csv, optionally .gz)
+    pub file: String,
+    // countries_tags value to keep, "en:russia" or just "russia"
+    #[serde(default = "default_country")]
+    pub country: String,
+}
+
+fn default_country() -> String { "en:russia".into() }
+
+fn now_ms() -> i64 { chrono::Utc::now().timestamp_millis() }
+
+fn import_jobs(state: &AppState) -> mongodb::Collection<ImportJob> { state.db.collection::<ImportJob>("import_jobs") }
+
+// Jobs run inside the server process; after a restart the ones left "running" can only be resumed
+pub async fn recover_import_jobs(db: &mongodb::Database) -> anyhow::Result<()> {
+    let res = db.collection::<ImportJob>("import_jobs").update_many(doc!{"status": "running"}, doc!{"$set": {"status": "interrupted"}}, None).await?;
+    if res.modified_count > 0 { info!(jobs = res.modified_count, "import jobs marked as interrupted"); }
+    Ok(())
+}
+
+// unique index file_running_unique
+fn job_running(e: &mongodb::error::Error) -> bool {
+    let msg = e.to_string();
+    msg.contains("E11000") || msg.contains("duplicate key")
+}
+
+pub async fn start_off_import(State(state): State<AppState>, Json(body): Json<OffImportBody>) -> impl IntoResponse {
+    let Some(path) = crate::import::resolve_import_path(&body.file) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "file_not_found"}))).into_response(); };
+    let jobs = import_jobs(&state);
+    let mut job = ImportJob {
+        id: None, kind: "off".into(), file: body.file, country: Some(body.country), status: "running".into(),
+        lines_done: 0, bytes_read: 0, bytes_total: std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+        created: 0, enriched: 0, unchanged: 0, skipped: 0, error: None, started_ms: now_ms(), updated_ms: now_ms(),
+    };
+    // file_running_unique: задание на этот файл уже идёт
+    match jobs.insert_one(&job, None).await {
+        Ok(res) => job.id = res.inserted_id.as_object_id(),
+        Err(e) if job_running(&e) => return (StatusCode::CONFLICT, Json(serde_json::json!({"error": "job_running"}))).into_response(),
+        Err(e) => { error!(?e, "insert import job failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    }
+    tokio::spawn(run_off_import(state.clone(), job.clone(), path));
+    (StatusCode::ACCEPTED, Json(job)).into_response()
+}
+
+pub async fn resume_import_job(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::parse_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let jobs = import_jobs(&state);
+    let mut job = match jobs.find_one(doc!{"_id": oid}, None).await {
+        Ok(Some(j)) => j,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "find import job failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    };
+    if job.status == "running" || job.status == "done" {
+        return (StatusCode::CONFLICT, Json(serde_json::json!({"error": format!("job_{}", job.status)}))).into_response();
+    }
+    let Some(path) = crate::import::resolve_import_path(&job.file) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "file_not_found"}))).into_response(); };
+    // only a job that is not running can be claimed, so two resumes can't start two runners
+    match jobs.update_one(doc!{"_id": oid, "status": &job.status}, doc!{"$set": {"status": "running", "updated_ms": now_ms()}, "$unset": {"error": ""}}, None).await {
+        Ok(r) if r.modified_count == 1 => {}
+        Ok(_) => return (StatusCode::CONFLICT, Json(serde_json::json!({"error": "job_running"}))).into_response(),
+        // другое задание на тот же файл уже идёт
+        Err(e) if job_running(&e) => return (StatusCode::CONFLICT, Json(serde_json::json!({"error": "job_running"}))).into_response(),
+        Err(e) => { error!(?e, "claim import job failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    }
+    job.status = "running".into();
+    job.error = None;
+    tokio::spawn(run_off_import(state.clone(), job.clone(), path));
+    (StatusCode::ACCEPTED, Json(job)).into_response()
+}
+
+pub async fn list_import_jobs(State(state): State<AppState>) -> impl IntoResponse {
+    let opts = mongodb::options::FindOptions::builder().sort(doc!{"started_ms": -1}).limit(50).build();
+    let mut cursor = match import_jobs(&state).find(None, opts).await { Ok(c)=>c, Err(e)=> { error!(?e, "query import jobs failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let mut out: Vec<ImportJob> = Vec::new();
+    while let Some(res) = cursor.next().await { match res { Ok(j)=> out.push(j), Err(e)=> { error!(?e, "import jobs cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+pub async fn get_import_job(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::parse_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    match import_jobs(&state).find_one(doc!{"_id": oid}, None).await {
+        Ok(Some(job)) => (StatusCode::OK, Json(job)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "find import job failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+enum Applied { Created, Enriched, Unchanged, Skipped }
+
+// progress is saved every PROGRESS_EVERY lines; a resumed job may redo up to that many lines,
+// which is harmless because products are matched by barcode
+const PROGRESS_EVERY: u64 = 500;
+
+async fn save_progress(state: &AppState, job: &ImportJob) {
+    let Some(id) = job.id else { return };
+    let mut set = doc!{
+        "status": &job.status, "lines_done": job.lines_done as i64, "bytes_read": job.bytes_read as i64,
+        "created": job.created as i64, "enriched": job.enriched as i64, "unchanged": job.unchanged as i64, "skipped": job.skipped as i64,
+        "updated_ms": now_ms(),
+    };
+    if let Some(err) = &job.error { set.insert("error", err); }
+    if let Err(e) = import_jobs(state).update_one(doc!{"_id": id}, doc!{"$set": set}, None).await { error!(?e, "save import progress failed"); }
+}
+
+async fn run_off_import(state: AppState, mut job: ImportJob, path: std::path::PathBuf) {
+    let country = job.country.clone().unwrap_or_else(default_country);
+    let categories = match category_lookup(&state).await { Ok(c)=>c, Err(e)=> { error!(?e, "load categories for import failed"); HashMap::new() } };
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<(u64, u64, Option<off::OffProduct>)>(256);
+    let skip = job.lines_done;
+    let reader = tokio::task::spawn_blocking(move || off::read_products(&path, skip, |line, bytes, p| tx.blocking_send((line, bytes, p)).is_ok()));
+    while let Some((line, bytes, product)) = rx.recv().await {
+        let applied = match product.filter(|p| p.sold_in(&country)) {
+            Some(p) => match apply_off_product(&state, &p, &categories).await {
+                Ok(a) => a,
+                Err(e) => {
+                    error!(?e, "apply off product failed");
+                    job.status = "failed".into();
+                    job.error = Some(e.to_string());
+                    break;
+                }
+            },
+            None => Applied::Skipped,
+        };
+        match applied { Applied::Created => job.created += 1, Applied::Enriched => job.enriched += 1, Applied::Unchanged => job.unchanged += 1, Applied::Skipped => job.skipped += 1 }
+        job.lines_done = line;
+        job.bytes_read = bytes;
+        if line % PROGRESS_EVERY == 0 { save_progress(&state, &job).await; }
+    }
+    drop(rx);
+    match reader.await {
+        Ok(Ok(())) if job.status == "running" => { job.status = "done".into(); job.bytes_read = job.bytes_total; }
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => { job.status = "failed".into(); job.error = Some(e.to_string()); }
+        Err(e) => { job.status = "failed".into(); job.error = Some(e.to_string()); }
+    }
+    save_progress(&state, &job).await;
+    info!(status = %job.status, created = job.created, enriched = job.enriched, "off import finished");
+    state.cache.invalidate(&["products"]);
+    crate::handlers::events::log_event(&state, "off_import", &format!("Импорт Open Food Facts ({}): создано {}, дополнено {}", job.status, job.created, job.enriched), None).await;
+}
+
+// normalized category name -> id; OFF tags are matched by their label ("en:milks" -> "milks")
+async fn category_lookup(state: &AppState) -> mongodb::error::Result<HashMap<String, ObjectId>> {
+    let mut out = HashMap::new();
+    let mut cursor = state.categories.find(None, None).await?;
+    while let Some(c) = cursor.next().await { let c = c?; if let Some(id) = c.id { out.insert(osm::normalize_name(&c.name), id); } }
+    Ok(out)
+}
+
+fn hint_categories(hints: &[String], categories: &HashMap<String, ObjectId>) -> Vec<ObjectId> {
+    let mut out: Vec<ObjectId> = Vec::new();
+    for h in hints {
+        let label = h.split_once(':').map(|(_, l)| l).unwrap_or(h);
+        if let Some(id) = categories.get(&osm::normalize_name(label)) { if !out.contains(id) { out.push(*id); } }
+    }
+    out
+}
+
+// Creates a product for an unknown barcode, otherwise fills fields that are empty or were
+// written by a previous import. Fields edited by hand are never overwritten.
+async fn apply_off_product(state: &AppState, p: &off::OffProduct, categories: &HashMap<String, ObjectId>) -> mongodb::error::Result<Applied> {
+    let Some(code) = crate::handlers::products::normalize_barcode(&p.code) else { return Ok(Applied::Skipped) };
+    if p.name.trim().is_empty() { return Ok(Applied::Skipped); }
+    let (unit, pack_size) = match p.quantity.as_deref().and_then(off::parse_quantity) { Some((u, s)) => (Some(u), Some(s)), None => (None, None) };
+    let cat_ids = hint_categories(&p.categories, categories);
+
+    let Some(existing) = state.products.find_one(doc!{"barcodes": &code}, None).await? else {
+        let mut fields: Vec<String> = vec!["title".into()];
+        if p.brand.is_some() { fields.push("brand".into()); }
+        if p.image_url.is_some() { fields.push("image_url".into()); }
+        if unit.is_some() { fields.extend(["unit".into(), "pack_size".into()]); }
+        if !p.categories.is_empty() { fields.push("category_hints".into()); }
+        if !cat_ids.is_empty() { fields.push("category_ids".into()); }
+        let product = Product {
+            id: None, title: p.name.clone(), desc: String::new(), image_url: p.image_url.clone(), category_ids: cat_ids,
+            unit, pack_size, barcodes: vec![code], brand: p.brand.clone(), category_hints: p.categories.clone(), imported_fields: fields, aliases: vec![],
+            deleted_at: None, deleted_by: None,
+        };
+        let res = state.products.insert_one(&product, None).await?;
+        let product = Product { id: res.inserted_id.as_object_id(), ..product };
+        state.search.upsert(&product);
+        state.suggest.upsert_product(&product);
+        return Ok(Applied::Created);
+    };
+
+    let owned = |field: &str, empty: bool| empty || existing.imported_fields.iter().any(|f| f == field);
+    let mut set = doc!{};
+    if owned("title", existing.title.trim().is_empty()) && existing.title != p.name { set.insert("title", &p.name); }
+    if let Some(b) = &p.brand { if owned("brand", existing.brand.is_none()) && existing.brand.as_ref() != Some(b) { set.insert("brand", b); } }
+    if let Some(img) = &p.image_url { if owned("image_url", existing.image_url.as_deref().unwrap_or("").is_empty()) && existing.image_url.as_ref() != Some(img) { set.insert("image_url", img); } }
+    if let (Some(u), Some(size)) = (unit, pack_size) {
+        if owned("unit", existing.unit.is_none()) && existing.unit != Some(u) { set.insert("unit", bson::to_bson(&u).unwrap_or(bson::Bson::Null)); }
+        if owned("pack_size", existing.pack_size.is_none()) && existing.pack_size != Some(size) { set.insert("pack_size", size); }
+    }
+    if !p.categories.is_empty() && owned("category_hints", existing.category_hints.is_empty()) && existing.category_hints != p.categories { set.insert("category_hints", &p.categories); }
+    if !cat_ids.is_empty() && owned("category_ids", existing.category_ids.is_empty()) && existing.category_ids != cat_ids { set.insert("category_ids", &cat_ids); }
+    if set.is_empty() { return Ok(Applied::Unchanged); }
+    let fields: Vec<String> = set.keys().cloned().collect();
+    state.products.update_one(doc!{"_id": existing.id}, doc!{"$set": set, "$addToSet": {"imported_fields": {"$each": fields}}}, None).await?;
+    if let Some(p) = state.products.find_one(doc!{"_id": existing.id}, None).await? {
+        state.search.upsert(&p);
+        state.suggest.upsert_product(&p);
+    }
+    Ok(Applied::Enriched)
+}
@@ -1,128 +1,299 @@
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
-use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
-use bson::{doc, oid::ObjectId};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use bson::{doc, oid::ObjectId, Document};
 use futures::stream::StreamExt;
+use mongodb::options::AggregateOptions;
 use tracing::error;
 
-use crate::models::StoreItem;
+use crate::handlers::prices::items_as_of_pipeline;
+use crate::handlers::trash::{live, Hidden};
+use crate::models::{GeoPoint, Product, Unit};
+use crate::money::{from_document_raw, Money, Quantity};
 use crate::state::AppState;
+use crate::stats::{summarize, Outliers, PriceStats};
 
-// Product-centric insights: list stores carrying the product with current price and per-store price history; also city stats
-pub async fn list_product_insights(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
-    let Ok(pid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    // current prices in stores
-    let mut cursor = match state.store_items.find(doc!{"product_id": pid.clone()}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query store_items by product failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut stores_prices: Vec<(ObjectId, f64)> = Vec::new();
-    let mut store_ids: HashSet<ObjectId> = HashSet::new();
-    while let Some(res) = cursor.next().await {
-        match res {
-            Ok(it) => { stores_prices.push((it.store_id, it.price)); store_ids.insert(it.store_id); }
-            Err(e) => { error!(?e, "cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
-        }
+// Each insight is one aggregation over store_items (or store_activities with as_of): the joins with
+// products, stores, city prices and history are $lookup stages backed by the indexes from
+// db/indexes.rs; robust stats over the joined prices are computed here (stats::summarize).
+
+#[derive(serde::Deserialize, Default)]
+pub struct InsightsQuery {
+    // optional location: restrict stats/cheapest to stores within `radius` meters
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub radius: Option<f64>,
+    // region for avg/min/max/cheapest; store insights default to the store's own region
+    pub region_id: Option<String>,
+    // ms; prices as of this moment, resolved from store_activities instead of the live store_items
+    pub as_of: Option<i64>,
+    // outlier exclusion for price stats: none (default) | mad | iqr, with an optional threshold k
+    pub outliers: Option<String>,
+    pub outlier_k: Option<f64>,
+}
+
+// price history up to as_of (all of it without one)
+fn history_filter(mut filter: Document, as_of: Option<i64>) -> Document {
+    if let Some(ts) = as_of { filter.insert("ts_ms", doc!{"$lte": ts}); }
+    filter
+}
+
+fn and(a: Document, b: Document) -> Document {
+    if a.is_empty() { b } else if b.is_empty() { a } else { doc!{"$and": [a, b]} }
+}
+
+// Which stores take part in stats: a region and/or a radius around a point. Empty scope — all stores.
+#[derive(Default)]
+pub struct StoreScope {
+    pub region_id: Option<ObjectId>,
+    region_stores: Option<HashSet<ObjectId>>,
+    nearby: Option<HashMap<ObjectId, f64>>,
+}
+
+impl StoreScope {
+    // filter restricted to the scope's stores (store_items / store_activities)
+    pub fn scoped(&self, filter: Document) -> Document {
+        let ids: Vec<ObjectId> = match (&self.region_stores, &self.nearby) {
+            (None, None) => return filter,
+            (Some(r), None) => r.iter().copied().collect(),
+            (None, Some(n)) => n.keys().copied().collect(),
+            (Some(r), Some(n)) => r.iter().filter(|id| n.contains_key(id)).copied().collect(),
+        };
+        and(filter, doc!{"store_id": {"$in": ids}})
     }
-    // store names
-    let mut stores_map: HashMap<ObjectId, String> = HashMap::new();
-    if !store_ids.is_empty() {
-        let mut sc = match state.stores.find(doc!{"_id": {"$in": store_ids.iter().collect::<Vec<_>>() }}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-        while let Some(res) = sc.next().await { match res { Ok(s)=> { if let Some(sid)=s.id { stores_map.insert(sid, s.name); } }, Err(e)=> { error!(?e, "stores cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    pub fn distance_m(&self, store_id: &ObjectId) -> Option<f64> {
+        self.nearby.as_ref().and_then(|n| n.get(store_id)).map(|d| d.round())
     }
-    // per-store history from activities
-    let mut acts_cursor = match state.store_activities.find(doc!{"product_id": pid.clone()}, mongodb::options::FindOptions::builder().sort(doc!{"ts_ms": 1}).build()).await { Ok(c)=>c, Err(e)=> { error!(?e, "query activities failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut history_by_store: HashMap<ObjectId, Vec<(i64,f64)>> = HashMap::new();
-    while let Some(res) = acts_cursor.next().await {
-        match res {
-            Ok(a) => {
-                if let (Some(sid), Some(price)) = (Some(a.store_id), a.price) { history_by_store.entry(sid).or_default().push((a.ts_ms, price)); }
-            }
-            Err(e) => { error!(?e, "activities cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+}
+
+pub async fn region_store_ids(state: &AppState, region_id: ObjectId) -> mongodb::error::Result<HashSet<ObjectId>> {
+    let mut cursor = state.stores.find(live(doc!{"region_id": region_id}), None).await?;
+    let mut out = HashSet::new();
+    while let Some(res) = cursor.next().await { if let Some(id) = res?.id { out.insert(id); } }
+    Ok(out)
+}
+
+fn bad_request(code: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": code}))).into_response()
+}
+
+pub async fn store_scope(state: &AppState, q: &InsightsQuery, default_region: Option<ObjectId>) -> Result<StoreScope, Response> {
+    let mut scope = StoreScope::default();
+    if q.radius.is_some_and(|r| r.is_nan() || r <= 0.0) { return Err(bad_request("invalid_radius")); }
+    let region_id = match q.region_id.as_deref().filter(|s| !s.is_empty()) {
+        Some(s) => Some(ObjectId::from_str(s).map_err(|_| bad_request("invalid_region_id"))?),
+        None => default_region,
+    };
+    if let Some(rid) = region_id {
+        match region_store_ids(state, rid).await {
+            Ok(ids) => { scope.region_id = Some(rid); scope.region_stores = Some(ids); }
+            Err(e) => { error!(?e, "query region stores failed"); return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()); }
         }
     }
-    // city stats
-    let count = stores_prices.len() as u64;
-    let sum: f64 = stores_prices.iter().map(|(_,p)| *p).sum();
-    let city_avg = if count>0 { Some(sum / count as f64) } else { None };
-    let min_store = stores_prices.iter().min_by(|a,b| a.1.partial_cmp(&b.1).unwrap()).cloned();
-    let max_store = stores_prices.iter().max_by(|a,b| a.1.partial_cmp(&b.1).unwrap()).cloned();
-
-    let min_json = min_store.map(|(sid,price)| serde_json::json!({"store_id": sid, "store_name": stores_map.get(&sid), "price": price}));
-    let max_json = max_store.map(|(sid,price)| serde_json::json!({"store_id": sid, "store_name": stores_map.get(&sid), "price": price}));
-
-    let stores_out: Vec<serde_json::Value> = stores_prices.into_iter().map(|(sid, price)| {
-        let hist = history_by_store.get(&sid).cloned().unwrap_or_default();
-        serde_json::json!({
-            "store_id": sid,
-            "store_name": stores_map.get(&sid),
-            "price": price,
-            "history": hist.iter().map(|(t,p)| serde_json::json!({"ts_ms": t, "price": p})).collect::<Vec<_>>()
-        })
+    let (lat, lon) = match (q.lat, q.lon) {
+        (None, None) => return Ok(scope),
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => return Err(bad_request("invalid_location")),
+    };
+    let Some(point) = GeoPoint::new(lat, lon) else { return Err(bad_request("invalid_location")); };
+    let radius = q.radius.unwrap_or_else(crate::handlers::stores::default_radius_m);
+    match crate::handlers::stores::stores_near(state, &point, radius, None).await {
+        Ok(found) => { scope.nearby = Some(found.into_iter().filter_map(|(s, d)| s.id.map(|id| (id, d))).collect()); Ok(scope) }
+        Err(e) => { error!(?e, "geoNear for insights failed"); Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()) }
+    }
+}
+
+// Positions {store_id, product_id, price} under the filter: the live store_items or, with as_of, the ones
+// resolved from history — the pipeline runs on items_source(as_of)
+fn items_pipeline(filter: Document, as_of: Option<i64>) -> Vec<Document> {
+    match as_of {
+        None => vec![doc!{"$match": filter}],
+        Some(ts) => items_as_of_pipeline(filter, ts),
+    }
+}
+
+fn items_source(as_of: Option<i64>) -> &'static str {
+    if as_of.is_some() { "store_activities" } else { "store_items" }
+}
+
+// $lookup of the prices {store_id, price} of the product in local_field (index {product_id})
+fn lookup_offers(local_field: &str, filter: Document, as_of: Option<i64>, as_field: &str) -> Document {
+    let mut pipeline = items_pipeline(filter, as_of);
+    pipeline.push(doc!{"$project": {"_id": 0, "store_id": 1, "price": 1}});
+    doc!{"$lookup": {"from": items_source(as_of), "localField": local_field, "foreignField": "product_id", "pipeline": pipeline, "as": as_field}}
+}
+
+// sub-pipeline of a $lookup into store_activities: priced entries up to as_of, oldest first (index {product_id, ts_ms})
+fn history_pipeline(filter: Document, as_of: Option<i64>) -> Vec<Document> {
+    vec![
+        doc!{"$match": history_filter(and(filter, doc!{"price": {"$ne": null}}), as_of)},
+        doc!{"$sort": {"ts_ms": 1}},
+        doc!{"$project": {"_id": 0, "ts_ms": 1, "price": 1}},
+    ]
+}
+
+async fn aggregate<T: serde::de::DeserializeOwned>(state: &AppState, collection: &str, pipeline: Vec<Document>) -> mongodb::error::Result<Vec<T>> {
+    let opts = AggregateOptions::builder().allow_disk_use(true).build();
+    let mut cursor = state.db.collection::<Document>(collection).aggregate(pipeline, opts).await?;
+    let mut out: Vec<T> = Vec::new();
+    while let Some(d) = cursor.next().await {
+        match from_document_raw::<T>(&d?) { Ok(v) => out.push(v), Err(e) => error!(?e, collection, "decode insights row failed") }
+    }
+    Ok(out)
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+struct HistoryPoint {
+    ts_ms: i64,
+    price: Money,
+}
+
+#[derive(serde::Deserialize)]
+struct Offer {
+    store_id: ObjectId,
+    price: Money,
+}
+
+// the latest priced history entry of a store
+#[derive(serde::Deserialize)]
+struct Observed {
+    #[serde(rename = "_id")]
+    store_id: ObjectId,
+    ts_ms: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct ProductStoreRow {
+    store_id: ObjectId,
+    price: Money,
+    store_name: Option<String>,
+    #[serde(default)]
+    history: Vec<HistoryPoint>,
+    observed_ts_ms: Option<i64>,
+}
+
+// Product-centric insights: list stores carrying the product with current price and per-store price history; also city stats
+pub async fn list_product_insights(State(state): State<AppState>, Path(id): Path<String>, Query(q): Query<InsightsQuery>) -> impl IntoResponse {
+    let Ok(pid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let scope = match store_scope(&state, &q, None).await { Ok(s) => s, Err(resp) => return resp };
+    let outliers = match Outliers::parse(q.outliers.as_deref(), q.outlier_k) { Ok(o) => o, Err(code) => return bad_request(code) };
+    let product = match state.products.find_one(live(doc!{"_id": pid}), None).await { Ok(p)=>p, Err(e)=> { error!(?e, "find product failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    // prices and history of products and stores in the trash are left out
+    let hidden = match Hidden::load(&state).await { Ok(h)=>h, Err(e)=> { error!(?e, "load trash failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let unit_price = |price: Money| product.as_ref().and_then(|p| p.unit_price(price));
+    let now_ms = q.as_of.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    // current prices in stores (or as of the requested moment) with the store name and the store's price history
+    let mut pipeline = items_pipeline(scope.scoped(hidden.items(doc!{"product_id": pid})), q.as_of);
+    pipeline.extend([
+        doc!{"$lookup": {"from": "stores", "localField": "store_id", "foreignField": "_id", "pipeline": [{"$project": {"name": 1}}], "as": "store"}},
+        doc!{"$lookup": {"from": "store_activities", "localField": "store_id", "foreignField": "store_id", "pipeline": history_pipeline(doc!{"product_id": pid}, q.as_of), "as": "history"}},
+        doc!{"$project": {
+            "_id": 0, "store_id": 1, "price": 1, "history": 1,
+            "store_name": {"$first": "$store.name"},
+            "observed_ts_ms": {"$max": {"$filter": {"input": "$history.ts_ms", "as": "ts", "cond": {"$lte": ["$$ts", now_ms]}}}},
+        }},
+    ]);
+    let rows: Vec<ProductStoreRow> = match aggregate(&state, items_source(q.as_of), pipeline).await { Ok(v)=>v, Err(e)=> { error!(?e, "product insights pipeline failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+
+    // city stats; with ?outliers= the excluded prices are flagged and left out of avg/min/max
+    let points: Vec<(Money, Option<i64>)> = rows.iter().map(|r| (r.price, r.observed_ts_ms)).collect();
+    let (stats, excluded) = summarize(&points, outliers, now_ms);
+    let kept: Vec<&ProductStoreRow> = rows.iter().zip(excluded.iter()).filter(|(_, out)| !**out).map(|(r, _)| r).collect();
+    let city_avg = stats.mean;
+    let offer_json = |r: &ProductStoreRow| serde_json::json!({"store_id": r.store_id, "store_name": r.store_name, "price": r.price, "unit_price": unit_price(r.price)});
+    let min_json = kept.iter().min_by_key(|r| r.price).map(|r| offer_json(r));
+    let max_json = kept.iter().max_by_key(|r| r.price).map(|r| offer_json(r));
+
+    let stores_out: Vec<serde_json::Value> = rows.iter().zip(excluded).map(|(r, outlier)| {
+        let mut v = serde_json::json!({
+            "store_id": r.store_id,
+            "store_name": r.store_name,
+            "price": r.price,
+            "unit_price": unit_price(r.price),
+            "observed_ts_ms": r.observed_ts_ms,
+            "outlier": outlier,
+            "history": r.history,
+        });
+        if let Some(d) = scope.distance_m(&r.store_id) { v["distance_m"] = serde_json::json!(d); }
+        v
     }).collect();
 
     let out = serde_json::json!({
         "stores": stores_out,
+        "region_id": scope.region_id,
+        "unit": product.as_ref().and_then(|p| p.unit),
+        "pack_size": product.as_ref().and_then(|p| p.pack_size),
         "city_avg": city_avg,
+        "city_avg_unit_price": city_avg.and_then(unit_price),
         "min": min_json,
         "max": max_json,
+        "stats": stats,
     });
     (StatusCode::OK, Json(out)).into_response()
 }
 
+#[derive(serde::Deserialize)]
+struct StoreProductRow {
+    product_id: ObjectId,
+    price: Money,
+    product: Option<Product>,
+    // prices of the product across the scope's stores
+    #[serde(default)]
+    city: Vec<Offer>,
+    #[serde(default)]
+    observed: Vec<Observed>,
+    // price history in this store
+    #[serde(default)]
+    history: Vec<HistoryPoint>,
+}
+
 // Batch insights for products in a store: current store price, city average, cheapest store, and price history in this store
-pub async fn list_store_product_insights(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+pub async fn list_store_product_insights(State(state): State<AppState>, Path(id): Path<String>, Query(q): Query<InsightsQuery>) -> impl IntoResponse {
     let Ok(store_oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    // store items for this store
-    let mut cursor = match state.store_items.find(doc!{"store_id": store_oid}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query store_items failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut store_items_vec: Vec<StoreItem> = Vec::new();
-    let mut product_ids: Vec<ObjectId> = Vec::new();
-    let mut store_prices: HashMap<ObjectId, f64> = HashMap::new();
-    while let Some(res) = cursor.next().await {
-        match res {
-            Ok(it) => {
-                store_prices.insert(it.product_id.clone(), it.price);
-                product_ids.push(it.product_id.clone());
-                store_items_vec.push(it);
-            }
-            Err(e) => { error!(?e, "cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
-        }
-    }
-    if product_ids.is_empty() {
-        return (StatusCode::OK, Json(Vec::<serde_json::Value>::new())).into_response();
-    }
-    // fetch product docs
-    let mut products_map: HashMap<ObjectId, (String, Option<String>)> = HashMap::new();
-    let mut pcursor = match state.products.find(doc!{"_id": {"$in": &product_ids}}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query products failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    while let Some(res) = pcursor.next().await {
-        match res {
-            Ok(p) => {
-                if let Some(pid) = p.id.clone() { products_map.insert(pid, (p.title, p.image_url)); }
-            }
-            Err(e) => { error!(?e, "products cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
-        }
-    }
-    // city stats from all store_items for these product_ids
-    let mut all_items_cursor = match state.store_items.find(doc!{"product_id": {"$in": &product_ids}}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query city items failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut sum: HashMap<ObjectId, f64> = HashMap::new();
-    let mut cnt: HashMap<ObjectId, u64> = HashMap::new();
-    let mut cheapest_price: HashMap<ObjectId, (f64, ObjectId)> = HashMap::new();
-    while let Some(res) = all_items_cursor.next().await {
-        match res {
-            Ok(it) => {
-                *sum.entry(it.product_id.clone()).or_insert(0.0) += it.price;
-                *cnt.entry(it.product_id.clone()).or_insert(0) += 1;
-                match cheapest_price.get(&it.product_id) {
-                    Some((p, _sid)) if *p <= it.price => {}
-                    _ => { cheapest_price.insert(it.product_id.clone(), (it.price, it.store_id.clone())); }
-                }
-            }
-            Err(e) => { error!(?e, "city items cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
-        }
+    // city stats are scoped to the store's region unless another one is requested
+    let store_region = match state.stores.find_one(doc!{"_id": store_oid}, None).await { Ok(s)=> s.and_then(|s| s.region_id), Err(e)=> { error!(?e, "find store failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let scope = match store_scope(&state, &q, store_region).await { Ok(s) => s, Err(resp) => return resp };
+    let outliers = match Outliers::parse(q.outliers.as_deref(), q.outlier_k) { Ok(o) => o, Err(code) => return bad_request(code) };
+    let hidden = match Hidden::load(&state).await { Ok(h)=>h, Err(e)=> { error!(?e, "load trash failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let now_ms = q.as_of.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    // prices across the scope's stores, without the trash
+    let offers = scope.scoped(hidden.items(doc!{}));
+    // store items for this store (or as of the requested moment), joined with the product, city prices,
+    // the latest priced entry per store (freshness) and the price history in this store
+    let mut pipeline = items_pipeline(hidden.items(doc!{"store_id": store_oid}), q.as_of);
+    pipeline.extend([
+        doc!{"$lookup": {"from": "products", "localField": "product_id", "foreignField": "_id", "pipeline": [{"$project": {"title": 1, "image_url": 1, "unit": 1, "pack_size": 1, "category_ids": 1}}], "as": "product"}},
+        lookup_offers("product_id", offers.clone(), q.as_of, "city"),
+        doc!{"$lookup": {"from": "store_activities", "localField": "product_id", "foreignField": "product_id", "pipeline": [
+            {"$match": and(offers.clone(), doc!{"price": {"$ne": null}, "ts_ms": {"$lte": now_ms}})},
+            {"$group": {"_id": "$store_id", "ts_ms": {"$max": "$ts_ms"}}},
+        ], "as": "observed"}},
+        doc!{"$lookup": {"from": "store_activities", "localField": "product_id", "foreignField": "product_id", "pipeline": history_pipeline(doc!{"store_id": store_oid}, q.as_of), "as": "history"}},
+        doc!{"$project": {"_id": 0, "product_id": 1, "price": 1, "product": {"$first": "$product"}, "city": 1, "observed": 1, "history": 1}},
+    ]);
+    let rows: Vec<StoreProductRow> = match aggregate(&state, items_source(q.as_of), pipeline).await { Ok(v)=>v, Err(e)=> { error!(?e, "store insights pipeline failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+
+    // robust stats per product; with ?outliers= the excluded prices don't count for avg and cheapest
+    let mut cheapest: Vec<Option<(ObjectId, Money)>> = Vec::with_capacity(rows.len());
+    let mut city_stats: Vec<(Option<PriceStats>, Option<bool>)> = Vec::with_capacity(rows.len());
+    for r in rows.iter() {
+        if r.city.is_empty() { cheapest.push(None); city_stats.push((None, None)); continue; }
+        let observed: HashMap<ObjectId, i64> = r.observed.iter().map(|o| (o.store_id, o.ts_ms)).collect();
+        let points: Vec<(Money, Option<i64>)> = r.city.iter().map(|o| (o.price, observed.get(&o.store_id).copied())).collect();
+        let (stats, excluded) = summarize(&points, outliers, now_ms);
+        cheapest.push(r.city.iter().zip(excluded.iter()).filter(|(_, out)| !**out).map(|(o, _)| (o.store_id, o.price)).min_by_key(|(_, price)| *price));
+        let own_outlier = r.city.iter().position(|o| o.store_id == store_oid).map(|i| excluded[i]);
+        city_stats.push((Some(stats), own_outlier));
     }
-    // fetch store names for cheapest
-    let cheapest_store_ids: HashSet<ObjectId> = cheapest_price.values().map(|(_, sid)| sid.clone()).collect();
+    // cheapest per kg/l/pcs among products of the same category and unit (other pack sizes, weighed goods)
+    let cats: HashSet<ObjectId> = rows.iter().filter_map(|r| r.product.as_ref()).filter(|p| p.unit.is_some()).flat_map(|p| p.category_ids.iter().copied()).collect();
+    let by_unit = match cheapest_by_unit(&state, cats.into_iter().collect(), &offers, q.as_of).await { Ok(m)=>m, Err(e)=> { error!(?e, "unit price pipeline failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    // names of the cheapest stores
+    let cheapest_ids: HashSet<ObjectId> = cheapest.iter().flatten().map(|(sid, _)| *sid).collect();
     let mut stores_map: HashMap<ObjectId, String> = HashMap::new();
-    if !cheapest_store_ids.is_empty() {
-        let mut scursor = match state.stores.find(doc!{"_id": {"$in": cheapest_store_ids.iter().collect::<Vec<_>>() }}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    if !cheapest_ids.is_empty() {
+        let mut scursor = match state.stores.find(doc!{"_id": {"$in": cheapest_ids.into_iter().collect::<Vec<_>>()}}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
         while let Some(res) = scursor.next().await {
             match res {
                 Ok(s) => { if let Some(sid) = s.id { stores_map.insert(sid, s.name); } }
@@ -130,47 +301,102 @@ pub async fn list_store_product_insights(State(state): State<AppState>, Path(id)
             }
         }
     }
-    // activities history for these products in this store
-    let mut acts_cursor = match state.store_activities.find(
-        doc!{"store_id": store_oid, "product_id": {"$in": &product_ids}},
-        mongodb::options::FindOptions::builder().sort(doc!{"ts_ms": 1}).build()
-    ).await { Ok(c)=>c, Err(e)=> { error!(?e, "query activities failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut history: HashMap<ObjectId, Vec<(i64,f64)>> = HashMap::new();
-    while let Some(res) = acts_cursor.next().await {
-        match res {
-            Ok(a) => {
-                if let (Some(pid), Some(price)) = (a.product_id, a.price) {
-                    history.entry(pid).or_default().push((a.ts_ms, price));
-                }
-            }
-            Err(e) => { error!(?e, "activities cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
-        }
-    }
 
     // build payload
     let mut out: Vec<serde_json::Value> = Vec::new();
-    for pid in product_ids {
-        let (title, image_url) = products_map.get(&pid).cloned().unwrap_or((pid.to_hex(), None));
-        let sp = store_prices.get(&pid).cloned();
-        let cavg = match (sum.get(&pid), cnt.get(&pid)) {
-            (Some(s), Some(c)) if *c > 0 => Some(*s / *c as f64),
-            _ => None,
-        };
-        let cheap = cheapest_price.get(&pid).map(|(p, sid)| {
-            let name = stores_map.get(sid).cloned();
-            serde_json::json!({"store_id": sid, "store_name": name, "price": p})
-        });
-        let hist = history.get(&pid).cloned().unwrap_or_default();
+    for ((r, cheap), (stats, own_outlier)) in rows.into_iter().zip(cheapest).zip(city_stats) {
+        let product = r.product.as_ref();
+        let (title, image_url) = product.map(|p| (p.title.clone(), p.image_url.clone())).unwrap_or((r.product_id.to_hex(), None));
+        let unit_price = |price: Money| product.and_then(|p| p.unit_price(price));
+        let cavg = stats.as_ref().and_then(|s| s.mean);
+        let cheap = cheap.map(|(sid, p)| serde_json::json!({"store_id": sid, "store_name": stores_map.get(&sid), "price": p, "unit_price": unit_price(p)}));
+        let cheap_unit = product.and_then(|p| p.unit.map(|u| (u, &p.category_ids))).and_then(|(u, cats)| {
+            cats.iter().filter_map(|cid| by_unit.get(&(*cid, u))).min_by_key(|c| c.unit_price)
+        }).map(|c| serde_json::json!({
+            "product_id": c.product_id,
+            "product_title": c.product_title,
+            "store_id": c.store_id,
+            "store_name": c.store_name,
+            "price": c.price,
+            "unit_price": c.unit_price,
+        }));
         out.push(serde_json::json!({
-            "product_id": pid,
+            "product_id": r.product_id,
             "product_title": title,
             "product_image_url": image_url,
-            "store_price": sp,
+            "unit": product.and_then(|p| p.unit),
+            "pack_size": product.and_then(|p| p.pack_size),
+            "store_price": r.price,
+            "store_unit_price": unit_price(r.price),
             "city_avg": cavg,
+            "city_avg_unit_price": cavg.and_then(unit_price),
+            "city_stats": stats,
+            "store_price_outlier": own_outlier,
             "cheapest": cheap,
-            "history": hist.iter().map(|(t,p)| serde_json::json!({"ts_ms": t, "price": p})).collect::<Vec<_>>()
+            "cheapest_per_unit": cheap_unit,
+            "history": r.history,
         }));
     }
 
     (StatusCode::OK, Json(out)).into_response()
 }
+
+pub struct UnitOffer {
+    pub product_id: ObjectId,
+    pub product_title: String,
+    pub store_id: ObjectId,
+    pub store_name: Option<String>,
+    pub price: Money,
+    pub unit_price: Money,
+}
+
+// the first offer of a (category, unit) partition
+#[derive(serde::Deserialize)]
+struct UnitRow {
+    category_id: ObjectId,
+    unit: Unit,
+    product_id: ObjectId,
+    product_title: String,
+    store_id: ObjectId,
+    store_name: Option<String>,
+    price: Money,
+    pack_size: Option<Quantity>,
+}
+
+// (category, unit) -> lowest price per unit within the scope, over all products of those categories:
+// offers of every product ranked per (category, unit) with $setWindowFields, the first one of each kept
+async fn cheapest_by_unit(state: &AppState, cats: Vec<ObjectId>, offers: &Document, as_of: Option<i64>) -> mongodb::error::Result<HashMap<(ObjectId, Unit), UnitOffer>> {
+    let mut out: HashMap<(ObjectId, Unit), UnitOffer> = HashMap::new();
+    if cats.is_empty() { return Ok(out); }
+    // per 1 kg/l/pcs: pack_size is stored in thousandths; weighed goods and pcs without a pack size as is
+    let unit_price = doc!{"$switch": {"branches": [
+        {"case": {"$eq": [{"$ifNull": ["$pack_size", null]}, null]}, "then": "$offer.price"},
+        {"case": {"$gt": ["$pack_size", 0]}, "then": {"$divide": [{"$multiply": ["$offer.price", 1000]}, "$pack_size"]}},
+    ], "default": null}};
+    let pipeline = vec![
+        doc!{"$match": live(doc!{"category_ids": {"$in": &cats}, "unit": {"$ne": null}})},
+        lookup_offers("_id", offers.clone(), as_of, "offer"),
+        doc!{"$unwind": "$offer"},
+        doc!{"$set": {"unit_price": unit_price}},
+        doc!{"$match": {"unit_price": {"$ne": null}}},
+        doc!{"$unwind": "$category_ids"},
+        doc!{"$match": {"category_ids": {"$in": &cats}}},
+        doc!{"$setWindowFields": {
+            "partitionBy": {"category_id": "$category_ids", "unit": "$unit"},
+            "sortBy": {"unit_price": 1},
+            "output": {"rank": {"$documentNumber": {}}},
+        }},
+        doc!{"$match": {"rank": 1}},
+        doc!{"$lookup": {"from": "stores", "localField": "offer.store_id", "foreignField": "_id", "pipeline": [{"$project": {"name": 1}}], "as": "store"}},
+        doc!{"$project": {
+            "_id": 0, "category_id": "$category_ids", "unit": 1, "product_id": "$_id", "product_title": "$title",
+            "store_id": "$offer.store_id", "store_name": {"$first": "$store.name"}, "price": "$offer.price", "pack_size": 1,
+        }},
+    ];
+    for r in aggregate::<UnitRow>(state, "products", pipeline).await? {
+        // ranked by the exact ratio, reported with the rounding of Product::unit_price
+        let Some(unit_price) = (match r.pack_size { Some(size) => r.price.per(size), None => Some(r.price) }) else { continue };
+        out.insert((r.category_id, r.unit), UnitOffer { product_id: r.product_id, product_title: r.product_title, store_id: r.store_id, store_name: r.store_name, price: r.price, unit_price });
+    }
+    Ok(out)
+}
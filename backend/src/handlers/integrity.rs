@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use futures::stream::StreamExt;
+use mongodb::options::{AggregateOptions, FindOptions};
+use tracing::{error, info};
+
+use crate::handlers::references::{col, delete_where, ids_matching, update_where};
+use crate::state::AppState;
+
+// Проверка целостности базы после ручных правок и импортов. Ищет ссылки на документы, которых
+// нет (товары, магазины, категории, сети, регионы — документы из корзины считаются живыми),
+// устаревшие product_name/store_name в истории цен, дубли и невозможные значения.
+// GET /integrity только составляет отчёт; POST /integrity/repair применяет безопасные
+// исправления — те же, что делает удаление с policy=cascade. Дубли логинов, отрицательные
+// цены и циклы категорий исправляются вручную: в отчёте у них repair = null.
+
+const SAMPLE_IDS: usize = 20;
+
+#[derive(serde::Serialize)]
+pub struct Issue {
+    pub check: &'static str,
+    pub collection: &'static str,
+    pub count: usize,
+    pub ids: Vec<ObjectId>,
+    pub repair: Option<&'static str>, // delete | unset | pull | rename | keep_newest
+    pub repaired: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct Report {
+    pub applied: bool,
+    pub ts_ms: i64,
+    pub issues: Vec<Issue>,
+}
+
+struct Checker<'a> {
+    state: &'a AppState,
+    apply: bool,
+    issues: Vec<Issue>,
+}
+
+async fn names(state: &AppState, collection: &str, field: &str) -> mongodb::error::Result<HashMap<ObjectId, String>> {
+    let opts = FindOptions::builder().projection(doc!{field: 1}).build();
+    let mut cursor = col(state, collection).find(None, opts).await?;
+    let mut out: HashMap<ObjectId, String> = HashMap::new();
+    while let Some(d) = cursor.next().await {
+        let d = d?;
+        if let Ok(id) = d.get_object_id("_id") { out.insert(id, d.get_str(field).unwrap_or_default().to_string()); }
+    }
+    Ok(out)
+}
+
+async fn known(state: &AppState, collection: &str) -> mongodb::error::Result<HashSet<ObjectId>> {
+    Ok(ids_matching(state, collection, doc!{}, None).await?.into_iter().collect())
+}
+
+// Документы, у которых в field (ObjectId или массив ObjectId) есть id не из known, и сами эти id
+async fn dangling(state: &AppState, collection: &str, field: &str, known: &HashSet<ObjectId>) -> mongodb::error::Result<(Vec<ObjectId>, Vec<ObjectId>)> {
+    let opts = FindOptions::builder().projection(doc!{field: 1}).build();
+    let mut cursor = col(state, collection).find(doc!{field: {"$ne": null}}, opts).await?;
+    let mut docs: Vec<ObjectId> = Vec::new();
+    let mut missing: HashSet<ObjectId> = HashSet::new();
+    while let Some(d) = cursor.next().await {
+        let d = d?;
+        let refs: Vec<ObjectId> = match d.get(field) {
+            Some(Bson::ObjectId(id)) => vec![*id],
+            Some(Bson::Array(a)) => a.iter().filter_map(|v| v.as_object_id()).collect(),
+            _ => Vec::new(),
+        };
+        let gone: Vec<ObjectId> = refs.into_iter().filter(|r| !known.contains(r)).collect();
+        if gone.is_empty() { continue; }
+        if let Ok(id) = d.get_object_id("_id") { docs.push(id); }
+        missing.extend(gone);
+    }
+    Ok((docs, missing.into_iter().collect()))
+}
+
+impl Checker<'_> {
+    fn push(&mut self, check: &'static str, collection: &'static str, ids: Vec<ObjectId>, repair: Option<&'static str>, repaired: usize) {
+        if ids.is_empty() { return; }
+        let count = ids.len();
+        let ids = ids.into_iter().take(SAMPLE_IDS).collect();
+        self.issues.push(Issue { check, collection, count, ids, repair, repaired });
+    }
+
+    // позиции и история без товара или магазина удаляются, как при policy=cascade
+    async fn delete_orphans(&mut self, check: &'static str, collection: &'static str, field: &str, known: &HashSet<ObjectId>) -> mongodb::error::Result<()> {
+        let (docs, _) = dangling(self.state, collection, field, known).await?;
+        let repaired = if self.apply && !docs.is_empty() { delete_where(self.state, collection, doc!{"_id": {"$in": &docs}}).await?.count } else { 0 };
+        self.push(check, collection, docs, Some("delete"), repaired);
+        Ok(())
+    }
+
+    async fn unset_orphans(&mut self, check: &'static str, collection: &'static str, field: &str, known: &HashSet<ObjectId>) -> mongodb::error::Result<()> {
+        let (docs, _) = dangling(self.state, collection, field, known).await?;
+        let repaired = if self.apply && !docs.is_empty() {
+            update_where(self.state, collection, "unlinked", doc!{"_id": {"$in": &docs}}, doc!{"$unset": {field: ""}}, None).await?.count
+        } else { 0 };
+        self.push(check, collection, docs, Some("unset"), repaired);
+        Ok(())
+    }
+
+    async fn pull_orphans(&mut self, check: &'static str, collection: &'static str, field: &str, known: &HashSet<ObjectId>) -> mongodb::error::Result<()> {
+        let (docs, missing) = dangling(self.state, collection, field, known).await?;
+        let repaired = if self.apply && !docs.is_empty() {
+            update_where(self.state, collection, "unlinked", doc!{"_id": {"$in": &docs}}, doc!{"$pull": {field: {"$in": &missing}}}, None).await?.count
+        } else { 0 };
+        self.push(check, collection, docs, Some("pull"), repaired);
+        Ok(())
+    }
+
+    // только отчёт: безопасного автоматического исправления нет
+    async fn report(&mut self, check: &'static str, collection: &'static str, filter: Document) -> mongodb::error::Result<()> {
+        let ids = ids_matching(self.state, collection, filter, None).await?;
+        self.push(check, collection, ids, None, 0);
+        Ok(())
+    }
+
+    // в старых чеках product_id мог сохраниться строкой; позиция остаётся, ссылка снимается
+    async fn operation_items(&mut self, products: &HashMap<ObjectId, String>) -> mongodb::error::Result<()> {
+        let opts = FindOptions::builder().projection(doc!{"items.product_id": 1}).build();
+        let mut cursor = col(self.state, "operations").find(doc!{"items.product_id": {"$ne": null}}, opts).await?;
+        let mut docs: Vec<ObjectId> = Vec::new();
+        let mut missing: Vec<Bson> = Vec::new();
+        while let Some(d) = cursor.next().await {
+            let d = d?;
+            let mut gone = false;
+            for it in d.get_array("items").map(|a| a.as_slice()).unwrap_or_default().iter().filter_map(|v| v.as_document()) {
+                let exists = match it.get("product_id") {
+                    Some(Bson::ObjectId(id)) => products.contains_key(id),
+                    Some(Bson::String(s)) => ObjectId::parse_str(s).map(|id| products.contains_key(&id)).unwrap_or(false),
+                    _ => true,
+                };
+                if exists { continue; }
+                gone = true;
+                let v = it.get("product_id").cloned().unwrap_or(Bson::Null);
+                if !missing.contains(&v) { missing.push(v); }
+            }
+            if gone { if let Ok(id) = d.get_object_id("_id") { docs.push(id); } }
+        }
+        let repaired = if self.apply && !docs.is_empty() {
+            let filters = Some(vec![doc!{"it.product_id": {"$in": &missing}}]);
+            update_where(self.state, "operations", "unlinked", doc!{"_id": {"$in": &docs}}, doc!{"$unset": {"items.$[it].product_id": ""}}, filters).await?.count
+        } else { 0 };
+        self.push("orphan_product", "operations", docs, Some("unset"), repaired);
+        Ok(())
+    }
+
+    // product_name/store_name в истории — копия названия на момент записи; приводим к текущему
+    async fn stale_names(&mut self, products: &HashMap<ObjectId, String>, stores: &HashMap<ObjectId, String>) -> mongodb::error::Result<()> {
+        let opts = FindOptions::builder().projection(doc!{"product_id": 1, "product_name": 1, "store_id": 1, "store_name": 1}).build();
+        let mut cursor = col(self.state, "store_activities").find(None, opts).await?;
+        let mut by_product: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+        let mut by_store: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+        while let Some(d) = cursor.next().await {
+            let d = d?;
+            let Ok(id) = d.get_object_id("_id") else { continue };
+            if let Some((pid, title)) = d.get_object_id("product_id").ok().and_then(|p| products.get_key_value(&p)) {
+                if d.get_str("product_name").ok() != Some(title.as_str()) { by_product.entry(*pid).or_default().push(id); }
+            }
+            if let Some((sid, name)) = d.get_object_id("store_id").ok().and_then(|s| stores.get_key_value(&s)) {
+                if d.get_str("store_name").ok() != Some(name.as_str()) { by_store.entry(*sid).or_default().push(id); }
+            }
+        }
+        for (check, field, groups, current) in [("stale_product_name", "product_name", by_product, products), ("stale_store_name", "store_name", by_store, stores)] {
+            let mut repaired = 0;
+            if self.apply {
+                for (id, docs) in groups.iter() {
+                    let res = col(self.state, "store_activities").update_many(doc!{"_id": {"$in": docs}}, doc!{"$set": {field: &current[id]}}, None).await?;
+                    repaired += res.modified_count as usize;
+                }
+            }
+            self.push(check, "store_activities", groups.into_values().flatten().collect(), Some("rename"), repaired);
+        }
+        Ok(())
+    }
+
+    // группы документов с одинаковым ключом; в каждой первым идёт самый старый _id
+    async fn groups(&self, collection: &str, key: Document) -> mongodb::error::Result<Vec<Vec<ObjectId>>> {
+        let pipeline = vec![
+            doc!{"$group": {"_id": key, "ids": {"$push": "$_id"}, "n": {"$sum": 1}}},
+            doc!{"$match": {"n": {"$gt": 1}}},
+        ];
+        let opts = AggregateOptions::builder().allow_disk_use(true).build();
+        let mut cursor = col(self.state, collection).aggregate(pipeline, opts).await?;
+        let mut out: Vec<Vec<ObjectId>> = Vec::new();
+        while let Some(d) = cursor.next().await {
+            let mut ids: Vec<ObjectId> = d?.get_array("ids").map(|a| a.iter().filter_map(|v| v.as_object_id()).collect()).unwrap_or_default();
+            ids.sort();
+            out.push(ids);
+        }
+        Ok(out)
+    }
+
+    // у товара в магазине одна цена: остаётся последняя добавленная позиция
+    async fn duplicate_store_items(&mut self) -> mongodb::error::Result<()> {
+        let extra: Vec<ObjectId> = self.groups("store_items", doc!{"store_id": "$store_id", "product_id": "$product_id"}).await?
+            .into_iter().flat_map(|mut ids| { ids.pop(); ids }).collect();
+        let repaired = if self.apply && !extra.is_empty() { delete_where(self.state, "store_items", doc!{"_id": {"$in": &extra}}).await?.count } else { 0 };
+        self.push("duplicate", "store_items", extra, Some("keep_newest"), repaired);
+        Ok(())
+    }
+
+    // какой из одноимённых аккаунтов настоящий, решает админ; уникальный индекс создастся при старте после чистки
+    async fn duplicate_usernames(&mut self) -> mongodb::error::Result<()> {
+        let ids: Vec<ObjectId> = self.groups("users", doc!{"username": "$username"}).await?.into_iter().flatten().collect();
+        self.push("duplicate_username", "users", ids, None, 0);
+        Ok(())
+    }
+
+    // категории, которые оказались собственными предками (данные до проверки parent_ids)
+    async fn category_cycles(&mut self) -> mongodb::error::Result<()> {
+        let opts = FindOptions::builder().projection(doc!{"parent_ids": 1}).build();
+        let mut cursor = col(self.state, "categories").find(None, opts).await?;
+        let mut parents: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+        while let Some(d) = cursor.next().await {
+            let d = d?;
+            let Ok(id) = d.get_object_id("_id") else { continue };
+            parents.insert(id, d.get_array("parent_ids").map(|a| a.iter().filter_map(|v| v.as_object_id()).collect()).unwrap_or_default());
+        }
+        let mut looped: Vec<ObjectId> = Vec::new();
+        for &id in parents.keys() {
+            let mut seen: HashSet<ObjectId> = HashSet::new();
+            let mut stack: Vec<ObjectId> = parents[&id].clone();
+            while let Some(p) = stack.pop() {
+                if p == id { looped.push(id); break; }
+                if seen.insert(p) { stack.extend(parents.get(&p).into_iter().flatten().copied()); }
+            }
+        }
+        looped.sort();
+        self.push("category_cycle", "categories", looped, None, 0);
+        Ok(())
+    }
+}
+
+pub async fn run(state: &AppState, apply: bool) -> mongodb::error::Result<Report> {
+    let products = names(state, "products", "title").await?;
+    let stores = names(state, "stores", "name").await?;
+    let product_ids: HashSet<ObjectId> = products.keys().copied().collect();
+    let store_ids: HashSet<ObjectId> = stores.keys().copied().collect();
+    let categories = known(state, "categories").await?;
+    let chains = known(state, "store_chains").await?;
+    let regions = known(state, "regions").await?;
+
+    let mut c = Checker { state, apply, issues: Vec::new() };
+    // висячие ссылки
+    c.delete_orphans("orphan_product", "store_items", "product_id", &product_ids).await?;
+    c.delete_orphans("orphan_store", "store_items", "store_id", &store_ids).await?;
+    c.delete_orphans("orphan_product", "store_activities", "product_id", &product_ids).await?;
+    c.delete_orphans("orphan_store", "store_activities", "store_id", &store_ids).await?;
+    c.delete_orphans("orphan_target", "product_redirects", "to", &product_ids).await?;
+    c.operation_items(&products).await?;
+    c.unset_orphans("orphan_store", "operations", "store_id", &store_ids).await?;
+    c.unset_orphans("orphan_chain", "stores", "chain_id", &chains).await?;
+    c.unset_orphans("orphan_region", "stores", "region_id", &regions).await?;
+    c.pull_orphans("orphan_category", "products", "category_ids", &categories).await?;
+    c.pull_orphans("orphan_parent", "categories", "parent_ids", &categories).await?;
+    c.category_cycles().await?;
+    // копии названий и дубли
+    c.stale_names(&products, &stores).await?;
+    c.duplicate_store_items().await?;
+    c.duplicate_usernames().await?;
+    // невозможные значения
+    c.report("negative_price", "store_items", doc!{"price": {"$lt": 0}}).await?;
+    c.report("negative_price", "store_activities", doc!{"price": {"$lt": 0}}).await?;
+    c.report("invalid_amount", "operations", doc!{"$or": [{"amount": {"$lt": 0}}, {"items.price": {"$lt": 0}}, {"items.quantity": {"$lte": 0}}]}).await?;
+    c.report("invalid_pack_size", "products", doc!{"pack_size": {"$lte": 0}}).await?;
+
+    let repaired: usize = c.issues.iter().map(|i| i.repaired).sum();
+    // category_ids у товаров — фильтр поискового индекса
+    if repaired > 0 {
+        if let Err(e) = state.search.rebuild(&state.products).await { error!(?e, "search index rebuild failed"); }
+        // уникальные индексы, не созданные при старте из-за дублей
+        if let Err(e) = crate::db::indexes::ensure_indexes(&state.db).await { error!(?e, "ensure indexes failed"); }
+        crate::handlers::price_stats::prices_changed(state).await;
+    }
+    info!(issues = c.issues.len(), found = c.issues.iter().map(|i| i.count).sum::<usize>(), repaired, apply, "integrity check finished");
+    Ok(Report { applied: apply, ts_ms: chrono::Utc::now().timestamp_millis(), issues: c.issues })
+}
+
+async fn respond(state: &AppState, apply: bool) -> axum::response::Response {
+    match run(state, apply).await {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(e) => { error!(?e, "integrity check failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+// GET /integrity — отчёт без изменений (dry run)
+pub async fn check_integrity(State(state): State<AppState>) -> impl IntoResponse {
+    respond(&state, false).await
+}
+
+// POST /integrity/repair — проверка с безопасными исправлениями
+pub async fn repair_integrity(State(state): State<AppState>) -> impl IntoResponse {
+    respond(&state, true).await
+}
@@ -0,0 +1,399 @@
+use std::str::FromStr;
+
+use axum::{async_trait, extract::{FromRequestParts, Query}, http::{request::Parts, StatusCode}, response::{IntoResponse, Response}, Json};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use futures::stream::StreamExt;
+use mongodb::{options::{CountOptions, FindOptions}, Collection};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
+
+use crate::money::Money;
+
+// Общий контракт списков: ?limit=&offset= (или page=), ?cursor= (keyset по полям сортировки),
+// ?sort=title,-ts_ms и фильтры по полям, которые объявляет обработчик в ListSpec.
+// Ответ — {items, total, limit, offset, next_cursor}. Запрос без этих параметров пока получает
+// прежний ответ (массив целиком или последние N записей), чтобы фронтенд переходил постепенно.
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Clone, Copy)]
+pub enum FilterKind {
+    // точное совпадение строки (для массивов — любой элемент)
+    Exact,
+    // подстрока без учёта регистра
+    Contains,
+    ObjectId,
+    // field_gte / field_lte, целые (ts_ms)
+    IntRange,
+    // field_gte / field_lte в рублях, в базе копейки
+    MoneyRange,
+}
+
+pub struct ListSpec {
+    // (параметр запроса, поле документа, вид фильтра)
+    pub filters: &'static [(&'static str, &'static str, FilterKind)],
+    pub sortable: &'static [&'static str],
+    // сортировка по умолчанию в формате параметра sort
+    pub default_sort: &'static str,
+    // сколько отдавать в старом формате (None — всё)
+    pub legacy_limit: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+pub struct ListQuery {
+    limit: Option<i64>,
+    offset: Option<u64>,
+    page: Option<u64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    params: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum ListError {
+    BadRequest(&'static str, String),
+    Db(mongodb::error::Error),
+}
+
+impl From<mongodb::error::Error> for ListError {
+    fn from(e: mongodb::error::Error) -> Self { ListError::Db(e) }
+}
+
+impl IntoResponse for ListError {
+    fn into_response(self) -> Response {
+        match self {
+            ListError::BadRequest(code, param) => (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": code, "param": param}))).into_response(),
+            ListError::Db(e) => {
+                error!(?e, "list query failed");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ListQuery {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(pairs) = Query::<Vec<(String, String)>>::try_from_uri(&parts.uri).map_err(|e| e.into_response())?;
+        let mut q = ListQuery::default();
+        let bad = |p: &str| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_param", "param": p}))).into_response();
+        for (k, v) in pairs {
+            match k.as_str() {
+                "limit" => q.limit = Some(v.parse().map_err(|_| bad("limit"))?),
+                "offset" => q.offset = Some(v.parse().map_err(|_| bad("offset"))?),
+                "page" => q.page = Some(v.parse().ok().filter(|p| *p >= 1).ok_or_else(|| bad("page"))?),
+                "cursor" => q.cursor = Some(v).filter(|c| !c.is_empty()),
+                "sort" => q.sort = Some(v).filter(|s| !s.is_empty()),
+                _ => q.params.push((k, v)),
+            }
+        }
+        Ok(q)
+    }
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub limit: i64,
+    pub offset: u64,
+    pub next_cursor: Option<String>,
+    // запрос без параметров списка — отвечать старым форматом
+    pub legacy: bool,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page { items: self.items.into_iter().map(f).collect(), total: self.total, limit: self.limit, offset: self.offset, next_cursor: self.next_cursor, legacy: self.legacy }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        if self.legacy { return (StatusCode::OK, Json(self.items)).into_response(); }
+        (StatusCode::OK, Json(serde_json::json!({
+            "items": self.items,
+            "total": self.total,
+            "limit": self.limit,
+            "offset": self.offset,
+            "next_cursor": self.next_cursor,
+        }))).into_response()
+    }
+}
+
+impl ListQuery {
+    fn is_legacy(&self, spec: &ListSpec) -> bool {
+        self.limit.is_none() && self.offset.is_none() && self.page.is_none() && self.cursor.is_none() && self.sort.is_none()
+            && !self.params.iter().any(|(k, _)| spec.filters.iter().any(|(p, _, kind)| filter_param_matches(k, p, *kind)))
+    }
+
+    fn sort_fields(&self, spec: &ListSpec) -> Result<Vec<(String, i32)>, ListError> {
+        let raw = self.sort.as_deref().unwrap_or(spec.default_sort);
+        let mut out: Vec<(String, i32)> = Vec::new();
+        for part in raw.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let (field, dir) = match part.strip_prefix('-') { Some(f) => (f, -1), None => (part.trim_start_matches('+'), 1) };
+            if !spec.sortable.contains(&field) && field != "_id" { return Err(ListError::BadRequest("invalid_sort", field.to_string())); }
+            if !out.iter().any(|(f, _)| f == field) { out.push((field.to_string(), dir)); }
+        }
+        // _id делает порядок однозначным — без него курсор может пропускать записи
+        if !out.iter().any(|(f, _)| f == "_id") {
+            let dir = out.last().map(|(_, d)| *d).unwrap_or(1);
+            out.push(("_id".to_string(), dir));
+        }
+        Ok(out)
+    }
+
+    fn filter(&self, spec: &ListSpec) -> Result<Document, ListError> {
+        let mut and: Vec<Document> = Vec::new();
+        for (key, value) in self.params.iter() {
+            for (param, field, kind) in spec.filters.iter() {
+                if !filter_param_matches(key, param, *kind) { continue; }
+                let bad = || ListError::BadRequest("invalid_filter", key.clone());
+                let cond = match kind {
+                    FilterKind::Exact => doc!{*field: value},
+                    FilterKind::Contains => doc!{*field: {"$regex": regex_escape(value), "$options": "i"}},
+                    FilterKind::ObjectId => doc!{*field: ObjectId::from_str(value).map_err(|_| bad())?},
+                    FilterKind::IntRange | FilterKind::MoneyRange => {
+                        let op = if key.ends_with("_gte") { "$gte" } else { "$lte" };
+                        let v: Bson = match kind {
+                            FilterKind::IntRange => value.parse::<i64>().map_err(|_| bad())?.into(),
+                            _ => Money::from_f64(value.replace(',', ".").parse::<f64>().ok().filter(|v| v.is_finite()).ok_or_else(bad)?).into(),
+                        };
+                        doc!{*field: {op: v}}
+                    }
+                };
+                and.push(cond);
+            }
+        }
+        Ok(if and.is_empty() { doc!{} } else { doc!{"$and": and} })
+    }
+
+    // Страница коллекции с учётом base (ограничения самого обработчика, например store_id)
+    pub async fn fetch<T>(&self, col: &Collection<T>, base: Document, spec: &ListSpec) -> Result<Page<T>, ListError>
+    where
+        T: DeserializeOwned + Serialize + Unpin + Send + Sync,
+    {
+        let legacy = self.is_legacy(spec);
+        let sort = self.sort_fields(spec)?;
+        let mut filter = self.filter(spec)?;
+        if !base.is_empty() { filter = doc!{"$and": [base, filter]}; }
+        let total = col.count_documents(filter.clone(), CountOptions::builder().build()).await?;
+
+        let sort_doc: Document = sort.iter().map(|(f, d)| (f.clone(), Bson::Int32(*d))).collect();
+        if legacy {
+            // прежние ответы: вся коллекция в естественном порядке или последние legacy_limit записей
+            let opts = FindOptions::builder().sort(spec.legacy_limit.map(|_| sort_doc)).limit(spec.legacy_limit).build();
+            let items = collect(col, filter, opts).await?;
+            return Ok(Page { limit: items.len() as i64, items, total, offset: 0, next_cursor: None, legacy });
+        }
+
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let mut offset = self.offset.unwrap_or_else(|| self.page.map(|p| (p - 1) * limit as u64).unwrap_or(0));
+        let mut page_filter = filter;
+        if let Some(token) = &self.cursor {
+            let after = decode_cursor(token, &sort).ok_or_else(|| ListError::BadRequest("invalid_cursor", "cursor".into()))?;
+            page_filter = doc!{"$and": [page_filter, keyset_after(&sort, &after)]};
+            offset = 0;
+        }
+        // на одну запись больше — чтобы знать, есть ли следующая страница
+        let opts = FindOptions::builder().sort(sort_doc).skip(offset).limit(limit + 1).build();
+        let mut items = collect(col, page_filter, opts).await?;
+        let has_more = items.len() as i64 > limit;
+        items.truncate(limit as usize);
+        let next_cursor = if has_more { items.last().and_then(|last| encode_cursor(last, &sort)) } else { None };
+        Ok(Page { items, total, limit, offset: if self.cursor.is_some() { 0 } else { offset }, next_cursor, legacy })
+    }
+
+    // То же для записей, которые собирает агрегация (например, цены на дату из истории): pipeline
+    // выдаёт документы вида T, фильтры, сортировка и курсор применяются к его результату
+    pub async fn fetch_pipeline<T, C>(&self, col: &Collection<C>, mut pipeline: Vec<Document>, spec: &ListSpec) -> Result<Page<T>, ListError>
+    where
+        T: DeserializeOwned + Serialize,
+    {
+        let legacy = self.is_legacy(spec);
+        let sort = self.sort_fields(spec)?;
+        let filter = self.filter(spec)?;
+        if !filter.is_empty() { pipeline.push(doc!{"$match": filter}); }
+        let opts = mongodb::options::AggregateOptions::builder().allow_disk_use(true).build();
+        let total = match col.aggregate([pipeline.clone(), vec![doc!{"$count": "n"}]].concat(), opts).await?.next().await {
+            Some(d) => { let d = d?; d.get_i32("n").map(|n| n as u64).or_else(|_| d.get_i64("n").map(|n| n as u64)).unwrap_or(0) }
+            None => 0,
+        };
+
+        let sort_doc: Document = sort.iter().map(|(f, d)| (f.clone(), Bson::Int32(*d))).collect();
+        if legacy {
+            if let Some(n) = spec.legacy_limit { pipeline.extend([doc!{"$sort": sort_doc}, doc!{"$limit": n}]); }
+            let items: Vec<T> = collect_pipeline(col, pipeline).await?;
+            return Ok(Page { limit: items.len() as i64, items, total, offset: 0, next_cursor: None, legacy });
+        }
+
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let mut offset = self.offset.unwrap_or_else(|| self.page.map(|p| (p - 1) * limit as u64).unwrap_or(0));
+        if let Some(token) = &self.cursor {
+            let after = decode_cursor(token, &sort).ok_or_else(|| ListError::BadRequest("invalid_cursor", "cursor".into()))?;
+            pipeline.push(doc!{"$match": keyset_after(&sort, &after)});
+            offset = 0;
+        }
+        pipeline.extend([doc!{"$sort": sort_doc}, doc!{"$skip": offset as i64}, doc!{"$limit": limit + 1}]);
+        let mut items: Vec<T> = collect_pipeline(col, pipeline).await?;
+        let has_more = items.len() as i64 > limit;
+        items.truncate(limit as usize);
+        let next_cursor = if has_more { items.last().and_then(|last| encode_cursor(last, &sort)) } else { None };
+        Ok(Page { items, total, limit, offset: if self.cursor.is_some() { 0 } else { offset }, next_cursor, legacy })
+    }
+}
+
+fn filter_param_matches(key: &str, param: &str, kind: FilterKind) -> bool {
+    match kind {
+        FilterKind::IntRange | FilterKind::MoneyRange => key.strip_prefix(param).is_some_and(|rest| rest == "_gte" || rest == "_lte"),
+        _ => key == param,
+    }
+}
+
+fn regex_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) { out.push('\\'); }
+        out.push(c);
+    }
+    out
+}
+
+async fn collect<T>(col: &Collection<T>, filter: Document, opts: FindOptions) -> mongodb::error::Result<Vec<T>>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let mut cursor = col.find(filter, opts).await?;
+    let mut items: Vec<T> = Vec::new();
+    while let Some(res) = cursor.next().await { items.push(res?); }
+    Ok(items)
+}
+
+async fn collect_pipeline<T: DeserializeOwned, C>(col: &Collection<C>, pipeline: Vec<Document>) -> mongodb::error::Result<Vec<T>> {
+    let opts = mongodb::options::AggregateOptions::builder().allow_disk_use(true).build();
+    let mut cursor = col.aggregate(pipeline, opts).await?;
+    let mut items: Vec<T> = Vec::new();
+    while let Some(d) = cursor.next().await {
+        match crate::money::from_document_raw(&d?) { Ok(it) => items.push(it), Err(e) => error!(?e, "decode aggregated list item failed") }
+    }
+    Ok(items)
+}
+
+// Курсор — значения полей сортировки последней записи (BSON в hex), вместе с самой сортировкой:
+// курсор от другой сортировки не принимается.
+fn encode_cursor<T: Serialize>(item: &T, sort: &[(String, i32)]) -> Option<String> {
+    let Ok(Bson::Document(d)) = crate::money::to_bson_raw(item) else { return None };
+    let values: Vec<Bson> = sort.iter().map(|(f, _)| d.get(f).cloned().unwrap_or(Bson::Null)).collect();
+    let sort_key: Vec<String> = sort.iter().map(|(f, dir)| format!("{}{}", if *dir < 0 { "-" } else { "" }, f)).collect();
+    let bytes = bson::to_vec(&doc!{"s": sort_key.join(","), "v": values}).ok()?;
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn decode_cursor(token: &str, sort: &[(String, i32)]) -> Option<Vec<Bson>> {
+    if !token.len().is_multiple_of(2) { return None; }
+    let bytes: Vec<u8> = (0..token.len()).step_by(2).map(|i| u8::from_str_radix(token.get(i..i + 2)?, 16).ok()).collect::<Option<_>>()?;
+    let d: Document = bson::from_slice(&bytes).ok()?;
+    let sort_key: Vec<String> = sort.iter().map(|(f, dir)| format!("{}{}", if *dir < 0 { "-" } else { "" }, f)).collect();
+    if d.get_str("s").ok()? != sort_key.join(",") { return None; }
+    let values = d.get_array("v").ok()?.clone();
+    (values.len() == sort.len()).then_some(values)
+}
+
+// (a > va) or (a = va and b > vb) or ... — с учётом направления каждого поля. Отсутствующее поле
+// в курсоре — null: Mongo ставит такие записи первыми по возрастанию и последними по убыванию, а
+// $gt/$lt с null (и null с $gt/$lt по строке) ничего не находят, поэтому null разбирается отдельно
+fn keyset_after(sort: &[(String, i32)], values: &[Bson]) -> Document {
+    let mut or: Vec<Document> = Vec::new();
+    for i in 0..sort.len() {
+        let mut cond = Document::new();
+        for j in 0..i { cond.insert(sort[j].0.clone(), values[j].clone()); }
+        let field = sort[i].0.clone();
+        match (&values[i], sort[i].1 < 0) {
+            // после null по убыванию ничего нет
+            (Bson::Null, true) => continue,
+            (Bson::Null, false) => { cond.insert(field, doc!{"$ne": null}); }
+            (v, false) => { cond.insert(field, doc!{"$gt": v.clone()}); }
+            (v, true) => { cond.insert("$or", vec![doc!{&field: {"$lt": v.clone()}}, doc!{&field: null}]); }
+        }
+        or.push(cond);
+    }
+    doc!{"$or": or}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering;
+
+    // то, как Mongo сравнивает значения одного поля: null и отсутствие — меньше всего, разные типы
+    // для $gt/$lt не сравниваются
+    fn cmp(a: Option<&Bson>, b: Option<&Bson>) -> Option<Ordering> {
+        match (a.filter(|v| **v != Bson::Null), b.filter(|v| **v != Bson::Null)) {
+            (None, None) => Some(Ordering::Equal),
+            (None, Some(_)) => Some(Ordering::Less),
+            (Some(_), None) => Some(Ordering::Greater),
+            (Some(Bson::String(x)), Some(Bson::String(y))) => Some(x.cmp(y)),
+            (Some(Bson::Int32(x)), Some(Bson::Int32(y))) => Some(x.cmp(y)),
+            _ => None,
+        }
+    }
+
+    fn matches(d: &Document, filter: &Document) -> bool {
+        filter.iter().all(|(k, v)| match (k.as_str(), v) {
+            ("$or", Bson::Array(a)) => a.iter().any(|f| matches(d, f.as_document().unwrap())),
+            ("$and", Bson::Array(a)) => a.iter().all(|f| matches(d, f.as_document().unwrap())),
+            (_, Bson::Document(ops)) => ops.iter().all(|(op, x)| {
+                let (have, x) = (d.get(k).filter(|v| **v != Bson::Null), Some(x).filter(|v| **v != Bson::Null));
+                match op.as_str() {
+                    "$ne" => cmp(have, x) != Some(Ordering::Equal),
+                    // type bracketing: с null и с другим типом — ложь
+                    "$gt" => have.is_some() && x.is_some() && cmp(have, x) == Some(Ordering::Greater),
+                    "$lt" => have.is_some() && x.is_some() && cmp(have, x) == Some(Ordering::Less),
+                    _ => unreachable!("{op}"),
+                }
+            }),
+            _ => cmp(d.get(k), Some(v)) == Some(Ordering::Equal),
+        })
+    }
+
+    fn page_through(rows: &[Document], sort: &[(String, i32)], limit: usize) -> Vec<i32> {
+        let mut sorted = rows.to_vec();
+        sorted.sort_by(|a, b| sort.iter().map(|(f, dir)| {
+            let o = cmp(a.get(f), b.get(f)).unwrap();
+            if *dir < 0 { o.reverse() } else { o }
+        }).find(|o| o.is_ne()).unwrap_or(Ordering::Equal));
+        let mut seen = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let after = cursor.as_deref().map(|c| keyset_after(sort, &decode_cursor(c, sort).unwrap()));
+            let mut page: Vec<&Document> = sorted.iter().filter(|d| after.as_ref().is_none_or(|f| matches(d, f))).take(limit + 1).collect();
+            let more = page.len() > limit;
+            page.truncate(limit);
+            seen.extend(page.iter().map(|d| d.get_i32("_id").unwrap()));
+            if !more { break; }
+            cursor = encode_cursor(*page.last().unwrap(), sort);
+            assert!(seen.len() <= rows.len(), "cursor loops");
+        }
+        assert_eq!(seen, sorted.iter().map(|d| d.get_i32("_id").unwrap()).collect::<Vec<_>>());
+        seen
+    }
+
+    #[test]
+    fn cursor_pages_through_missing_and_null_values() {
+        let brands = [Some("b"), None, Some("a"), None, Some("c"), Some("a"), None, Some("b"), None];
+        let rows: Vec<Document> = brands.iter().enumerate().map(|(i, b)| {
+            let mut d = doc!{"_id": i as i32};
+            match b { Some(b) => { d.insert("brand", *b); } None if i % 2 == 0 => { d.insert("brand", Bson::Null); } None => {} }
+            d
+        }).collect();
+        for dir in [1, -1] {
+            let sort = vec![("brand".to_string(), dir), ("_id".to_string(), dir)];
+            for limit in 1..=4 { assert_eq!(page_through(&rows, &sort, limit).len(), rows.len()); }
+        }
+        // смешанные направления
+        let sort = vec![("brand".to_string(), -1), ("_id".to_string(), 1)];
+        assert_eq!(page_through(&rows, &sort, 2).len(), rows.len());
+    }
+}
@@ -2,7 +2,10 @@ pub mod health;
 pub mod products;
 pub mod categories;
 pub mod stores;
+pub mod chains;
+pub mod regions;
 pub mod insights;
+pub mod heatmap;
 pub mod uploads;
 pub mod activities;
 pub mod auth;
@@ -13,4 +16,14 @@ pub mod events;
 pub mod dev;
 pub mod operations;
 pub mod export;
+pub mod imports;
+pub mod duplicates;
+pub mod suggest;
+pub mod listing;
+pub mod references;
+pub mod trash;
+pub mod audit;
+pub mod integrity;
+pub mod prices;
+pub mod price_stats;
 // telegram status endpoint is in module telegram
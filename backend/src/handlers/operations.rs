@@ -4,13 +4,13 @@ use bson::{doc, oid::ObjectId};
 use futures::stream::StreamExt;
 use tracing::error;
 
-use crate::{state::AppState, models::{Operation, OperationItem}};
+use crate::{state::AppState, models::{Operation, OperationItem, Unit}, money::{self, Money}};
 
 #[derive(serde::Deserialize)]
 pub struct CreateOperationBody {
     pub date: String,
     pub seller: String,
-    pub amount: f64,
+    pub amount: Money,
     pub items: Vec<OperationItem>,
     pub qr: Option<String>,
     pub uploaded_by: Option<String>,
@@ -99,6 +99,7 @@ pub async fn update_status(State(state): State<AppState>, Path(id): Path<String>
         let raw_col = state.db.collection::<bson::Document>("operations");
         let raw_doc = raw_col.find_one(doc!{"_id": oid}, None).await.ok().flatten();
 
+        let mut posted: Vec<ObjectId> = Vec::new();
         for (idx, it) in existing.items.iter().enumerate() {
             // Determine product_id: prefer typed, otherwise from raw document
             let mut prod_opt: Option<ObjectId> = it.product_id.clone();
@@ -119,21 +120,29 @@ pub async fn update_status(State(state): State<AppState>, Path(id): Path<String>
             }
 
             let Some(product_oid) = prod_opt else { continue };
-            let price = it.price;
+            let product = state.products.find_one(doc!{"_id": &product_oid}, None).await.ok().flatten();
+            // Weighed line (fractional quantity = weight): price is per kg/l. Weighed products keep it as is,
+            // packaged ones get the pack price back.
+            let price = match product.as_ref().and_then(|p| p.pack_size.filter(|_| it.is_weighed() && matches!(p.unit, Some(Unit::Kg | Unit::L)))) {
+                Some(size) => it.price.times(size),
+                None => it.price,
+            };
             // Upsert store item price
             let filter = doc!{"store_id": &store_oid, "product_id": &product_oid};
             let update = doc!{"$set": {"price": price}, "$setOnInsert": {"store_id": &store_oid, "product_id": &product_oid}};
             let upsert_opts = mongodb::options::UpdateOptions::builder().upsert(true).build();
             let _ = state.store_items.update_one(filter, update, upsert_opts).await;
+            posted.push(product_oid);
 
             // Fetch names for activity
-            let product_name = match state.products.find_one(doc!{"_id": &product_oid}, None).await { Ok(opt)=> opt.map(|p| p.title), Err(_)=> None };
+            let product_name = product.map(|p| p.title);
             let store_name = match state.stores.find_one(doc!{"_id": &store_oid}, None).await { Ok(opt)=> opt.map(|s| s.name), Err(_)=> None };
 
             // Record activity with receipt timestamp
             let act = crate::models::StoreActivity { id: None, store_id: store_oid.clone(), product_id: Some(product_oid.clone()), kind: "price_set".into(), ts_ms, price: Some(price), product_name, store_name };
             let _ = state.store_activities.insert_one(act, None).await;
         }
+        crate::handlers::price_stats::price_changed(&state, posted).await;
     }
 
     match col.update_one(doc!{"_id": oid}, doc!{"$set": {"status": body.status}}, None).await {
@@ -161,7 +170,7 @@ pub async fn update_operation(State(state): State<AppState>, Path(id): Path<Stri
             set.insert("store_id", bson::Bson::Null);
         }
     }
-    if let Some(items) = body.items { set.insert("items", bson::to_bson(&items).unwrap_or(bson::Bson::Null)); }
+    if let Some(items) = body.items { set.insert("items", money::to_bson_raw(&items).unwrap_or(bson::Bson::Null)); }
     if set.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
     let col = state.db.collection::<Operation>("operations");
     match col.update_one(doc!{"_id": oid}, doc!{"$set": set}, None).await {
@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId, Document};
+use futures::stream::StreamExt;
+use mongodb::options::{AggregateOptions, FindOneOptions, ReplaceOptions};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::handlers::trash::Hidden;
+use crate::models::{PriceChange, ProductPriceStats};
+use crate::money::{from_document_raw, Money};
+use crate::state::AppState;
+
+// Сводка цен товара по магазинам в product_price_stats (_id — id товара): min/median/avg/max, число
+// магазинов, самый дешёвый магазин и последнее изменение цены. Документ есть только у товаров с
+// ценами (магазины в корзине не учитываются). Обработчики, меняющие цену одного товара (позиции
+// магазина, проведение чека, пересчёт и объединение), пересчитывают его сводку — refresh; массовые
+// изменения (импорт, тестовые данные, удаление и корзина магазинов, починка базы) — rebuild_all,
+// он же POST /prices/stats/rebuild.
+
+// записи истории, меняющие цену
+const CHANGE_KINDS: [&str; 4] = ["item_added", "price_updated", "price_set", "item_removed"];
+
+pub fn collection(state: &AppState) -> mongodb::Collection<ProductPriceStats> { state.db.collection::<ProductPriceStats>("product_price_stats") }
+
+// None — у товара нет цен
+fn summarize(product_id: ObjectId, prices: &mut [(ObjectId, Money)], last_change: Option<PriceChange>, now_ms: i64) -> Option<ProductPriceStats> {
+    prices.sort_by_key(|(store_id, price)| (*price, *store_id));
+    let sorted: Vec<Money> = prices.iter().map(|(_, p)| *p).collect();
+    let (&(cheapest_store_id, min), &(_, max)) = (prices.first()?, prices.last()?);
+    Some(ProductPriceStats {
+        product_id,
+        store_count: prices.len() as i64,
+        min,
+        median: Money::percentile(&sorted, 0.5)?,
+        avg: Money::avg(&sorted)?,
+        max,
+        cheapest_store_id,
+        last_change,
+        updated_ts_ms: now_ms,
+    })
+}
+
+// пересчитать сводку одного товара
+pub async fn refresh(state: &AppState, product_id: ObjectId) -> mongodb::error::Result<()> {
+    let hidden = Hidden::load(state).await?;
+    let mut prices: Vec<(ObjectId, Money)> = Vec::new();
+    let mut cursor = state.store_items.find(hidden.items(doc!{"product_id": product_id}), None).await?;
+    while let Some(it) = cursor.next().await { let it = it?; prices.push((it.store_id, it.price)); }
+    let opts = FindOneOptions::builder().sort(doc!{"ts_ms": -1, "_id": -1}).build();
+    let last = state.store_activities.find_one(doc!{"product_id": product_id, "kind": {"$in": CHANGE_KINDS.to_vec()}}, opts).await?;
+    let last_change = last.map(|a| PriceChange { store_id: a.store_id, kind: a.kind, price: a.price, ts_ms: a.ts_ms });
+    let filter = doc!{"_id": product_id};
+    match summarize(product_id, &mut prices, last_change, chrono::Utc::now().timestamp_millis()) {
+        Some(stats) => { collection(state).replace_one(filter, stats, ReplaceOptions::builder().upsert(true).build()).await?; }
+        None => { collection(state).delete_one(filter, None).await?; }
+    }
+    Ok(())
+}
+
+// для обработчиков: цена уже записана, поэтому ошибка пересчёта только в лог
+pub async fn price_changed(state: &AppState, product_ids: impl IntoIterator<Item = ObjectId>) {
+    for id in product_ids.into_iter().collect::<HashSet<_>>() {
+        if let Err(e) = refresh(state, id).await { error!(?e, product_id = %id, "refresh price stats failed"); }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct LastChangeRow {
+    #[serde(rename = "_id")]
+    product_id: ObjectId,
+    store_id: ObjectId,
+    kind: String,
+    price: Option<Money>,
+    ts_ms: i64,
+}
+
+// пересобрать коллекцию целиком; возвращает число товаров с ценами
+pub async fn rebuild_all(state: &AppState) -> mongodb::error::Result<usize> {
+    let hidden = Hidden::load(state).await?;
+    let mut by_product: HashMap<ObjectId, Vec<(ObjectId, Money)>> = HashMap::new();
+    let mut cursor = state.store_items.find(hidden.items(doc!{}), None).await?;
+    while let Some(it) = cursor.next().await { let it = it?; by_product.entry(it.product_id).or_default().push((it.store_id, it.price)); }
+
+    // последнее изменение цены по каждому товару
+    let pipeline = vec![
+        doc!{"$match": {"product_id": {"$ne": null}, "kind": {"$in": CHANGE_KINDS.to_vec()}}},
+        doc!{"$sort": {"ts_ms": -1, "_id": -1}},
+        doc!{"$group": {"_id": "$product_id", "store_id": {"$first": "$store_id"}, "kind": {"$first": "$kind"}, "price": {"$first": "$price"}, "ts_ms": {"$first": "$ts_ms"}}},
+    ];
+    let mut last: HashMap<ObjectId, PriceChange> = HashMap::new();
+    let mut cursor = state.store_activities.aggregate(pipeline, AggregateOptions::builder().allow_disk_use(true).build()).await?;
+    while let Some(d) = cursor.next().await {
+        match from_document_raw::<LastChangeRow>(&d?) {
+            Ok(r) => { last.insert(r.product_id, PriceChange { store_id: r.store_id, kind: r.kind, price: r.price, ts_ms: r.ts_ms }); }
+            Err(e) => error!(?e, "decode last price change failed"),
+        }
+    }
+
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let col = collection(state);
+    let ids: Vec<ObjectId> = by_product.keys().copied().collect();
+    for (product_id, mut prices) in by_product {
+        let Some(stats) = summarize(product_id, &mut prices, last.remove(&product_id), now_ms) else { continue };
+        col.replace_one(doc!{"_id": product_id}, stats, ReplaceOptions::builder().upsert(true).build()).await?;
+    }
+    col.delete_many(doc!{"_id": {"$nin": &ids}}, None).await?;
+    // вызывается и из фоновых задач, вне middleware кэша
+    state.cache.invalidate(&["product_price_stats"]);
+    info!(products = ids.len(), "product price stats rebuilt");
+    Ok(ids.len())
+}
+
+// для массовых изменений: ошибка только в лог, сводку можно пересобрать командой
+pub async fn prices_changed(state: &AppState) {
+    if let Err(e) = rebuild_all(state).await { error!(?e, "rebuild price stats failed"); }
+}
+
+// сводки по id товаров одним запросом (для списков)
+pub async fn load(state: &AppState, product_ids: &[ObjectId]) -> mongodb::error::Result<HashMap<ObjectId, ProductPriceStats>> {
+    let mut out: HashMap<ObjectId, ProductPriceStats> = HashMap::new();
+    if product_ids.is_empty() { return Ok(out); }
+    let mut cursor = collection(state).find(doc!{"_id": {"$in": product_ids}}, None).await?;
+    while let Some(s) = cursor.next().await { let s = s?; out.insert(s.product_id, s); }
+    Ok(out)
+}
+
+// сводка для ответа API: без _id, он совпадает с id товара
+pub fn summary_json(stats: &ProductPriceStats) -> serde_json::Value {
+    let mut v = serde_json::to_value(stats).unwrap_or_default();
+    if let Some(obj) = v.as_object_mut() { obj.remove("_id"); }
+    v
+}
+
+// при первом запуске с этой коллекцией — заполнить её из store_items
+pub fn spawn_backfill(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let empty = match collection(&state).find_one(Document::new(), None).await { Ok(d) => d.is_none(), Err(e) => { error!(?e, "check price stats failed"); return; } };
+        if empty { prices_changed(&state).await; }
+    })
+}
+
+// POST /prices/stats/rebuild — пересобрать сводки цен всех товаров
+pub async fn rebuild_price_stats(State(state): State<AppState>) -> impl IntoResponse {
+    match rebuild_all(&state).await {
+        Ok(products) => (StatusCode::OK, Json(serde_json::json!({"products": products}))).into_response(),
+        Err(e) => { error!(?e, "rebuild price stats failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
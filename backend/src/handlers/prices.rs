@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use axum::{extract::{Query, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use futures::stream::StreamExt;
+use mongodb::options::{AggregateOptions, UpdateOptions};
+use tracing::{error, info};
+
+use crate::money::Money;
+use crate::state::AppState;
+
+// Текущая цена (store_items) и история (store_activities) пишутся раздельно — в add_store_product,
+// update_store_product и update_status (цены из чеков с датой чека) — и могут разойтись.
+// Пересчёт выводит текущую цену каждой пары магазин/товар из последней доверенной записи истории:
+// item_added, price_updated или price_set с положительной ценой и не из будущего; если последняя
+// запись — item_removed, позиции быть не должно. Пары без доверенной истории не трогаются.
+// GET /prices/rebuild показывает расхождения, POST /prices/rebuild их применяет.
+
+const PRICED_KINDS: [&str; 3] = ["item_added", "price_updated", "price_set"];
+
+#[derive(serde::Deserialize)]
+pub struct RebuildQuery {
+    pub store_id: Option<String>,
+    pub product_id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Change {
+    pub store_id: ObjectId,
+    pub product_id: ObjectId,
+    pub action: &'static str, // add | update | remove
+    pub current: Option<Money>,
+    pub derived: Option<Money>,
+    // запись истории, из которой выведена цена
+    pub activity_id: ObjectId,
+    pub kind: String,
+    pub ts_ms: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Report {
+    pub applied: bool,
+    // пары с доверенной историей
+    pub checked: usize,
+    // позиции store_items без доверенной истории — оставлены как есть
+    pub untracked: usize,
+    pub changes: Vec<Change>,
+}
+
+struct Latest {
+    kind: String,
+    price: Option<Money>,
+    ts_ms: i64,
+    activity_id: ObjectId,
+}
+
+// Пайплайн по store_activities: последняя доверенная запись не позже upto_ms по каждой паре
+// магазин/товар из scope — {_id: {store_id, product_id}, kind, price, ts_ms, activity_id}
+fn latest_pipeline(scope: Document, upto_ms: i64) -> Vec<Document> {
+    let trusted = doc!{
+        "product_id": {"$ne": null},
+        "ts_ms": {"$lte": upto_ms},
+        "$or": [{"kind": "item_removed"}, {"kind": {"$in": PRICED_KINDS.to_vec()}, "price": {"$gt": 0}}],
+    };
+    vec![
+        doc!{"$match": if scope.is_empty() { trusted } else { doc!{"$and": [scope, trusted]} }},
+        doc!{"$sort": {"ts_ms": -1, "_id": -1}},
+        doc!{"$group": {
+            "_id": {"store_id": "$store_id", "product_id": "$product_id"},
+            "kind": {"$first": "$kind"},
+            "price": {"$first": "$price"},
+            "ts_ms": {"$first": "$ts_ms"},
+            "activity_id": {"$first": "$_id"},
+        }},
+    ]
+}
+
+// Позиции store_items, какими они были на момент as_of (документы вида StoreItem): пары, у которых
+// последняя запись к этому времени — цена, а не item_removed. _id — id этой записи истории.
+pub fn items_as_of_pipeline(scope: Document, as_of: i64) -> Vec<Document> {
+    let mut pipeline = latest_pipeline(scope, as_of);
+    pipeline.push(doc!{"$match": {"kind": {"$ne": "item_removed"}}});
+    pipeline.push(doc!{"$project": {"_id": "$activity_id", "store_id": "$_id.store_id", "product_id": "$_id.product_id", "price": 1}});
+    pipeline
+}
+
+#[derive(serde::Deserialize)]
+pub struct AsOfQuery {
+    // мс; цены на этот момент из истории вместо текущих store_items
+    pub as_of: Option<i64>,
+}
+
+// последняя доверенная запись истории по каждой паре магазин/товар
+async fn latest_entries(state: &AppState, scope: &Document) -> mongodb::error::Result<HashMap<(ObjectId, ObjectId), Latest>> {
+    let pipeline = latest_pipeline(scope.clone(), chrono::Utc::now().timestamp_millis());
+    let opts = AggregateOptions::builder().allow_disk_use(true).build();
+    let mut cursor = state.store_activities.aggregate(pipeline, opts).await?;
+    let mut out: HashMap<(ObjectId, ObjectId), Latest> = HashMap::new();
+    while let Some(d) = cursor.next().await {
+        match latest_from_doc(&d?) {
+            Ok((key, latest)) => { out.insert(key, latest); }
+            Err(e) => error!(?e, "decode latest price entry failed"),
+        }
+    }
+    Ok(out)
+}
+
+#[derive(serde::Deserialize)]
+struct LatestKey {
+    store_id: ObjectId,
+    product_id: ObjectId,
+}
+
+#[derive(serde::Deserialize)]
+struct LatestRow {
+    #[serde(rename = "_id")]
+    key: LatestKey,
+    kind: String,
+    // Int32 или Int64 в копейках — через Money, как и при чтении store_items
+    price: Option<Money>,
+    #[serde(default)]
+    ts_ms: i64,
+    activity_id: ObjectId,
+}
+
+fn latest_from_doc(d: &Document) -> bson::de::Result<((ObjectId, ObjectId), Latest)> {
+    let row: LatestRow = crate::money::from_document_raw(d)?;
+    let price = if row.kind == "item_removed" { None } else { row.price };
+    Ok(((row.key.store_id, row.key.product_id), Latest { kind: row.kind, price, ts_ms: row.ts_ms, activity_id: row.activity_id }))
+}
+
+pub async fn rebuild(state: &AppState, scope: Document, apply: bool) -> mongodb::error::Result<Report> {
+    let latest = latest_entries(state, &scope).await?;
+    let mut current: HashMap<(ObjectId, ObjectId), Money> = HashMap::new();
+    let mut cursor = state.store_items.find(scope, None).await?;
+    while let Some(it) = cursor.next().await { let it = it?; current.insert((it.store_id, it.product_id), it.price); }
+    let untracked = current.keys().filter(|k| !latest.contains_key(k)).count();
+
+    let mut changes: Vec<Change> = Vec::new();
+    for (&(store_id, product_id), l) in latest.iter() {
+        let now = current.get(&(store_id, product_id)).copied();
+        let action = match (now, l.price) {
+            (Some(_), None) => "remove",
+            (None, Some(_)) => "add",
+            (Some(a), Some(b)) if a != b => "update",
+            _ => continue,
+        };
+        changes.push(Change { store_id, product_id, action, current: now, derived: l.price, activity_id: l.activity_id, kind: l.kind.clone(), ts_ms: l.ts_ms });
+    }
+    changes.sort_by_key(|c| (c.store_id, c.product_id));
+
+    if apply {
+        for c in changes.iter() {
+            let filter = doc!{"store_id": c.store_id, "product_id": c.product_id};
+            match c.derived {
+                Some(price) => {
+                    let update = doc!{"$set": {"price": Bson::from(price)}, "$setOnInsert": {"store_id": c.store_id, "product_id": c.product_id}};
+                    state.store_items.update_one(filter, update, UpdateOptions::builder().upsert(true).build()).await?;
+                }
+                None => { state.store_items.delete_many(filter, None).await?; }
+            }
+        }
+        if !changes.is_empty() { info!(changed = changes.len(), "store prices rebuilt from history"); }
+        crate::handlers::price_stats::price_changed(state, changes.iter().map(|c| c.product_id)).await;
+    }
+    Ok(Report { applied: apply, checked: latest.len(), untracked, changes })
+}
+
+fn bad_request(code: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": code}))).into_response()
+}
+
+async fn respond(state: &AppState, q: RebuildQuery, apply: bool) -> Response {
+    let mut scope = Document::new();
+    for (field, raw, code) in [("store_id", &q.store_id, "invalid_store_id"), ("product_id", &q.product_id, "invalid_product_id")] {
+        let Some(raw) = raw.as_deref().filter(|s| !s.is_empty()) else { continue };
+        match ObjectId::from_str(raw) { Ok(oid) => { scope.insert(field, oid); } Err(_) => return bad_request(code) }
+    }
+    match rebuild(state, scope, apply).await {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(e) => { error!(?e, "rebuild prices failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+// GET /prices/rebuild?store_id=&product_id= — расхождения без изменений
+pub async fn preview_rebuild(State(state): State<AppState>, Query(q): Query<RebuildQuery>) -> impl IntoResponse {
+    respond(&state, q, false).await
+}
+
+// POST /prices/rebuild?store_id=&product_id= — привести store_items к истории
+pub async fn apply_rebuild(State(state): State<AppState>, Query(q): Query<RebuildQuery>) -> impl IntoResponse {
+    respond(&state, q, true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: &str, price: Bson) -> Document {
+        doc!{"_id": {"store_id": ObjectId::new(), "product_id": ObjectId::new()}, "kind": kind, "price": price, "ts_ms": 1_000i64, "activity_id": ObjectId::new()}
+    }
+
+    #[test]
+    fn latest_price_in_any_integer_type() {
+        // небольшие цены из JSON bson хранит как Int32 — это всё равно цена, а не удаление
+        for price in [Bson::Int32(8990), Bson::Int64(8990)] {
+            let (_, latest) = latest_from_doc(&entry("price_updated", price)).unwrap();
+            assert_eq!(latest.price, Some(Money::from_minor(8990)));
+        }
+        let (_, latest) = latest_from_doc(&entry("price_set", Bson::Double(89.9))).unwrap();
+        assert_eq!(latest.price, Some(Money::from_minor(8990)));
+        let (_, removed) = latest_from_doc(&entry("item_removed", Bson::Int32(8990))).unwrap();
+        assert_eq!(removed.price, None);
+        let (_, no_price) = latest_from_doc(&entry("item_removed", Bson::Null)).unwrap();
+        assert_eq!((no_price.kind.as_str(), no_price.ts_ms), ("item_removed", 1_000));
+    }
+}
@@ -1,41 +1,128 @@
 use std::str::FromStr;
 
-use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
 use bson::{doc, oid::ObjectId, Bson};
 use futures::stream::StreamExt;
 use tracing::error;
 
+use crate::handlers::auth::Principal;
+use crate::handlers::listing::{FilterKind, ListQuery, ListSpec};
+use crate::handlers::price_stats;
+use crate::handlers::references::{delete_entity, DeleteQuery, Entity};
+use crate::handlers::trash::{self, live};
 use crate::models::{Product, ProductCreate, ProductUpdate};
+use crate::money::Quantity;
 use crate::state::AppState;
 
-pub async fn list_products(State(state): State<AppState>) -> impl IntoResponse {
-    let mut cursor = match state.products.find(None, None).await {
+pub const PRODUCTS_LIST: ListSpec = ListSpec {
+    filters: &[
+        ("title", "title", FilterKind::Contains),
+        ("brand", "brand", FilterKind::Exact),
+        ("unit", "unit", FilterKind::Exact),
+        ("barcode", "barcodes", FilterKind::Exact),
+        ("category_id", "category_ids", FilterKind::ObjectId),
+    ],
+    sortable: &["title", "brand"],
+    default_sort: "title",
+    legacy_limit: None,
+};
+
+// у каждого товара — price_summary из product_price_stats (null, если цен нет), одним запросом на страницу
+pub async fn list_products(State(state): State<AppState>, q: ListQuery) -> impl IntoResponse {
+    let page = match q.fetch(&state.products, live(doc! {}), &PRODUCTS_LIST).await { Ok(p)=>p, Err(e)=> return e.into_response() };
+    let ids: Vec<ObjectId> = page.items.iter().filter_map(|p| p.id).collect();
+    let summaries = match price_stats::load(&state, &ids).await { Ok(m)=>m, Err(e)=> { error!(?e, "load price stats failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    page.map(|p| {
+        let summary = p.id.and_then(|id| summaries.get(&id)).map(price_stats::summary_json);
+        let mut v = serde_json::to_value(&p).unwrap_or_default();
+        v["price_summary"] = serde_json::json!(summary);
+        v
+    }).into_response()
+}
+
+#[derive(serde::Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    // через запятую; товар подходит, если есть хотя бы в одной
+    pub category_id: Option<String>,
+    // вместе с подкатегориями
+    #[serde(default)]
+    pub include_descendants: bool,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_search_limit")]
+    pub limit: usize,
+}
+
+fn default_search_limit() -> usize { 20 }
+
+// GET /products/search?q=&category_id=&offset=&limit= — ranked by the in-memory index, documents from Mongo
+pub async fn search_products(State(state): State<AppState>, Query(q): Query<SearchQuery>) -> impl IntoResponse {
+    let limit = q.limit.clamp(1, 100);
+    let categories = match q.category_id.as_deref().filter(|s| !s.trim().is_empty()) {
+        Some(list) => {
+            let mut set = std::collections::HashSet::new();
+            for s in list.split(',') {
+                let Ok(oid) = ObjectId::from_str(s.trim()) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_category_id"}))).into_response() };
+                set.insert(oid);
+            }
+            if q.include_descendants {
+                match crate::handlers::categories::CategoryGraph::load(&state).await {
+                    Ok(g) => { let roots: Vec<ObjectId> = set.iter().copied().collect(); for c in roots { set.extend(g.descendants(c)); } }
+                    Err(e) => {
+                        error!(?e, "load categories failed");
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                }
+            }
+            Some(set)
+        }
+        None => None,
+    };
+    let hits = state.search.search(&q.q, categories.as_ref());
+    let total = hits.len();
+    let page: Vec<&crate::search::Hit> = hits.iter().skip(q.offset).take(limit).collect();
+    let ids: Vec<ObjectId> = page.iter().map(|h| h.id).collect();
+    let mut found: std::collections::HashMap<ObjectId, Product> = std::collections::HashMap::new();
+    let mut cursor = match state.products.find(live(doc! {"_id": {"$in": &ids}}), None).await {
         Ok(c) => c,
         Err(e) => {
             error!(?e, "failed to query products");
             return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
     };
-
-    let mut items: Vec<Product> = Vec::new();
     while let Some(res) = cursor.next().await {
         match res {
-            Ok(doc) => items.push(doc),
+            Ok(p) => { if let Some(id) = p.id { found.insert(id, p); } }
             Err(e) => {
                 error!(?e, "cursor error");
                 return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
         }
     }
-    (StatusCode::OK, Json(items)).into_response()
+    let items: Vec<serde_json::Value> = page.iter().filter_map(|h| {
+        let mut v = serde_json::to_value(found.remove(&h.id)?).ok()?;
+        v["score"] = serde_json::json!((h.score * 1000.0).round() / 1000.0);
+        Some(v)
+    }).collect();
+    (StatusCode::OK, Json(serde_json::json!({"items": items, "total": total, "offset": q.offset, "limit": limit}))).into_response()
 }
 
 pub async fn get_product(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
     let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    let filter = doc! {"_id": oid};
+    let filter = live(doc! {"_id": oid});
     match state.products.find_one(filter, None).await {
         Ok(Some(doc)) => (StatusCode::OK, Json(doc)).into_response(),
-        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        // a merged product resolves to the one it was merged into
+        Ok(None) => match crate::handlers::duplicates::resolve_redirect(&state, oid).await {
+            Ok(Some(doc)) => (StatusCode::OK, Json(doc)).into_response(),
+            Ok(None) => StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                error!(?e, "resolve product redirect failed");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        },
         Err(e) => {
             error!(?e, "find_one failed");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -43,7 +130,57 @@ pub async fn get_product(State(state): State<AppState>, Path(id): Path<String>)
     }
 }
 
+// EAN-8 / UPC-A / EAN-13 / GTIN-14 with a GS1 check digit. UPC-A and GTIN-14 with a leading
+// zero are stored as EAN-13 so the same product can't be registered under two spellings.
+pub fn normalize_barcode(raw: &str) -> Option<String> {
+    let code: String = raw.chars().filter(|c| !c.is_whitespace()).collect();
+    if !code.chars().all(|c| c.is_ascii_digit()) || ![8, 12, 13, 14].contains(&code.len()) { return None; }
+    let digits: Vec<u32> = code.chars().filter_map(|c| c.to_digit(10)).collect();
+    let (body, check) = digits.split_at(digits.len() - 1);
+    let sum: u32 = body.iter().rev().enumerate().map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d }).sum();
+    if (10 - sum % 10) % 10 != check[0] { return None; }
+    Some(match code.len() {
+        12 => format!("0{}", code),
+        14 if code.starts_with('0') => code[1..].to_string(),
+        _ => code,
+    })
+}
+
+// Err carries the first invalid code as sent by the client
+fn normalize_barcodes(list: &[String]) -> Result<Vec<String>, String> {
+    let mut out: Vec<String> = Vec::new();
+    for raw in list {
+        let Some(code) = normalize_barcode(raw) else { return Err(raw.clone()) };
+        if !out.contains(&code) { out.push(code); }
+    }
+    Ok(out)
+}
+
+fn invalid_barcode(code: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_barcode", "code": code}))).into_response()
+}
+
+// unique index on barcodes
+fn barcode_taken(e: &mongodb::error::Error) -> bool {
+    let msg = e.to_string();
+    msg.contains("E11000") || msg.contains("duplicate key")
+}
+
+pub async fn get_product_by_barcode(State(state): State<AppState>, Path(code): Path<String>) -> impl IntoResponse {
+    let Some(code) = normalize_barcode(&code) else { return invalid_barcode(&code); };
+    match state.products.find_one(live(doc! {"barcodes": &code}), None).await {
+        Ok(Some(doc)) => (StatusCode::OK, Json(doc)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!(?e, "find product by barcode failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 pub async fn create_product(State(state): State<AppState>, Json(payload): Json<ProductCreate>) -> impl IntoResponse {
+    let barcodes = match normalize_barcodes(&payload.barcodes) { Ok(b) => b, Err(code) => return invalid_barcode(&code) };
+    if payload.pack_size.is_some_and(|s| s <= Quantity::ZERO) { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_pack_size"}))).into_response(); }
     // map category ids
     let mut cat_ids: Vec<ObjectId> = Vec::new();
     for s in payload.category_ids.iter() {
@@ -55,6 +192,15 @@ pub async fn create_product(State(state): State<AppState>, Json(payload): Json<P
         desc: payload.desc,
         image_url: payload.image_url,
         category_ids: cat_ids,
+        unit: payload.unit,
+        pack_size: payload.pack_size,
+        barcodes,
+        brand: payload.brand.filter(|b| !b.trim().is_empty()),
+        category_hints: vec![],
+        imported_fields: vec![],
+        aliases: vec![],
+        deleted_at: None,
+        deleted_by: None,
     };
 
     match state.products.insert_one(product, None).await {
@@ -65,10 +211,15 @@ pub async fn create_product(State(state): State<AppState>, Json(payload): Json<P
             };
             let filter = doc! {"_id": id};
             match state.products.find_one(filter, None).await {
-                Ok(Some(created)) => (StatusCode::CREATED, Json(created)).into_response(),
+                Ok(Some(created)) => {
+                    state.search.upsert(&created);
+                    state.suggest.upsert_product(&created);
+                    (StatusCode::CREATED, Json(created)).into_response()
+                }
                 _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
             }
         }
+        Err(e) if barcode_taken(&e) => (StatusCode::CONFLICT, Json(serde_json::json!({"error": "barcode_taken"}))).into_response(),
         Err(e) => {
             error!(?e, "insert failed");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -87,13 +238,45 @@ pub async fn update_product(State(state): State<AppState>, Path(id): Path<String
         for s in cats.iter() { if let Ok(oid) = ObjectId::from_str(s) { list.push(oid); } }
         set.insert("category_ids", list);
     }
-    if set.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
+    if let Some(u) = patch.unit { set.insert("unit", bson::to_bson(&u).unwrap_or(Bson::Null)); }
+    let mut unset = doc! {};
+    match patch.pack_size {
+        Some(s) if s < Quantity::ZERO => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_pack_size"}))).into_response(),
+        Some(s) if s == Quantity::ZERO => { unset.insert("pack_size", ""); }
+        Some(s) => { set.insert("pack_size", s); }
+        None => {}
+    }
+    if let Some(list) = patch.barcodes {
+        match normalize_barcodes(&list) {
+            Ok(codes) if codes.is_empty() => { unset.insert("barcodes", ""); }
+            Ok(codes) => { set.insert("barcodes", codes); }
+            Err(code) => return invalid_barcode(&code),
+        }
+    }
+    if let Some(list) = patch.aliases {
+        let list: Vec<String> = list.into_iter().map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect();
+        if list.is_empty() { unset.insert("aliases", ""); } else { set.insert("aliases", list); }
+    }
+    if let Some(b) = patch.brand {
+        if b.trim().is_empty() { unset.insert("brand", ""); } else { set.insert("brand", b); }
+    }
+    if set.is_empty() && unset.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
 
-    let filter = doc! {"_id": oid};
-    let update = doc! {"$set": set};
-    match state.products.find_one_and_update(filter.clone(), update, None).await {
-        Ok(Some(updated)) => (StatusCode::OK, Json(updated)).into_response(),
+    // manually edited fields are no longer owned by imports
+    let edited: Vec<String> = set.keys().chain(unset.keys()).cloned().collect();
+    let filter = live(doc! {"_id": oid});
+    let mut update = doc! {"$pullAll": {"imported_fields": edited}};
+    if !set.is_empty() { update.insert("$set", set); }
+    if !unset.is_empty() { update.insert("$unset", unset); }
+    let opts = mongodb::options::FindOneAndUpdateOptions::builder().return_document(mongodb::options::ReturnDocument::After).build();
+    match state.products.find_one_and_update(filter.clone(), update, opts).await {
+        Ok(Some(updated)) => {
+            state.search.upsert(&updated);
+            state.suggest.upsert_product(&updated);
+            (StatusCode::OK, Json(updated)).into_response()
+        }
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) if barcode_taken(&e) => (StatusCode::CONFLICT, Json(serde_json::json!({"error": "barcode_taken"}))).into_response(),
         Err(e) => {
             error!(?e, "update failed");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -101,25 +284,25 @@ pub async fn update_product(State(state): State<AppState>, Path(id): Path<String
     }
 }
 
-pub async fn delete_product(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
-    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    let filter = doc! {"_id": oid};
-    match state.products.delete_one(filter, None).await {
-        Ok(res) if res.deleted_count == 1 => StatusCode::NO_CONTENT.into_response(),
-        Ok(_) => StatusCode::NOT_FOUND.into_response(),
-        Err(e) => {
-            error!(?e, "delete failed");
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
-    }
+// moves to the trash; with ?policy=restrict|cascade|reassign&reassign_to=<id> deletes right away, see handlers::references
+pub async fn delete_product(State(state): State<AppState>, Path(id): Path<String>, Query(q): Query<DeleteQuery>, principal: Principal) -> impl IntoResponse {
+    delete_entity(&state, Entity::Product, &id, &q, &principal.username).await
+}
+
+pub async fn restore_product(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    trash::restore(&state, Entity::Product, &id).await
 }
 
 pub async fn add_product_category(State(state): State<AppState>, Path((id, cat_id)): Path<(String, String)>) -> impl IntoResponse {
     let (Ok(oid), Ok(cid)) = (ObjectId::from_str(&id), ObjectId::from_str(&cat_id)) else { return StatusCode::BAD_REQUEST.into_response(); };
     let filter = doc! {"_id": oid};
-    let update = doc! {"$addToSet": {"category_ids": cid}};
+    // a manual edit: imports no longer own the categories
+    let update = doc! {"$addToSet": {"category_ids": cid}, "$pullAll": {"imported_fields": ["category_ids"]}};
     match state.products.update_one(filter, update, None).await {
-        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Ok(_) => {
+            if let Err(e) = state.search.refresh(&state.products, oid).await { error!(?e, "search index refresh failed"); }
+            StatusCode::NO_CONTENT.into_response()
+        }
         Err(e) => { error!(?e, "add category failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
     }
 }
@@ -127,10 +310,54 @@ pub async fn add_product_category(State(state): State<AppState>, Path((id, cat_i
 pub async fn remove_product_category(State(state): State<AppState>, Path((id, cat_id)): Path<(String, String)>) -> impl IntoResponse {
     let (Ok(oid), Ok(cid)) = (ObjectId::from_str(&id), ObjectId::from_str(&cat_id)) else { return StatusCode::BAD_REQUEST.into_response(); };
     let filter = doc! {"_id": oid};
-    let update = doc! {"$pull": {"category_ids": cid}};
+    // a manual edit: imports no longer own the categories
+    let update = doc! {"$pull": {"category_ids": cid}, "$pullAll": {"imported_fields": ["category_ids"]}};
     match state.products.update_one(filter, update, None).await {
-        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Ok(_) => {
+            if let Err(e) = state.search.refresh(&state.products, oid).await { error!(?e, "search index refresh failed"); }
+            StatusCode::NO_CONTENT.into_response()
+        }
         Err(e) => { error!(?e, "remove category failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn barcodes() {
+        let cases: &[(&str, Option<&str>)] = &[
+            // валидные EAN-13, EAN-8, GTIN-14
+            ("4607001771517", Some("4607001771517")),
+            ("4006381333931", Some("4006381333931")),
+            ("96385074", Some("96385074")),
+            ("10036000291459", Some("10036000291459")),
+            (" 4607001 771517 ", Some("4607001771517")),
+            // UPC-A и GTIN-14 с ведущим нулём — как EAN-13
+            ("036000291452", Some("0036000291452")),
+            ("00036000291452", Some("0036000291452")),
+            // неверная контрольная цифра
+            ("4607001771518", None),
+            ("96385075", None),
+            ("036000291453", None),
+            ("10036000291450", None),
+            // не цифры и неподходящая длина
+            ("46070017715a7", None),
+            ("46070017715", None),
+            ("1234567", None),
+            ("", None),
+        ];
+        for (raw, want) in cases {
+            assert_eq!(normalize_barcode(raw).as_deref(), *want, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn barcode_list_dedupes_spellings() {
+        let list = vec!["036000291452".to_string(), "0036000291452".into(), "96385074".into()];
+        assert_eq!(normalize_barcodes(&list), Ok(vec!["0036000291452".to_string(), "96385074".into()]));
+        assert_eq!(normalize_barcodes(&["96385074".into(), "123".into()]), Err("123".to_string()));
+    }
+}
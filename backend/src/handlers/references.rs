@@ -0,0 +1,265 @@
+use std::str::FromStr;
+
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use futures::stream::StreamExt;
+use mongodb::options::{FindOptions, UpdateOptions};
+use tracing::{error, info};
+
+use crate::handlers::price_stats;
+use crate::models::{Product, ProductRedirect, Store};
+use crate::state::AppState;
+
+// DELETE без ?policy переносит товар, магазин или категорию в корзину (handlers::trash).
+// С ?policy документ удаляется сразу, вместе со ссылками на него:
+//   restrict — 409 со списком зависимых документов, если они есть;
+//   cascade — зависимые записи удаляются, ссылки в чеках и списках обнуляются;
+//   reassign — ссылки переводятся на reassign_to.
+// Ответ перечисляет все затронутые документы.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entity { Product, Store, Category }
+
+#[derive(serde::Deserialize)]
+pub struct DeleteQuery {
+    pub policy: Option<String>,
+    pub reassign_to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Policy { Restrict, Cascade, Reassign(ObjectId) }
+
+#[derive(serde::Serialize)]
+pub struct Touched {
+    pub collection: &'static str,
+    pub action: &'static str, // deleted | reassigned | unlinked
+    pub count: usize,
+    pub ids: Vec<ObjectId>,
+}
+
+// сколько id зависимых документов показывать в ответе 409
+const SAMPLE_IDS: i64 = 20;
+
+impl Entity {
+    pub fn collection(self) -> &'static str {
+        match self { Entity::Product => "products", Entity::Store => "stores", Entity::Category => "categories" }
+    }
+
+    // Документы, которые ссылаются на сущность и мешают удалению в режиме restrict.
+    // product_redirects сюда не входят: старые id объединённых товаров чистятся всегда.
+    pub fn dependents(self, id: ObjectId) -> Vec<(&'static str, Document)> {
+        match self {
+            Entity::Product => vec![
+                ("store_items", doc!{"product_id": id}),
+                ("store_activities", doc!{"product_id": id}),
+                // в старых чеках product_id мог сохраниться строкой
+                ("operations", doc!{"items.product_id": {"$in": [Bson::ObjectId(id), Bson::String(id.to_hex())]}}),
+            ],
+            Entity::Store => vec![
+                ("store_items", doc!{"store_id": id}),
+                ("store_activities", doc!{"store_id": id}),
+                ("operations", doc!{"store_id": id}),
+            ],
+            Entity::Category => vec![
+                ("products", doc!{"category_ids": id}),
+                ("categories", doc!{"parent_ids": id}),
+            ],
+        }
+    }
+}
+
+pub(crate) fn col(state: &AppState, name: &str) -> mongodb::Collection<Document> { state.db.collection::<Document>(name) }
+
+pub(crate) async fn ids_matching(state: &AppState, collection: &str, filter: Document, limit: Option<i64>) -> mongodb::error::Result<Vec<ObjectId>> {
+    let opts = FindOptions::builder().projection(doc!{"_id": 1}).limit(limit).build();
+    let mut cursor = col(state, collection).find(filter, opts).await?;
+    let mut out: Vec<ObjectId> = Vec::new();
+    while let Some(d) = cursor.next().await { if let Ok(id) = d?.get_object_id("_id") { out.push(id); } }
+    Ok(out)
+}
+
+// id выбираются до изменения, чтобы отчёт точно совпадал с тем, что изменилось
+pub(crate) async fn delete_where(state: &AppState, collection: &'static str, filter: Document) -> mongodb::error::Result<Touched> {
+    let ids = ids_matching(state, collection, filter, None).await?;
+    if !ids.is_empty() { col(state, collection).delete_many(doc!{"_id": {"$in": &ids}}, None).await?; }
+    Ok(Touched { collection, action: "deleted", count: ids.len(), ids })
+}
+
+pub(crate) async fn update_where(state: &AppState, collection: &'static str, action: &'static str, filter: Document, update: Document, array_filters: Option<Vec<Document>>) -> mongodb::error::Result<Touched> {
+    let ids = ids_matching(state, collection, filter, None).await?;
+    if !ids.is_empty() {
+        let opts = UpdateOptions::builder().array_filters(array_filters).build();
+        col(state, collection).update_many(doc!{"_id": {"$in": &ids}}, update, opts).await?;
+    }
+    Ok(Touched { collection, action, count: ids.len(), ids })
+}
+
+fn parse_policy(q: &DeleteQuery) -> Result<Policy, &'static str> {
+    match q.policy.as_deref().unwrap_or("restrict") {
+        "restrict" => Ok(Policy::Restrict),
+        "cascade" => Ok(Policy::Cascade),
+        "reassign" => q.reassign_to.as_deref().and_then(|s| ObjectId::from_str(s).ok()).map(Policy::Reassign).ok_or("invalid_reassign_to"),
+        _ => Err("invalid_policy"),
+    }
+}
+
+fn bad_request(code: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": code}))).into_response()
+}
+
+pub async fn delete_entity(state: &AppState, entity: Entity, id: &str, q: &DeleteQuery, actor: &str) -> Response {
+    let Ok(oid) = ObjectId::from_str(id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    if q.policy.is_none() { return crate::handlers::trash::soft_delete(state, entity, oid, actor).await; }
+    let policy = match parse_policy(q) { Ok(p) => p, Err(code) => return bad_request(code) };
+    match col(state, entity.collection()).find_one(doc!{"_id": oid}, None).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "find entity failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    }
+    if let Policy::Reassign(target) = policy {
+        if target == oid { return bad_request("reassign_to_self"); }
+        // перенос на документ из корзины потерял бы ссылки при его очистке
+        match col(state, entity.collection()).find_one(doc!{"_id": target, "deleted_at": null}, None).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return bad_request("reassign_target_not_found"),
+            Err(e) => { error!(?e, "find reassign target failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+        }
+        // дочерние категории получат нового родителя — он не должен быть их потомком
+        if entity == Entity::Category {
+            match crate::handlers::categories::CategoryGraph::load(state).await {
+                Ok(g) if g.descendants(oid).contains(&target) => return (StatusCode::CONFLICT, Json(serde_json::json!({"error": "category_cycle", "parent_id": target}))).into_response(),
+                Ok(_) => {}
+                Err(e) => { error!(?e, "load categories failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+            }
+        }
+    }
+
+    if policy == Policy::Restrict {
+        let mut blocking: Vec<serde_json::Value> = Vec::new();
+        for (collection, filter) in entity.dependents(oid) {
+            let count = match col(state, collection).count_documents(filter.clone(), None).await { Ok(n) => n, Err(e) => { error!(?e, "count dependents failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+            if count == 0 { continue; }
+            let ids = match ids_matching(state, collection, filter, Some(SAMPLE_IDS)).await { Ok(v) => v, Err(e) => { error!(?e, "list dependents failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+            blocking.push(serde_json::json!({"collection": collection, "count": count, "ids": ids}));
+        }
+        if !blocking.is_empty() {
+            return (StatusCode::CONFLICT, Json(serde_json::json!({"error": "has_dependents", "dependents": blocking}))).into_response();
+        }
+    }
+
+    let touched = match remove(state, entity, oid, policy).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "delete failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    };
+
+    let policy_name = match policy { Policy::Restrict => "restrict", Policy::Cascade => "cascade", Policy::Reassign(_) => "reassign" };
+    let touched: Vec<Touched> = touched.into_iter().filter(|t| t.count > 0).collect();
+    info!(collection = entity.collection(), id = %oid, policy = policy_name, touched = touched.iter().map(|t| t.count).sum::<usize>(), "entity deleted");
+    (StatusCode::OK, Json(serde_json::json!({
+        "deleted": {"collection": entity.collection(), "_id": oid},
+        "policy": policy_name,
+        "reassign_to": match policy { Policy::Reassign(t) => Some(t), _ => None },
+        "touched": touched,
+    }))).into_response()
+}
+
+// None — документ уже удалён параллельным запросом
+async fn remove(state: &AppState, entity: Entity, id: ObjectId, policy: Policy) -> mongodb::error::Result<Option<Vec<Touched>>> {
+    let touched = apply_policy(state, entity, id, policy).await?;
+    let res = col(state, entity.collection()).delete_one(doc!{"_id": id}, None).await?;
+    sync_indexes(state, entity, id).await;
+    sync_price_stats(state, entity, id, policy).await;
+    Ok((res.deleted_count == 1).then_some(touched))
+}
+
+// окончательное удаление из корзины: зависимые записи удаляются, как при policy=cascade
+pub async fn purge(state: &AppState, entity: Entity, id: ObjectId) -> mongodb::error::Result<Vec<Touched>> {
+    Ok(remove(state, entity, id, Policy::Cascade).await?.unwrap_or_default().into_iter().filter(|t| t.count > 0).collect())
+}
+
+async fn apply_policy(state: &AppState, entity: Entity, id: ObjectId, policy: Policy) -> mongodb::error::Result<Vec<Touched>> {
+    let mut out: Vec<Touched> = Vec::new();
+    let id_forms = || vec![Bson::ObjectId(id), Bson::String(id.to_hex())];
+    match (entity, policy) {
+        // restrict доходит сюда только без зависимых — остаются редиректы
+        (Entity::Product, Policy::Restrict | Policy::Cascade) => {
+            out.push(delete_where(state, "store_items", doc!{"product_id": id}).await?);
+            out.push(delete_where(state, "store_activities", doc!{"product_id": id}).await?);
+            // чек остаётся, позиция теряет привязку к товару
+            out.push(update_where(state, "operations", "unlinked", doc!{"items.product_id": {"$in": id_forms()}}, doc!{"$unset": {"items.$[it].product_id": ""}}, Some(vec![doc!{"it.product_id": {"$in": id_forms()}}])).await?);
+            out.push(delete_where(state, "product_redirects", doc!{"to": id}).await?);
+        }
+        (Entity::Product, Policy::Reassign(target)) => {
+            let title = state.products.find_one(doc!{"_id": target}, None).await?.map(|p: Product| p.title).unwrap_or_default();
+            // в магазине, где целевой товар уже есть, остаётся его цена
+            let taken: Vec<ObjectId> = distinct_ids(state, "store_items", "store_id", doc!{"product_id": target}).await?;
+            out.push(delete_where(state, "store_items", doc!{"product_id": id, "store_id": {"$in": &taken}}).await?);
+            out.push(update_where(state, "store_items", "reassigned", doc!{"product_id": id}, doc!{"$set": {"product_id": target}}, None).await?);
+            out.push(update_where(state, "store_activities", "reassigned", doc!{"product_id": id}, doc!{"$set": {"product_id": target, "product_name": &title}}, None).await?);
+            out.push(update_where(state, "operations", "reassigned", doc!{"items.product_id": {"$in": id_forms()}}, doc!{"$set": {"items.$[it].product_id": target}}, Some(vec![doc!{"it.product_id": {"$in": id_forms()}}])).await?);
+            out.push(update_where(state, "product_redirects", "reassigned", doc!{"to": id}, doc!{"$set": {"to": target}}, None).await?);
+            // старые ссылки на удалённый товар ведут на целевой
+            let redirect = ProductRedirect { id, to: target, ts_ms: chrono::Utc::now().timestamp_millis() };
+            let upsert = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+            state.db.collection::<ProductRedirect>("product_redirects").replace_one(doc!{"_id": id}, redirect, upsert).await?;
+        }
+        (Entity::Store, Policy::Restrict | Policy::Cascade) => {
+            out.push(delete_where(state, "store_items", doc!{"store_id": id}).await?);
+            out.push(delete_where(state, "store_activities", doc!{"store_id": id}).await?);
+            out.push(update_where(state, "operations", "unlinked", doc!{"store_id": id}, doc!{"$unset": {"store_id": ""}}, None).await?);
+        }
+        (Entity::Store, Policy::Reassign(target)) => {
+            let name = state.stores.find_one(doc!{"_id": target}, None).await?.map(|s: Store| s.name).unwrap_or_default();
+            let taken: Vec<ObjectId> = distinct_ids(state, "store_items", "product_id", doc!{"store_id": target}).await?;
+            out.push(delete_where(state, "store_items", doc!{"store_id": id, "product_id": {"$in": &taken}}).await?);
+            out.push(update_where(state, "store_items", "reassigned", doc!{"store_id": id}, doc!{"$set": {"store_id": target}}, None).await?);
+            out.push(update_where(state, "store_activities", "reassigned", doc!{"store_id": id}, doc!{"$set": {"store_id": target, "store_name": &name}}, None).await?);
+            out.push(update_where(state, "operations", "reassigned", doc!{"store_id": id}, doc!{"$set": {"store_id": target}}, None).await?);
+        }
+        (Entity::Category, Policy::Restrict | Policy::Cascade) => {
+            out.push(update_where(state, "products", "unlinked", doc!{"category_ids": id}, doc!{"$pull": {"category_ids": id}}, None).await?);
+            out.push(update_where(state, "categories", "unlinked", doc!{"parent_ids": id}, doc!{"$pull": {"parent_ids": id}}, None).await?);
+        }
+        (Entity::Category, Policy::Reassign(target)) => {
+            for (collection, field) in [("products", "category_ids"), ("categories", "parent_ids")] {
+                // $addToSet и $pull одного поля нельзя в одном обновлении
+                let t = update_where(state, collection, "reassigned", doc!{field: id}, doc!{"$addToSet": {field: target}}, None).await?;
+                if !t.ids.is_empty() { col(state, collection).update_many(doc!{"_id": {"$in": &t.ids}}, doc!{"$pull": {field: id}}, None).await?; }
+                out.push(t);
+            }
+        }
+    }
+    Ok(out)
+}
+
+async fn distinct_ids(state: &AppState, collection: &str, field: &str, filter: Document) -> mongodb::error::Result<Vec<ObjectId>> {
+    let values = col(state, collection).distinct(field, filter, None).await?;
+    Ok(values.into_iter().filter_map(|v| v.as_object_id()).collect())
+}
+
+// цены удалённого товара ушли или перешли к целевому; у магазина — меняются сводки многих товаров
+async fn sync_price_stats(state: &AppState, entity: Entity, id: ObjectId, policy: Policy) {
+    match (entity, policy) {
+        (Entity::Product, Policy::Reassign(target)) => price_stats::price_changed(state, [id, target]).await,
+        (Entity::Product, _) => price_stats::price_changed(state, [id]).await,
+        (Entity::Store, _) => price_stats::prices_changed(state).await,
+        (Entity::Category, _) => {}
+    }
+}
+
+async fn sync_indexes(state: &AppState, entity: Entity, id: ObjectId) {
+    use crate::search::SuggestKind;
+    match entity {
+        Entity::Product => {
+            state.search.remove(&id);
+            state.suggest.remove(SuggestKind::Product, id);
+        }
+        Entity::Store => state.suggest.remove(SuggestKind::Store, id),
+        Entity::Category => {
+            state.suggest.remove(SuggestKind::Category, id);
+            // у товаров поменялись category_ids — фильтр поиска по категориям
+            if let Err(e) = state.search.rebuild(&state.products).await { error!(?e, "search index rebuild failed"); }
+        }
+    }
+}
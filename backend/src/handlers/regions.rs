@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, Json};
+use bson::{doc, oid::ObjectId, Bson};
+use futures::stream::StreamExt;
+use tracing::error;
+
+use crate::handlers::trash::{live, Hidden};
+use crate::models::{Region, RegionCreate, RegionUpdate};
+use crate::money::Money;
+use crate::state::AppState;
+
+pub async fn list_regions(State(state): State<AppState>) -> impl IntoResponse {
+    let mut cursor = match state.regions.find(None, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query regions failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let mut items: Vec<Region> = Vec::new();
+    while let Some(res) = cursor.next().await { match res { Ok(doc)=> items.push(doc), Err(e)=> { error!(?e, "cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    (StatusCode::OK, Json(items)).into_response()
+}
+
+pub async fn get_region(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    match state.regions.find_one(doc!{"_id": oid}, None).await {
+        Ok(Some(doc)) => (StatusCode::OK, Json(doc)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "find region failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub async fn create_region(State(state): State<AppState>, Json(payload): Json<RegionCreate>) -> impl IntoResponse {
+    if payload.name.trim().is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
+    let region = Region { id: None, name: payload.name, desc: payload.desc };
+    match state.regions.insert_one(region, None).await {
+        Ok(result) => {
+            let id = match result.inserted_id { Bson::ObjectId(oid) => oid, _ => ObjectId::new() };
+            match state.regions.find_one(doc!{"_id": id}, None).await { Ok(Some(created)) => (StatusCode::CREATED, Json(created)).into_response(), _ => StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+        }
+        Err(e) => { error!(?e, "insert region failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub async fn update_region(State(state): State<AppState>, Path(id): Path<String>, Json(patch): Json<RegionUpdate>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let mut set = doc!{};
+    if let Some(v) = patch.name { set.insert("name", v); }
+    if let Some(v) = patch.desc { set.insert("desc", v); }
+    if set.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
+    let opts = mongodb::options::FindOneAndUpdateOptions::builder().return_document(mongodb::options::ReturnDocument::After).build();
+    match state.regions.find_one_and_update(doc!{"_id": oid}, doc!{"$set": set}, opts).await {
+        Ok(Some(updated)) => (StatusCode::OK, Json(updated)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "update region failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub async fn delete_region(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    match state.regions.delete_one(doc!{"_id": oid}, None).await {
+        Ok(res) if res.deleted_count == 1 => {
+            if let Err(e) = state.stores.update_many(doc!{"region_id": oid}, doc!{"$unset": {"region_id": ""}}, None).await { error!(?e, "detach stores from region failed"); }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "delete region failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct CompareQuery {
+    // comma-separated product ids
+    pub product_ids: String,
+    // comma-separated region ids; all regions when omitted
+    pub region_ids: Option<String>,
+}
+
+// repeated ids are kept once, in the order given
+fn parse_ids(list: &str) -> Result<Vec<ObjectId>, ()> {
+    let mut out: Vec<ObjectId> = Vec::new();
+    for s in list.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let oid = ObjectId::from_str(s).map_err(|_| ())?;
+        if !out.contains(&oid) { out.push(oid); }
+    }
+    Ok(out)
+}
+
+// a basket, not a catalog dump: prices of every product are loaded for every region
+const MAX_COMPARE_PRODUCTS: usize = 200;
+
+// Same product set across regions: per product avg/median/min/max and the basket total per region
+pub async fn compare_regions(State(state): State<AppState>, Query(q): Query<CompareQuery>) -> impl IntoResponse {
+    let Ok(product_ids) = parse_ids(&q.product_ids) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_product_id"}))).into_response(); };
+    if product_ids.is_empty() { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "empty_product_ids"}))).into_response(); }
+    if product_ids.len() > MAX_COMPARE_PRODUCTS { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "too_many_product_ids", "max": MAX_COMPARE_PRODUCTS}))).into_response(); }
+    let region_filter = match q.region_ids.as_deref() {
+        Some(list) => match parse_ids(list) { Ok(ids) => doc!{"_id": {"$in": ids}}, Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_region_id"}))).into_response() },
+        None => doc!{},
+    };
+    let mut regions: Vec<Region> = Vec::new();
+    let mut rc = match state.regions.find(region_filter, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query regions failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = rc.next().await { match res { Ok(r)=> regions.push(r), Err(e)=> { error!(?e, "regions cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    let region_ids: Vec<ObjectId> = regions.iter().filter_map(|r| r.id).collect();
+
+    let mut store_region: HashMap<ObjectId, ObjectId> = HashMap::new();
+    let mut sc = match state.stores.find(live(doc!{"region_id": {"$in": &region_ids}}), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query region stores failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = sc.next().await { match res { Ok(s)=> { if let (Some(sid), Some(rid)) = (s.id, s.region_id) { store_region.insert(sid, rid); } }, Err(e)=> { error!(?e, "stores cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+
+    // region -> product -> prices
+    let mut prices: HashMap<ObjectId, HashMap<ObjectId, Vec<Money>>> = HashMap::new();
+    if !store_region.is_empty() {
+        let hidden = match Hidden::load(&state).await { Ok(h)=>h, Err(e)=> { error!(?e, "load trash failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        let mut ic = match state.store_items.find(hidden.items(doc!{"product_id": {"$in": &product_ids}, "store_id": {"$in": store_region.keys().collect::<Vec<_>>()}}), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query store_items failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        while let Some(res) = ic.next().await {
+            match res {
+                Ok(it) => { if let Some(rid) = store_region.get(&it.store_id) { prices.entry(*rid).or_default().entry(it.product_id).or_default().push(it.price); } }
+                Err(e) => { error!(?e, "store_items cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+            }
+        }
+    }
+
+    let mut titles: HashMap<ObjectId, String> = HashMap::new();
+    let mut pc = match state.products.find(live(doc!{"_id": {"$in": &product_ids}}), None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query products failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    while let Some(res) = pc.next().await { match res { Ok(p)=> { if let Some(id) = p.id { titles.insert(id, p.title); } }, Err(e)=> { error!(?e, "products cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+
+    let regions_out: Vec<serde_json::Value> = regions.iter().filter_map(|r| r.id.map(|rid| (rid, r))).map(|(rid, r)| {
+        let by_product = prices.remove(&rid).unwrap_or_default();
+        let mut basket_total = Money::ZERO;
+        let products: Vec<serde_json::Value> = product_ids.iter().map(|pid| {
+            let Some(list) = by_product.get(pid) else { return serde_json::json!({"product_id": pid, "count": 0}) };
+            let avg = Money::avg(list).unwrap_or(Money::ZERO);
+            basket_total += avg;
+            let mut sorted = list.clone();
+            serde_json::json!({
+                "product_id": pid,
+                "count": list.len(),
+                "avg": avg,
+                "median": Money::median(&mut sorted),
+                "min": list.iter().min(),
+                "max": list.iter().max(),
+            })
+        }).collect();
+        serde_json::json!({
+            "region_id": rid,
+            "region_name": r.name,
+            "coverage": by_product.len() as f64 / product_ids.len() as f64,
+            "basket_total": basket_total,
+            "products": products,
+        })
+    }).collect();
+
+    let out = serde_json::json!({
+        "products": product_ids.iter().map(|pid| serde_json::json!({"product_id": pid, "title": titles.get(pid)})).collect::<Vec<_>>(),
+        "regions": regions_out,
+    });
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ids_dedupes_in_order() {
+        let (a, b) = (ObjectId::new(), ObjectId::new());
+        assert_eq!(parse_ids(&format!("{a}, {b},{a},,{b}")), Ok(vec![a, b]));
+        assert_eq!(parse_ids(""), Ok(vec![]));
+        assert_eq!(parse_ids(&format!("{a},nope")), Err(()));
+    }
+}
@@ -1,38 +1,43 @@
 use std::str::FromStr;
 use std::collections::HashMap as StdHashMap;
 
-use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, Json};
 use bson::{doc, oid::ObjectId};
 use futures::stream::StreamExt;
 use tracing::error;
 
-use crate::models::{Store, StoreCreate, StoreUpdate, StoreItem, StoreItemCreate, StoreItemUpdate, StoreActivity};
+use crate::handlers::auth::Principal;
+use crate::handlers::listing::{FilterKind, ListQuery, ListSpec};
+use crate::handlers::price_stats;
+use crate::handlers::prices::{items_as_of_pipeline, AsOfQuery};
+use crate::handlers::references::{delete_entity, DeleteQuery, Entity};
+use crate::handlers::trash::{self, live};
+use crate::models::{GeoPoint, Store, StoreCreate, StoreUpdate, StoreItem, StoreItemCreate, StoreItemUpdate, StoreActivity};
 use crate::state::AppState;
 
-pub async fn list_stores(State(state): State<AppState>) -> impl IntoResponse {
-    let mut cursor = match state.stores.find(None, None).await {
-        Ok(c) => c,
-        Err(e) => {
-            error!(?e, "failed to query stores");
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-        }
-    };
-    let mut items: Vec<Store> = Vec::new();
-    while let Some(res) = cursor.next().await {
-        match res {
-            Ok(doc) => items.push(doc),
-            Err(e) => {
-                error!(?e, "cursor error");
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-            }
-        }
+const STORES_LIST: ListSpec = ListSpec {
+    filters: &[
+        ("name", "name", FilterKind::Contains),
+        ("addr", "addr", FilterKind::Contains),
+        ("brand", "brand", FilterKind::Exact),
+        ("chain_id", "chain_id", FilterKind::ObjectId),
+        ("region_id", "region_id", FilterKind::ObjectId),
+    ],
+    sortable: &["name", "addr"],
+    default_sort: "name",
+    legacy_limit: None,
+};
+
+pub async fn list_stores(State(state): State<AppState>, q: ListQuery) -> impl IntoResponse {
+    match q.fetch(&state.stores, live(doc! {}), &STORES_LIST).await {
+        Ok(page) => page.into_response(),
+        Err(e) => e.into_response(),
     }
-    (StatusCode::OK, Json(items)).into_response()
 }
 
 pub async fn get_store(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
     let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    let filter = doc! {"_id": oid};
+    let filter = live(doc! {"_id": oid});
     match state.stores.find_one(filter, None).await {
         Ok(Some(doc)) => (StatusCode::OK, Json(doc)).into_response(),
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
@@ -43,20 +48,62 @@ pub async fn get_store(State(state): State<AppState>, Path(id): Path<String>) ->
     }
 }
 
+// lat/lon приходят парой; одна координата без другой или вне диапазона — ошибка
+fn location_from(lat: Option<f64>, lon: Option<f64>) -> Result<Option<GeoPoint>, ()> {
+    match (lat, lon) {
+        (None, None) => Ok(None),
+        (Some(lat), Some(lon)) => GeoPoint::new(lat, lon).map(Some).ok_or(()),
+        _ => Err(()),
+    }
+}
+
+// chain_id / region_id must point to an existing chain / region, as in chains::assign_chain
+async fn check_reference(state: &AppState, collection: &str, id: Option<ObjectId>, not_found: &'static str) -> Result<(), axum::response::Response> {
+    let Some(id) = id else { return Ok(()) };
+    match state.db.collection::<bson::Document>(collection).count_documents(doc!{"_id": id}, None).await {
+        Ok(0) => Err((StatusCode::NOT_FOUND, Json(serde_json::json!({"error": not_found}))).into_response()),
+        Ok(_) => Ok(()),
+        Err(e) => { error!(?e, collection, "find referenced document failed"); Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()) }
+    }
+}
+
 pub async fn create_store(State(state): State<AppState>, Json(payload): Json<StoreCreate>) -> impl IntoResponse {
+    let Ok(location) = location_from(payload.lat, payload.lon) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_location"}))).into_response(); };
+    let chain_id = match payload.chain_id.as_deref().filter(|s| !s.is_empty()).map(ObjectId::from_str) {
+        None => None,
+        Some(Ok(oid)) => Some(oid),
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_chain_id"}))).into_response(),
+    };
+    let region_id = match payload.region_id.as_deref().filter(|s| !s.is_empty()).map(ObjectId::from_str) {
+        None => None,
+        Some(Ok(oid)) => Some(oid),
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_region_id"}))).into_response(),
+    };
+    if let Err(res) = check_reference(&state, "store_chains", chain_id, "chain_not_found").await { return res; }
+    if let Err(res) = check_reference(&state, "regions", region_id, "region_not_found").await { return res; }
     let store = Store {
         id: None,
         name: payload.name,
         addr: payload.addr,
         desc: payload.desc,
         image_url: payload.image_url,
+        location,
+        brand: payload.brand,
+        osm_id: None,
+        chain_id,
+        region_id,
+        deleted_at: None,
+        deleted_by: None,
     };
     match state.stores.insert_one(store, None).await {
         Ok(result) => {
             let id = match result.inserted_id { bson::Bson::ObjectId(oid) => oid, _ => ObjectId::new() };
             let filter = doc! {"_id": id};
             match state.stores.find_one(filter, None).await {
-                Ok(Some(created)) => (StatusCode::CREATED, Json(created)).into_response(),
+                Ok(Some(created)) => {
+                    state.suggest.upsert_store(&created);
+                    (StatusCode::CREATED, Json(created)).into_response()
+                }
                 _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
             }
         }
@@ -74,11 +121,41 @@ pub async fn update_store(State(state): State<AppState>, Path(id): Path<String>,
     if let Some(v) = patch.addr { set.insert("addr", v); }
     if let Some(v) = patch.desc { set.insert("desc", v); }
     if let Some(v) = patch.image_url { set.insert("image_url", v); }
-    if set.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
-    let filter = doc! {"_id": oid};
-    let update = doc! {"$set": set};
-    match state.stores.find_one_and_update(filter, update, None).await {
-        Ok(Some(updated)) => (StatusCode::OK, Json(updated)).into_response(),
+    if let Some(v) = patch.brand { set.insert("brand", v); }
+    // an empty id detaches the store: the field is removed, as delete_chain/delete_region and integrity repair do
+    let mut unset = doc! {};
+    if let Some(v) = patch.chain_id {
+        if v.is_empty() { unset.insert("chain_id", ""); }
+        else if let Ok(cid) = ObjectId::from_str(&v) {
+            if let Err(res) = check_reference(&state, "store_chains", Some(cid), "chain_not_found").await { return res; }
+            set.insert("chain_id", cid);
+        }
+        else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_chain_id"}))).into_response(); }
+    }
+    if let Some(v) = patch.region_id {
+        if v.is_empty() { unset.insert("region_id", ""); }
+        else if let Ok(rid) = ObjectId::from_str(&v) {
+            if let Err(res) = check_reference(&state, "regions", Some(rid), "region_not_found").await { return res; }
+            set.insert("region_id", rid);
+        }
+        else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_region_id"}))).into_response(); }
+    }
+    match location_from(patch.lat, patch.lon) {
+        Ok(Some(loc)) => { set.insert("location", bson::to_bson(&loc).unwrap_or(bson::Bson::Null)); }
+        Ok(None) => {}
+        Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_location"}))).into_response(),
+    }
+    if set.is_empty() && unset.is_empty() { return StatusCode::BAD_REQUEST.into_response(); }
+    let filter = live(doc! {"_id": oid});
+    let mut update = doc! {};
+    if !set.is_empty() { update.insert("$set", set); }
+    if !unset.is_empty() { update.insert("$unset", unset); }
+    let opts = mongodb::options::FindOneAndUpdateOptions::builder().return_document(mongodb::options::ReturnDocument::After).build();
+    match state.stores.find_one_and_update(filter, update, opts).await {
+        Ok(Some(updated)) => {
+            state.suggest.upsert_store(&updated);
+            (StatusCode::OK, Json(updated)).into_response()
+        }
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
         Err(e) => {
             error!(?e, "update store failed");
@@ -87,24 +164,93 @@ pub async fn update_store(State(state): State<AppState>, Path(id): Path<String>,
     }
 }
 
-pub async fn delete_store(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
-    let Ok(oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    let filter = doc! {"_id": oid};
-    match state.stores.delete_one(filter, None).await {
-        Ok(res) if res.deleted_count == 1 => StatusCode::NO_CONTENT.into_response(),
-        Ok(_) => StatusCode::NOT_FOUND.into_response(),
-        Err(e) => {
-            error!(?e, "delete store failed");
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+// moves to the trash; with ?policy=restrict|cascade|reassign&reassign_to=<id> deletes right away, see handlers::references
+pub async fn delete_store(State(state): State<AppState>, Path(id): Path<String>, Query(q): Query<DeleteQuery>, principal: Principal) -> impl IntoResponse {
+    delete_entity(&state, Entity::Store, &id, &q, &principal.username).await
+}
+
+pub async fn restore_store(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    trash::restore(&state, Entity::Store, &id).await
+}
+
+#[derive(serde::Deserialize)]
+pub struct NearbyQuery {
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default = "default_radius_m")]
+    pub radius: f64,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+pub fn default_radius_m() -> f64 { 2000.0 }
+const DEFAULT_NEARBY_LIMIT: i64 = 50;
+const MAX_NEARBY_LIMIT: i64 = 200;
+
+// Stores within `radius_m` meters of the point, nearest first, with distance in meters.
+// Stores without a location or in the trash are never returned.
+pub async fn stores_near(state: &AppState, point: &GeoPoint, radius_m: f64, limit: Option<i64>) -> mongodb::error::Result<Vec<(Store, f64)>> {
+    let mut pipeline = vec![doc!{"$geoNear": {
+        "near": {"type": "Point", "coordinates": [point.lon(), point.lat()]},
+        "distanceField": "distance_m",
+        "maxDistance": radius_m,
+        "spherical": true,
+        "query": {"deleted_at": null},
+    }}];
+    if let Some(l) = limit { pipeline.push(doc!{"$limit": l}); }
+    let mut cursor = state.stores.aggregate(pipeline, None).await?;
+    let mut out = Vec::new();
+    while let Some(res) = cursor.next().await {
+        let d = res?;
+        let dist = d.get_f64("distance_m").unwrap_or(0.0);
+        let id = d.get_object_id("_id").ok();
+        match bson::from_document::<Store>(d) {
+            Ok(store) => out.push((store, dist)),
+            Err(e) => error!(?e, ?id, "decode store failed"),
+        }
+    }
+    Ok(out)
+}
+
+pub async fn list_nearby_stores(State(state): State<AppState>, Query(q): Query<NearbyQuery>) -> impl IntoResponse {
+    let Some(point) = GeoPoint::new(q.lat, q.lon) else { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_location"}))).into_response(); };
+    if q.radius.is_nan() || q.radius <= 0.0 { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_radius"}))).into_response(); }
+    let limit = q.limit.unwrap_or(DEFAULT_NEARBY_LIMIT);
+    if limit < 1 { return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_limit"}))).into_response(); }
+    match stores_near(&state, &point, q.radius, Some(limit.min(MAX_NEARBY_LIMIT))).await {
+        Ok(found) => {
+            let payload: Vec<serde_json::Value> = found.into_iter().map(|(store, dist)| {
+                let mut v = serde_json::to_value(&store).unwrap_or_default();
+                v["distance_m"] = serde_json::json!(dist.round());
+                v
+            }).collect();
+            (StatusCode::OK, Json(payload)).into_response()
         }
+        Err(e) => { error!(?e, "geoNear stores failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
     }
 }
 
-pub async fn list_store_products(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+const STORE_PRODUCTS_LIST: ListSpec = ListSpec {
+    filters: &[
+        ("product_id", "product_id", FilterKind::ObjectId),
+        ("price", "price", FilterKind::MoneyRange),
+    ],
+    sortable: &["price"],
+    default_sort: "_id",
+    legacy_limit: None,
+};
+
+// ?as_of=<мс> — ассортимент и цены на эту дату из истории (handlers::prices), _id позиции — id записи истории
+pub async fn list_store_products(State(state): State<AppState>, Path(id): Path<String>, Query(aq): Query<AsOfQuery>, q: ListQuery) -> impl IntoResponse {
     let Ok(store_oid) = ObjectId::from_str(&id) else { return StatusCode::BAD_REQUEST.into_response(); };
-    let mut cursor = match state.store_items.find(doc!{"store_id": store_oid}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query store_items failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut items: Vec<StoreItem> = Vec::new();
-    while let Some(res) = cursor.next().await { match res { Ok(doc)=> items.push(doc), Err(e)=> { error!(?e, "cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
+    let hidden = match trash::Hidden::load(&state).await { Ok(h)=>h, Err(e)=> { error!(?e, "load trash failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+    let base = hidden.items(doc!{"store_id": store_oid});
+    let page = match aq.as_of {
+        Some(ts) => q.fetch_pipeline(&state.store_activities, items_as_of_pipeline(base, ts), &STORE_PRODUCTS_LIST).await,
+        None => q.fetch(&state.store_items, base, &STORE_PRODUCTS_LIST).await,
+    };
+    let page = match page { Ok(p)=>p, Err(e)=> return e.into_response() };
+    let items: &Vec<StoreItem> = &page.items;
     // collect product ids
     let pids: Vec<ObjectId> = items.iter().map(|it| it.product_id.clone()).collect();
     let mut products_map = StdHashMap::new();
@@ -112,7 +258,7 @@ pub async fn list_store_products(State(state): State<AppState>, Path(id): Path<S
         let mut pcursor = match state.products.find(doc!{"_id": {"$in": &pids}}, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "query products for store items failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
         while let Some(res) = pcursor.next().await { match res { Ok(p)=> { if let Some(id) = p.id { products_map.insert(id, p); } }, Err(e)=> { error!(?e, "products cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } } }
     }
-    let payload: Vec<serde_json::Value> = items.into_iter().map(|it| {
+    page.map(|it| {
         let prod = products_map.get(&it.product_id);
         serde_json::json!({
             "_id": it.id,
@@ -121,8 +267,7 @@ pub async fn list_store_products(State(state): State<AppState>, Path(id): Path<S
             "price": it.price,
             "product": prod,
         })
-    }).collect();
-    (StatusCode::OK, Json(payload)).into_response()
+    }).into_response()
 }
 
 pub async fn add_store_product(State(state): State<AppState>, Path(id): Path<String>, Json(body): Json<StoreItemCreate>) -> impl IntoResponse {
@@ -139,6 +284,7 @@ pub async fn add_store_product(State(state): State<AppState>, Path(id): Path<Str
             let store_name = match state.stores.find_one(doc!{"_id": store_oid}, None).await { Ok(opt)=> opt.map(|s| s.name), Err(_)=> None };
             let activity = StoreActivity { id: None, store_id: store_oid, product_id: Some(product_oid), kind: "item_added".to_string(), ts_ms: now_ms, price: Some(body.price), product_name, store_name };
             let _ = state.store_activities.insert_one(activity, None).await;
+            price_stats::price_changed(&state, [product_oid]).await;
             // return new doc
             match state.store_items.find_one(doc!{"store_id": store_oid, "product_id": product_oid}, None).await {
                 Ok(Some(doc)) => {
@@ -170,6 +316,7 @@ pub async fn update_store_product(State(state): State<AppState>, Path((id, produ
             let store_name = match state.stores.find_one(doc!{"_id": store_oid}, None).await { Ok(opt)=> opt.map(|s| s.name), Err(_)=> None };
             let activity = StoreActivity { id: None, store_id: store_oid, product_id: Some(product_oid), kind: "price_updated".to_string(), ts_ms: now_ms, price: Some(body.price), product_name, store_name };
             let _ = state.store_activities.insert_one(activity, None).await;
+            price_stats::price_changed(&state, [product_oid]).await;
             StatusCode::NO_CONTENT.into_response()
         },
         Ok(_) => StatusCode::NOT_FOUND.into_response(),
@@ -187,6 +334,7 @@ pub async fn remove_store_product(State(state): State<AppState>, Path((id, produ
             let store_name = match state.stores.find_one(doc!{"_id": store_oid}, None).await { Ok(opt)=> opt.map(|s| s.name), Err(_)=> None };
             let activity = StoreActivity { id: None, store_id: store_oid, product_id: Some(product_oid), kind: "item_removed".to_string(), ts_ms: now_ms, price: None, product_name, store_name };
             let _ = state.store_activities.insert_one(activity, None).await;
+            price_stats::price_changed(&state, [product_oid]).await;
             StatusCode::NO_CONTENT.into_response()
         },
         Ok(_) => StatusCode::NOT_FOUND.into_response(),
@@ -0,0 +1,29 @@
+use axum::{extract::{Query, State}, http::StatusCode, response::IntoResponse, Json};
+
+use crate::search::SuggestKind;
+use crate::state::AppState;
+
+#[derive(serde::Deserialize)]
+pub struct SuggestQuery {
+    #[serde(default)]
+    pub q: String,
+    // product,store,category через запятую; пусто — все
+    pub types: Option<String>,
+    #[serde(default = "default_suggest_limit")]
+    pub limit: usize,
+}
+
+fn default_suggest_limit() -> usize { 10 }
+
+// GET /suggest?q=&types=&limit= — mixed suggestions for the header search box, served from memory
+pub async fn suggest(State(state): State<AppState>, Query(q): Query<SuggestQuery>) -> impl IntoResponse {
+    let mut kinds: Vec<SuggestKind> = Vec::new();
+    for t in q.types.as_deref().unwrap_or("").split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        match serde_json::from_value::<SuggestKind>(serde_json::Value::String(t.to_lowercase())) {
+            Ok(k) => { if !kinds.contains(&k) { kinds.push(k); } }
+            Err(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_type", "type": t}))).into_response(),
+        }
+    }
+    let items = state.suggest.suggest(&q.q, &kinds, q.limit.clamp(1, 30));
+    (StatusCode::OK, Json(items)).into_response()
+}
@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use axum::{extract::{Query, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use futures::stream::StreamExt;
+use mongodb::options::FindOptions;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::handlers::price_stats;
+use crate::handlers::references::{col, ids_matching, purge, Entity};
+use crate::state::AppState;
+
+// Корзина. DELETE без ?policy не удаляет товар, магазин или категорию, а ставит deleted_at
+// (мс) и deleted_by. Такие документы не видны в публичных списках, поиске и аналитике, а их
+// цены и история остаются в базе — POST .../restore возвращает всё как было. Фоновая задача
+// раз в час удаляет насовсем (как policy=cascade) то, что лежит в корзине дольше
+// TRASH_RETENTION_DAYS дней (по умолчанию 30).
+
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+const PURGE_EVERY: std::time::Duration = std::time::Duration::from_secs(3600);
+const DAY_MS: i64 = 24 * 3600 * 1000;
+
+pub fn retention_days() -> i64 {
+    std::env::var("TRASH_RETENTION_DAYS").ok().and_then(|s| s.parse().ok()).filter(|d: &i64| *d >= 0).unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+// фильтр по товарам, магазинам и категориям без удалённых (поле отсутствует или null)
+pub fn live(mut filter: Document) -> Document {
+    filter.insert("deleted_at", Bson::Null);
+    filter
+}
+
+fn in_trash() -> Document { doc!{"deleted_at": {"$ne": null}} }
+
+// Удалённые товары и магазины — чтобы не показывать их цены (store_items) и историю (store_activities)
+pub struct Hidden {
+    products: Vec<ObjectId>,
+    stores: Vec<ObjectId>,
+}
+
+impl Hidden {
+    pub async fn load(state: &AppState) -> mongodb::error::Result<Self> {
+        Ok(Hidden {
+            products: ids_matching(state, "products", in_trash(), None).await?,
+            stores: ids_matching(state, "stores", in_trash(), None).await?,
+        })
+    }
+
+    // фильтр по store_items / store_activities без записей удалённых товаров и магазинов
+    pub fn items(&self, filter: Document) -> Document {
+        let mut hide = Document::new();
+        if !self.products.is_empty() { hide.insert("product_id", doc!{"$nin": &self.products}); }
+        if !self.stores.is_empty() { hide.insert("store_id", doc!{"$nin": &self.stores}); }
+        if hide.is_empty() { filter } else if filter.is_empty() { hide } else { doc!{"$and": [filter, hide]} }
+    }
+}
+
+pub async fn soft_delete(state: &AppState, entity: Entity, id: ObjectId, actor: &str) -> Response {
+    let now = chrono::Utc::now().timestamp_millis();
+    let update = doc!{"$set": {"deleted_at": now, "deleted_by": actor}};
+    match col(state, entity.collection()).update_one(live(doc!{"_id": id}), update, None).await {
+        Ok(res) if res.matched_count == 1 => {}
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "soft delete failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    }
+    use crate::search::SuggestKind;
+    match entity {
+        Entity::Product => {
+            state.search.remove(&id);
+            state.suggest.remove(SuggestKind::Product, id);
+        }
+        Entity::Store => {
+            state.suggest.remove(SuggestKind::Store, id);
+            // цены магазина в корзине не входят в сводки товаров
+            price_stats::prices_changed(state).await;
+        }
+        Entity::Category => state.suggest.remove(SuggestKind::Category, id),
+    }
+    info!(collection = entity.collection(), id = %id, actor, "entity moved to trash");
+    (StatusCode::OK, Json(serde_json::json!({
+        "deleted": {"collection": entity.collection(), "_id": id},
+        "policy": "soft",
+        "deleted_at": now,
+        "deleted_by": actor,
+        "purge_after": now + retention_days() * DAY_MS,
+    }))).into_response()
+}
+
+pub async fn restore(state: &AppState, entity: Entity, id: &str) -> Response {
+    let Ok(oid) = ObjectId::from_str(id) else { return StatusCode::BAD_REQUEST.into_response(); };
+    let filter = doc!{"_id": oid, "deleted_at": {"$ne": null}};
+    match col(state, entity.collection()).update_one(filter, doc!{"$unset": {"deleted_at": "", "deleted_by": ""}}, None).await {
+        Ok(res) if res.matched_count == 1 => {}
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "restore failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
+    }
+    let filter = doc!{"_id": oid};
+    let restored = match entity {
+        Entity::Product => state.products.find_one(filter, None).await.map(|p| p.map(|p| {
+            state.search.upsert(&p);
+            state.suggest.upsert_product(&p);
+            serde_json::to_value(p)
+        })),
+        Entity::Store => {
+            price_stats::prices_changed(state).await;
+            state.stores.find_one(filter, None).await.map(|s| s.map(|s| { state.suggest.upsert_store(&s); serde_json::to_value(s) }))
+        }
+        Entity::Category => state.categories.find_one(filter, None).await.map(|c| c.map(|c| { state.suggest.upsert_category(&c); serde_json::to_value(c) })),
+    };
+    info!(collection = entity.collection(), id = %oid, "entity restored");
+    match restored {
+        Ok(Some(Ok(v))) => (StatusCode::OK, Json(v)).into_response(),
+        Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => { error!(?e, "find restored entity failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct TrashQuery {
+    // products | stores | categories, по умолчанию все
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
+
+const ENTITIES: [Entity; 3] = [Entity::Product, Entity::Store, Entity::Category];
+
+// GET /trash: содержимое корзины, недавно удалённые первыми
+pub async fn list_trash(State(state): State<AppState>, Query(q): Query<TrashQuery>) -> impl IntoResponse {
+    let entities: Vec<Entity> = match q.kind.as_deref() {
+        None | Some("") => ENTITIES.to_vec(),
+        Some(kind) => match ENTITIES.iter().find(|e| e.collection() == kind) {
+            Some(e) => vec![*e],
+            None => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_type"}))).into_response(),
+        },
+    };
+    let retention = retention_days();
+    let mut out: Vec<serde_json::Value> = Vec::new();
+    for entity in entities {
+        let opts = FindOptions::builder().projection(doc!{"title": 1, "name": 1, "addr": 1, "deleted_at": 1, "deleted_by": 1}).build();
+        let mut cursor = match col(&state, entity.collection()).find(in_trash(), opts).await { Ok(c)=>c, Err(e)=> { error!(?e, "query trash failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+        while let Some(d) = cursor.next().await {
+            let d = match d { Ok(d)=>d, Err(e)=> { error!(?e, "read trash failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
+            let deleted_at = d.get_i64("deleted_at").unwrap_or(0);
+            out.push(serde_json::json!({
+                "collection": entity.collection(),
+                "_id": d.get_object_id("_id").ok(),
+                "name": d.get_str("title").or_else(|_| d.get_str("name")).unwrap_or_default(),
+                "addr": d.get_str("addr").ok(),
+                "deleted_at": deleted_at,
+                "deleted_by": d.get_str("deleted_by").ok(),
+                "purge_after": deleted_at + retention * DAY_MS,
+            }));
+        }
+    }
+    out.sort_by_key(|v| std::cmp::Reverse(v["deleted_at"].as_i64().unwrap_or(0)));
+    (StatusCode::OK, Json(out)).into_response()
+}
+
+// Удаляет насовсем всё, что лежит в корзине дольше older_than_days
+pub async fn purge_expired(state: &AppState, older_than_days: i64) -> mongodb::error::Result<Vec<serde_json::Value>> {
+    let cutoff = chrono::Utc::now().timestamp_millis() - older_than_days * DAY_MS;
+    let mut purged: Vec<serde_json::Value> = Vec::new();
+    for entity in ENTITIES {
+        for id in ids_matching(state, entity.collection(), doc!{"deleted_at": {"$ne": null, "$lte": cutoff}}, None).await? {
+            let touched = purge(state, entity, id).await?;
+            purged.push(serde_json::json!({"collection": entity.collection(), "_id": id, "touched": touched}));
+        }
+    }
+    if !purged.is_empty() { info!(count = purged.len(), older_than_days, "trash purged"); state.cache.clear(); }
+    Ok(purged)
+}
+
+#[derive(serde::Deserialize)]
+pub struct PurgeQuery {
+    pub older_than_days: Option<i64>,
+}
+
+// POST /trash/purge: очистка без ожидания фоновой задачи; ?older_than_days=0 — вся корзина
+pub async fn purge_trash(State(state): State<AppState>, Query(q): Query<PurgeQuery>) -> impl IntoResponse {
+    let days = q.older_than_days.unwrap_or_else(retention_days).max(0);
+    match purge_expired(&state, days).await {
+        Ok(purged) => (StatusCode::OK, Json(serde_json::json!({"older_than_days": days, "purged": purged}))).into_response(),
+        Err(e) => { error!(?e, "purge trash failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
+    }
+}
+
+pub fn spawn_purger(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(PURGE_EVERY);
+        loop {
+            tick.tick().await;
+            if let Err(e) = purge_expired(&state, retention_days()).await { error!(?e, "scheduled trash purge failed"); }
+        }
+    })
+}
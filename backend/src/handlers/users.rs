@@ -1,9 +1,9 @@
 use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
 use bson::{doc, oid::ObjectId};
-use futures::stream::StreamExt;
 use tracing::error;
 use argon2::{Argon2, password_hash::{PasswordHasher, SaltString}};
 
+use crate::handlers::listing::{FilterKind, ListQuery, ListSpec};
 use crate::models::User;
 use crate::state::AppState;
 use axum::http::HeaderMap;
@@ -21,15 +21,22 @@ pub struct UpdateUser { pub username: Option<String>, pub password: Option<Strin
 #[derive(serde::Serialize)]
 pub struct PublicUser { pub _id: ObjectId, pub username: String, pub role: String }
 
-pub async fn list_users(State(state): State<AppState>) -> impl IntoResponse {
+const USERS_LIST: ListSpec = ListSpec {
+    filters: &[
+        ("username", "username", FilterKind::Contains),
+        ("role", "role", FilterKind::Exact),
+    ],
+    sortable: &["username", "role"],
+    default_sort: "username",
+    legacy_limit: None,
+};
+
+pub async fn list_users(State(state): State<AppState>, q: ListQuery) -> impl IntoResponse {
     let coll = state.db.collection::<User>("users");
-    let mut cur = match coll.find(None, None).await { Ok(c)=>c, Err(e)=> { error!(?e, "users.find failed"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); } };
-    let mut out: Vec<PublicUser> = Vec::new();
-    while let Some(item) = cur.next().await { match item {
-        Ok(u) => { if let Some(id)=u.id { out.push(PublicUser{ _id:id, username:u.username, role:u.role }) } },
-        Err(e) => { error!(?e, "users cursor error"); return StatusCode::INTERNAL_SERVER_ERROR.into_response(); }
-    }}
-    Json(out).into_response()
+    match q.fetch(&coll, doc!{}, &USERS_LIST).await {
+        Ok(page) => page.map(|u| PublicUser{ _id: u.id.unwrap_or_default(), username: u.username, role: u.role }).into_response(),
+        Err(e) => e.into_response(),
+    }
 }
 
 pub async fn create_user(State(state): State<AppState>, Json(body): Json<CreateUser>) -> impl IntoResponse {
@@ -63,6 +70,8 @@ pub async fn update_user(State(state): State<AppState>, Path(id): Path<String>,
     match coll.update_one(doc!{"_id": oid}, doc!{"$set": set}, None).await {
         Ok(r) if r.matched_count>0 => StatusCode::NO_CONTENT.into_response(),
         Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        // username_unique
+        Err(e) if e.to_string().contains("E11000") => StatusCode::CONFLICT.into_response(),
         Err(e) => { error!(?e, "update user failed"); StatusCode::INTERNAL_SERVER_ERROR.into_response() }
     }
 }
@@ -0,0 +1,11 @@
+// Разбор внешних источников данных для админских импортов (файлы лежат локально в IMPORT_DIR).
+pub mod osm;
+pub mod off;
+
+// Файл импорта ищется только внутри IMPORT_DIR (по умолчанию `imports`); путь — просто имя файла.
+pub fn resolve_import_path(file: &str) -> Option<std::path::PathBuf> {
+    if file.is_empty() || file.contains("..") || file.contains('/') || file.contains('\\') { return None; }
+    let dir = std::env::var("IMPORT_DIR").unwrap_or_else(|_| "imports".into());
+    let path = std::path::Path::new(&dir).join(file);
+    path.is_file().then_some(path)
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::models::Unit;
+use crate::money::Quantity;
+
+// Товары из дампа Open Food Facts: JSONL (openfoodfacts-products.jsonl) или CSV-выгрузка
+// (на самом деле TSV с заголовком), оба варианта можно сжать gzip. Файл читается потоково.
+
+#[derive(Debug, Clone, Default)]
+pub struct OffProduct {
+    pub code: String,
+    pub name: String,
+    pub brand: Option<String>,
+    pub quantity: Option<String>, // как в OFF: "930 ml", "6 x 100 g"
+    pub image_url: Option<String>,
+    pub categories: Vec<String>, // теги вида "en:milks"
+    pub countries: Vec<String>,  // теги вида "en:russia"
+}
+
+impl OffProduct {
+    // country: тег ("en:russia") или просто название ("russia")
+    pub fn sold_in(&self, country: &str) -> bool {
+        let c = country.trim().to_lowercase();
+        let tag = if c.contains(':') { c } else { format!("en:{}", c) };
+        self.countries.iter().any(|t| t.eq_ignore_ascii_case(&tag))
+    }
+}
+
+enum Format { Jsonl, Tsv }
+
+// Счётчик прочитанных байт исходного (сжатого) файла — для процента выполнения
+struct Counting<R> { inner: R, read: Arc<AtomicU64> }
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+// Calls `f(line_no, bytes_read, product)` for each data line after the first `skip` ones;
+// `product` is None for lines that can't be parsed. Stops early when `f` returns false.
+pub fn read_products(path: &std::path::Path, skip: u64, mut f: impl FnMut(u64, u64, Option<OffProduct>) -> bool) -> Result<()> {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or_default().to_lowercase();
+    let (base, gz) = match name.strip_suffix(".gz") { Some(b) => (b.to_string(), true), None => (name.clone(), false) };
+    let format = if base.ends_with(".jsonl") || base.ends_with(".json") {
+        Format::Jsonl
+    } else if base.ends_with(".csv") || base.ends_with(".tsv") {
+        Format::Tsv
+    } else {
+        bail!("unsupported file type: {}", name)
+    };
+    let read = Arc::new(AtomicU64::new(0));
+    let file = Counting { inner: std::fs::File::open(path)?, read: read.clone() };
+    let reader: Box<dyn BufRead> = if gz {
+        Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let mut lines = reader.lines();
+    let header: Vec<String> = match format {
+        Format::Tsv => match lines.next() { Some(h) => h?.split('\t').map(|s| s.to_string()).collect(), None => return Ok(()) },
+        Format::Jsonl => Vec::new(),
+    };
+    let columns: HashMap<&str, usize> = header.iter().enumerate().map(|(i, h)| (h.as_str(), i)).collect();
+    let mut line_no = 0u64;
+    for line in lines {
+        let line = line?;
+        line_no += 1;
+        if line_no <= skip { continue; }
+        let product = match format {
+            Format::Jsonl => parse_json_line(&line),
+            Format::Tsv => parse_tsv_line(&line, &columns),
+        };
+        if !f(line_no, read.load(Ordering::Relaxed), product) { break; }
+    }
+    Ok(())
+}
+
+fn split_tags(s: &str) -> Vec<String> {
+    s.split(',').map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect()
+}
+
+fn non_empty(s: Option<&str>) -> Option<String> {
+    s.map(|v| v.trim()).filter(|v| !v.is_empty()).map(|v| v.to_string())
+}
+
+// brands: "Простоквашино, Danone" — берём первый
+fn first_brand(s: Option<&str>) -> Option<String> {
+    non_empty(s.and_then(|b| b.split(',').next()))
+}
+
+fn parse_json_line(line: &str) -> Option<OffProduct> {
+    let v: serde_json::Value = serde_json::from_str(line).ok()?;
+    let tags = |key: &str| -> Vec<String> {
+        match &v[key] {
+            serde_json::Value::Array(a) => a.iter().filter_map(|t| t.as_str()).map(|t| t.to_lowercase()).collect(),
+            serde_json::Value::String(s) => split_tags(s),
+            _ => Vec::new(),
+        }
+    };
+    Some(OffProduct {
+        code: v["code"].as_str().map(|s| s.to_string()).or_else(|| v["code"].as_u64().map(|c| c.to_string()))?,
+        name: non_empty(v["product_name_ru"].as_str()).or_else(|| non_empty(v["product_name"].as_str())).unwrap_or_default(),
+        brand: first_brand(v["brands"].as_str()),
+        quantity: non_empty(v["quantity"].as_str()),
+        image_url: non_empty(v["image_front_url"].as_str()).or_else(|| non_empty(v["image_url"].as_str())),
+        categories: tags("categories_tags"),
+        countries: tags("countries_tags"),
+    })
+}
+
+fn parse_tsv_line(line: &str, columns: &HashMap<&str, usize>) -> Option<OffProduct> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let col = |name: &str| columns.get(name).and_then(|i| fields.get(*i)).copied();
+    Some(OffProduct {
+        code: non_empty(col("code"))?,
+        name: non_empty(col("product_name_ru")).or_else(|| non_empty(col("product_name"))).unwrap_or_default(),
+        brand: first_brand(col("brands")),
+        quantity: non_empty(col("quantity")),
+        image_url: non_empty(col("image_url")),
+        categories: col("categories_tags").map(split_tags).unwrap_or_default(),
+        countries: col("countries_tags").map(split_tags).unwrap_or_default(),
+    })
+}
+
+// "930 ml" -> (l, 0.93), "1,5 кг" -> (kg, 1.5), "6 x 100 g" -> (kg, 0.6), "10 шт" -> (pcs, 10)
+pub fn parse_quantity(raw: &str) -> Option<(Unit, Quantity)> {
+    let s: Vec<char> = raw.to_lowercase().replace(',', ".").chars().collect();
+    let mut i = 0;
+    let mut mult = 1.0;
+    while i < s.len() {
+        if !s[i].is_ascii_digit() { i += 1; continue; }
+        let start = i;
+        while i < s.len() && (s[i].is_ascii_digit() || s[i] == '.') { i += 1; }
+        let Ok(n) = s[start..i].iter().collect::<String>().parse::<f64>() else { continue };
+        while i < s.len() && s[i].is_whitespace() { i += 1; }
+        if i < s.len() && matches!(s[i], 'x' | 'х' | '×' | '*') && s.get(i + 1).is_none_or(|c| c.is_whitespace() || c.is_ascii_digit()) {
+            mult *= n;
+            i += 1;
+            continue;
+        }
+        let word_start = i;
+        while i < s.len() && s[i].is_alphabetic() { i += 1; }
+        let word: String = s[word_start..i].iter().collect();
+        let (unit, factor) = match word.as_str() {
+            "ml" | "мл" => (Unit::L, 0.001),
+            "cl" => (Unit::L, 0.01),
+            "l" | "л" | "ltr" | "litre" | "liter" => (Unit::L, 1.0),
+            "g" | "г" | "гр" => (Unit::Kg, 0.001),
+            "kg" | "кг" => (Unit::Kg, 1.0),
+            "pcs" | "pc" | "шт" => (Unit::Pcs, 1.0),
+            _ => continue,
+        };
+        let size = Quantity::from_f64(mult * n * factor);
+        return (size > Quantity::ZERO).then_some((unit, size));
+    }
+    None
+}
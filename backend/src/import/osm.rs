@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+
+use anyhow::{anyhow, bail, Result};
+
+// Магазины из выгрузки OpenStreetMap: только точки (node) с shop=supermarket|convenience.
+// Поддерживаются .osm.pbf (минимальный разбор protobuf без внешних crate'ов) и GeoJSON.
+
+pub const SHOP_KINDS: [&str; 2] = ["supermarket", "convenience"];
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct OsmShop {
+    pub osm_id: String, // "node/<id>"
+    pub shop: String,
+    pub name: String,
+    pub brand: Option<String>,
+    pub addr: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl OsmShop {
+    fn from_tags(osm_id: String, lat: f64, lon: f64, tags: &HashMap<String, String>) -> Option<Self> {
+        let shop = tags.get("shop")?;
+        if !SHOP_KINDS.contains(&shop.as_str()) { return None; }
+        let brand = tags.get("brand").cloned();
+        let name = tags.get("name").cloned().or_else(|| brand.clone())?;
+        let street = [tags.get("addr:street"), tags.get("addr:housenumber")].into_iter().flatten().cloned().collect::<Vec<_>>().join(", ");
+        let addr = [tags.get("addr:city").cloned(), Some(street).filter(|s| !s.is_empty())].into_iter().flatten().collect::<Vec<_>>().join(", ");
+        Some(OsmShop { osm_id, shop: shop.clone(), name, brand, addr, lat, lon })
+    }
+}
+
+pub fn read_shops(path: &std::path::Path) -> Result<Vec<OsmShop>> {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or_default().to_lowercase();
+    // выгрузка региона — сотни мегабайт: pbf читается поблочно, целиком в память не грузится
+    let file = BufReader::new(std::fs::File::open(path)?);
+    if name.ends_with(".osm.pbf") || name.ends_with(".pbf") {
+        read_pbf(file)
+    } else if name.ends_with(".geojson") || name.ends_with(".json") {
+        read_geojson(file)
+    } else {
+        bail!("unsupported file type: {}", name)
+    }
+}
+
+// GeoJSON: FeatureCollection точек; теги либо прямо в properties, либо в properties.tags
+// (так выгружают osmtogeojson и Overpass Turbo).
+pub fn read_geojson(reader: impl Read) -> Result<Vec<OsmShop>> {
+    let v: serde_json::Value = serde_json::from_reader(reader)?;
+    let features = v.get("features").and_then(|f| f.as_array()).ok_or_else(|| anyhow!("not a FeatureCollection"))?;
+    let mut out = Vec::new();
+    for f in features {
+        let geom = &f["geometry"];
+        if geom["type"].as_str() != Some("Point") { continue; }
+        let (Some(lon), Some(lat)) = (geom["coordinates"][0].as_f64(), geom["coordinates"][1].as_f64()) else { continue };
+        let props = &f["properties"];
+        let tag_src = if props["tags"].is_object() { &props["tags"] } else { props };
+        let tags: HashMap<String, String> = tag_src.as_object().map(|m| {
+            m.iter().filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string()))).collect()
+        }).unwrap_or_default();
+        let raw_id = f["id"].as_str().map(|s| s.to_string())
+            .or_else(|| f["id"].as_i64().map(|i| format!("node/{}", i)))
+            .or_else(|| props["@id"].as_str().map(|s| s.to_string()))
+            .or_else(|| props["id"].as_i64().map(|i| format!("node/{}", i)));
+        let Some(osm_id) = raw_id else { continue };
+        if let Some(shop) = OsmShop::from_tags(osm_id, lat, lon, &tags) { out.push(shop); }
+    }
+    Ok(out)
+}
+
+// --- .osm.pbf ---
+// Формат: [u32 BE длина][BlobHeader][Blob]*, Blob содержит zlib-сжатый PrimitiveBlock.
+// Разбираем только то, что нужно для точек: stringtable, nodes и dense nodes.
+// Файл приходит от пользователя, поэтому размеры из заголовков проверяются по пределам формата
+// (заголовок до 64 КиБ, блок до 32 МиБ) до того, как под них выделяется память.
+
+const MAX_HEADER: usize = 64 << 10;
+const MAX_BLOB: usize = 32 << 20;
+
+struct Pb<'a> { buf: &'a [u8], pos: usize }
+
+enum Field<'a> { Varint(u64), Bytes(&'a [u8]) }
+
+impl<'a> Pb<'a> {
+    fn new(buf: &'a [u8]) -> Self { Pb { buf, pos: 0 } }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut out = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self.buf.get(self.pos).ok_or_else(|| anyhow!("truncated varint"))?;
+            self.pos += 1;
+            out |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 { return Ok(out); }
+        }
+        bail!("varint too long")
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|e| *e <= self.buf.len()).ok_or_else(|| anyhow!("truncated field"))?;
+        let s = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(s)
+    }
+
+    // next (field number, value); fixed32/fixed64 are skipped
+    fn next(&mut self) -> Result<Option<(u64, Field<'a>)>> {
+        while self.pos < self.buf.len() {
+            let key = self.varint()?;
+            let (num, wire) = (key >> 3, key & 7);
+            match wire {
+                0 => return Ok(Some((num, Field::Varint(self.varint()?)))),
+                1 => { self.take(8)?; }
+                2 => { let n = self.varint()? as usize; return Ok(Some((num, Field::Bytes(self.take(n)?)))); }
+                5 => { self.take(4)?; }
+                _ => bail!("unsupported wire type {}", wire),
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn zigzag(v: u64) -> i64 { ((v >> 1) as i64) ^ -((v & 1) as i64) }
+
+fn packed(bytes: &[u8]) -> Result<Vec<u64>> {
+    let mut pb = Pb::new(bytes);
+    let mut out = Vec::new();
+    while pb.pos < bytes.len() { out.push(pb.varint()?); }
+    Ok(out)
+}
+
+// true — блок прочитан, false — файл кончился ровно на границе блока
+fn read_block(r: &mut impl Read, buf: &mut Vec<u8>, len: usize, what: &str) -> Result<bool> {
+    buf.clear();
+    let got = r.take(len as u64).read_to_end(buf)?;
+    if got == len { return Ok(true); }
+    if got == 0 { return Ok(false); }
+    bail!("truncated {}", what)
+}
+
+pub fn read_pbf(mut reader: impl Read) -> Result<Vec<OsmShop>> {
+    let mut out = Vec::new();
+    let (mut len_buf, mut header, mut blob) = (Vec::new(), Vec::new(), Vec::new());
+    while read_block(&mut reader, &mut len_buf, 4, "blob length")? {
+        let hlen = u32::from_be_bytes([len_buf[0], len_buf[1], len_buf[2], len_buf[3]]) as usize;
+        if hlen > MAX_HEADER { bail!("blob header too large: {}", hlen); }
+        if !read_block(&mut reader, &mut header, hlen, "blob header")? { bail!("truncated blob header"); }
+        let (mut kind, mut datasize) = (String::new(), 0u64);
+        let mut pb = Pb::new(&header);
+        while let Some((num, f)) = pb.next()? {
+            match (num, f) {
+                (1, Field::Bytes(b)) => kind = String::from_utf8_lossy(b).into_owned(),
+                (3, Field::Varint(v)) => datasize = v,
+                _ => {}
+            }
+        }
+        let datasize = usize::try_from(datasize).ok().filter(|n| *n <= MAX_BLOB).ok_or_else(|| anyhow!("blob too large: {}", datasize))?;
+        if !read_block(&mut reader, &mut blob, datasize, "blob")? { bail!("truncated blob"); }
+        if kind != "OSMData" { continue; }
+        let data = blob_data(&blob)?;
+        read_primitive_block(&data, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn blob_data(blob: &[u8]) -> Result<Vec<u8>> {
+    let mut pb = Pb::new(blob);
+    while let Some((num, f)) = pb.next()? {
+        match (num, f) {
+            (1, Field::Bytes(raw)) => return Ok(raw.to_vec()),
+            (3, Field::Bytes(z)) => {
+                let mut out = Vec::new();
+                flate2::read::ZlibDecoder::new(z).take(MAX_BLOB as u64 + 1).read_to_end(&mut out)?;
+                if out.len() > MAX_BLOB { bail!("blob too large when unpacked"); }
+                return Ok(out);
+            }
+            (4..=7, Field::Bytes(_)) => bail!("unsupported blob compression"),
+            _ => {}
+        }
+    }
+    bail!("empty blob")
+}
+
+fn read_primitive_block(data: &[u8], out: &mut Vec<OsmShop>) -> Result<()> {
+    let mut strings: Vec<String> = Vec::new();
+    let mut groups: Vec<&[u8]> = Vec::new();
+    let (mut granularity, mut lat_offset, mut lon_offset) = (100i64, 0i64, 0i64);
+    let mut pb = Pb::new(data);
+    while let Some((num, f)) = pb.next()? {
+        match (num, f) {
+            (1, Field::Bytes(st)) => {
+                let mut sp = Pb::new(st);
+                while let Some((n, sf)) = sp.next()? {
+                    if let (1, Field::Bytes(s)) = (n, sf) { strings.push(String::from_utf8_lossy(s).into_owned()); }
+                }
+            }
+            (2, Field::Bytes(g)) => groups.push(g),
+            (17, Field::Varint(v)) => granularity = v as i64,
+            (19, Field::Varint(v)) => lat_offset = v as i64,
+            (20, Field::Varint(v)) => lon_offset = v as i64,
+            _ => {}
+        }
+    }
+    let coord = |offset: i64, v: i64| 1e-9 * (offset + granularity * v) as f64;
+    let s = |i: u64| strings.get(i as usize).cloned().unwrap_or_default();
+    for g in groups {
+        let mut gp = Pb::new(g);
+        while let Some((num, f)) = gp.next()? {
+            match (num, f) {
+                // plain Node
+                (1, Field::Bytes(n)) => {
+                    let (mut id, mut lat, mut lon) = (0i64, 0i64, 0i64);
+                    let (mut keys, mut vals) = (Vec::new(), Vec::new());
+                    let mut np = Pb::new(n);
+                    while let Some((nn, nf)) = np.next()? {
+                        match (nn, nf) {
+                            (1, Field::Varint(v)) => id = zigzag(v),
+                            (2, Field::Bytes(b)) => keys = packed(b)?,
+                            (3, Field::Bytes(b)) => vals = packed(b)?,
+                            (8, Field::Varint(v)) => lat = zigzag(v),
+                            (9, Field::Varint(v)) => lon = zigzag(v),
+                            _ => {}
+                        }
+                    }
+                    let tags: HashMap<String, String> = keys.iter().zip(vals.iter()).map(|(k, v)| (s(*k), s(*v))).collect();
+                    if let Some(shop) = OsmShop::from_tags(format!("node/{}", id), coord(lat_offset, lat), coord(lon_offset, lon), &tags) { out.push(shop); }
+                }
+                // DenseNodes: delta-coded ids/coords, keys_vals — пары индексов, узлы разделены нулём
+                (2, Field::Bytes(d)) => {
+                    let (mut ids, mut lats, mut lons, mut kv) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+                    let mut dp = Pb::new(d);
+                    while let Some((dn, df)) = dp.next()? {
+                        match (dn, df) {
+                            (1, Field::Bytes(b)) => ids = packed(b)?,
+                            (8, Field::Bytes(b)) => lats = packed(b)?,
+                            (9, Field::Bytes(b)) => lons = packed(b)?,
+                            (10, Field::Bytes(b)) => kv = packed(b)?,
+                            _ => {}
+                        }
+                    }
+                    let (mut id, mut lat, mut lon) = (0i64, 0i64, 0i64);
+                    let mut kv_iter = kv.into_iter();
+                    for (i, raw_id) in ids.iter().enumerate() {
+                        id += zigzag(*raw_id);
+                        lat += zigzag(*lats.get(i).unwrap_or(&0));
+                        lon += zigzag(*lons.get(i).unwrap_or(&0));
+                        let mut tags: HashMap<String, String> = HashMap::new();
+                        while let Some(k) = kv_iter.next() {
+                            if k == 0 { break; }
+                            let v = kv_iter.next().unwrap_or(0);
+                            tags.insert(s(k), s(v));
+                        }
+                        if tags.is_empty() { continue; }
+                        if let Some(shop) = OsmShop::from_tags(format!("node/{}", id), coord(lat_offset, lat), coord(lon_offset, lon), &tags) { out.push(shop); }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// Нормализация названия для сопоставления: нижний регистр, ё→е, только буквы/цифры
+pub fn normalize_name(s: &str) -> String {
+    s.to_lowercase()
+        .replace('ё', "е")
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+pub fn names_match(a: &str, b: &str) -> bool {
+    let (a, b) = (normalize_name(a), normalize_name(b));
+    !a.is_empty() && !b.is_empty() && (a == b || a.contains(&b) || b.contains(&a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn varint(mut v: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 { out.push(b); return out; }
+            out.push(b | 0x80);
+        }
+    }
+
+    fn zz(v: i64) -> u64 { ((v << 1) ^ (v >> 63)) as u64 }
+
+    fn field_varint(num: u64, v: u64) -> Vec<u8> { [varint(num << 3), varint(v)].concat() }
+
+    fn field_bytes(num: u64, b: &[u8]) -> Vec<u8> { [varint(num << 3 | 2), varint(b.len() as u64), b.to_vec()].concat() }
+
+    fn packed_of(vals: &[u64]) -> Vec<u8> { vals.iter().flat_map(|v| varint(*v)).collect() }
+
+    fn file_block(kind: &str, blob: &[u8]) -> Vec<u8> {
+        let header = [field_bytes(1, kind.as_bytes()), field_varint(3, blob.len() as u64)].concat();
+        [(header.len() as u32).to_be_bytes().to_vec(), header, blob.to_vec()].concat()
+    }
+
+    fn zlib_blob(data: &[u8]) -> Vec<u8> {
+        let mut z = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        z.write_all(data).unwrap();
+        [field_varint(2, data.len() as u64), field_bytes(3, &z.finish().unwrap())].concat()
+    }
+
+    // PrimitiveBlock: три dense-узла (магазин, без тегов, кафе) и обычный узел-магазин
+    fn primitive_block() -> Vec<u8> {
+        let strings = ["", "shop", "supermarket", "name", "Пятёрочка", "amenity", "cafe", "convenience", "brand", "Магнит"];
+        let table: Vec<u8> = strings.iter().flat_map(|s| field_bytes(1, s.as_bytes())).collect();
+        // координаты в единицах granularity: 100 наноградусов
+        let (lats, lons) = ([557_500_000i64, 557_500_100, 557_500_200], [376_200_000i64, 376_200_100, 376_200_200]);
+        let delta = |v: &[i64]| v.iter().scan(0i64, |prev, x| { let d = x - *prev; *prev = *x; Some(zz(d)) }).collect::<Vec<_>>();
+        let dense = [
+            field_bytes(1, &packed_of(&delta(&[10, 11, 12]))),
+            field_bytes(8, &packed_of(&delta(&lats))),
+            field_bytes(9, &packed_of(&delta(&lons))),
+            field_bytes(10, &packed_of(&[1, 2, 3, 4, 0, 0, 5, 6, 0])),
+        ].concat();
+        let node = [
+            field_varint(1, zz(20)),
+            field_bytes(2, &packed_of(&[1, 8])),
+            field_bytes(3, &packed_of(&[7, 9])),
+            field_varint(8, zz(-100)),
+            field_varint(9, zz(200)),
+        ].concat();
+        let group = [field_bytes(2, &dense), field_bytes(1, &node)].concat();
+        [field_bytes(1, &table), field_bytes(2, &group)].concat()
+    }
+
+    fn pbf_file() -> Vec<u8> {
+        // OSMHeader пропускается, данные — сжатый блок
+        [file_block("OSMHeader", &field_bytes(1, b"header")), file_block("OSMData", &zlib_blob(&primitive_block()))].concat()
+    }
+
+    #[test]
+    fn varint_decoding() {
+        for v in [0u64, 1, 127, 128, 300, 1 << 35, u64::MAX] {
+            assert_eq!(Pb::new(&varint(v)).varint().unwrap(), v);
+        }
+        assert_eq!(Pb::new(&[0xac, 0x02]).varint().unwrap(), 300);
+        assert!(Pb::new(&[0x80, 0x80]).varint().is_err());
+        assert!(Pb::new(&[0xff; 11]).varint().is_err());
+        assert_eq!(zigzag(zz(-3)), -3);
+        assert_eq!(zigzag(zz(i64::MIN)), i64::MIN);
+    }
+
+    #[test]
+    fn length_delimited_decoding() {
+        let buf = [field_varint(3, 150), field_bytes(1, b"abc"), vec![(4 << 3) | 5, 1, 2, 3, 4], field_bytes(2, b"")].concat();
+        let mut pb = Pb::new(&buf);
+        assert!(matches!(pb.next().unwrap(), Some((3, Field::Varint(150)))));
+        assert!(matches!(pb.next().unwrap(), Some((1, Field::Bytes(b"abc")))));
+        // fixed32 пропускается
+        assert!(matches!(pb.next().unwrap(), Some((2, Field::Bytes(b"")))));
+        assert!(pb.next().unwrap().is_none());
+        // длина больше буфера, в том числе с переполнением
+        assert!(Pb::new(&[(1 << 3) | 2, 5, b'a']).next().is_err());
+        assert!(Pb::new(&[&[(1 << 3) | 2][..], &varint(u64::MAX)].concat()).next().is_err());
+        assert!(Pb::new(&[(1 << 3) | 3]).next().is_err());
+    }
+
+    #[test]
+    fn dense_and_plain_nodes() {
+        let shops = read_pbf(&pbf_file()[..]).unwrap();
+        assert_eq!(shops.len(), 2);
+        let dense = &shops[0];
+        assert_eq!((dense.osm_id.as_str(), dense.shop.as_str(), dense.name.as_str()), ("node/10", "supermarket", "Пятёрочка"));
+        assert!((dense.lat - 55.75).abs() < 1e-9 && (dense.lon - 37.62).abs() < 1e-9);
+        let plain = &shops[1];
+        assert_eq!((plain.osm_id.as_str(), plain.shop.as_str(), plain.name.as_str(), plain.brand.as_deref()), ("node/20", "convenience", "Магнит", Some("Магнит")));
+        assert!((plain.lat + 1e-5).abs() < 1e-12 && (plain.lon - 2e-5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn malformed_files() {
+        assert!(read_pbf(&[][..]).unwrap().is_empty());
+        let file = pbf_file();
+        // обрыв в длине, заголовке и блоке
+        for cut in [2, 6, file.len() - 1] { assert!(read_pbf(&file[..cut]).is_err(), "cut at {cut}"); }
+        // размеры из заголовков больше пределов формата
+        let huge = [field_bytes(1, b"OSMData"), field_varint(3, u64::MAX)].concat();
+        assert!(read_pbf(&[(huge.len() as u32).to_be_bytes().to_vec(), huge].concat()[..]).is_err());
+        assert!(read_pbf(&u32::MAX.to_be_bytes()[..]).is_err());
+        // мусор внутри блока и неподдерживаемое сжатие
+        assert!(read_pbf(&file_block("OSMData", &[0x0f, 0x00])[..]).is_err());
+        assert!(read_pbf(&file_block("OSMData", &field_bytes(4, b"lzma"))[..]).is_err());
+        assert!(read_pbf(&file_block("OSMData", &field_bytes(3, b"not zlib"))[..]).is_err());
+    }
+
+    #[test]
+    fn name_matching() {
+        assert_eq!(normalize_name("  Пятёрочка №12!"), "пятерочка12");
+        assert!(names_match("Пятёрочка", "ПЯТЕРОЧКА"));
+        assert!(names_match("Магнит у дома", "магнит"));
+        assert!(!names_match("Магнит", "Пятёрочка"));
+        assert!(!names_match("", ""));
+        assert!(!names_match("!!!", "Магнит"));
+    }
+}
@@ -13,6 +13,11 @@ mod telegram; // legacy telegram module
 mod db; // новый модуль для БД (логическая декомпозиция)
 mod services; // слой сервисов
 mod router; // новый сборщик маршрутов
+mod import; // разбор файлов для админских импортов (OSM и т.п.)
+mod money; // денежные суммы в копейках (Int64 в БД, рубли в API)
+mod search; // полнотекстовый поиск товаров (индекс в памяти)
+mod stats; // медиана, квантили и выбросы для аналитики цен
+mod cache; // кэш ответов публичных GET с ETag
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -31,6 +36,10 @@ async fn main() -> anyhow::Result<()> {
     // Start Telegram poller (runs only when enabled in settings)
     // TODO: вынести в отдельный сервис позже (оркестрация интеграций)
     let _tg_handle = services::telegram::spawn_poller(state.clone());
+    // окончательное удаление из корзины по истечении TRASH_RETENTION_DAYS
+    let _purge_handle = handlers::trash::spawn_purger(state.clone());
+    // сводки цен товаров: заполнить, если коллекция ещё пустая
+    let _price_stats_handle = handlers::price_stats::spawn_backfill(state.clone());
 
     // Новый способ сборки маршрутов с логическими "микросервисными" неймспейсами
     // и сохранением всех текущих endpoint-ов без изменений путей
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use bson::oid::ObjectId;
+use bson::{oid::ObjectId, Document};
+use crate::money::{Money, Quantity};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Product {
@@ -12,6 +13,57 @@ pub struct Product {
     pub image_url: Option<String>,
     #[serde(default)]
     pub category_ids: Vec<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<Unit>,
+    // amount in one pack, in `unit` (0.93 for a 930 ml carton); none with kg/l means sold by weight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pack_size: Option<Quantity>,
+    // EAN-8/EAN-13/GTIN-14, normalized (UPC-A stored as EAN-13); unique across products
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub barcodes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    // category tags from an external catalog (Open Food Facts "en:milks"), used to pick categories
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub category_hints: Vec<String>,
+    // fields last written by an import; a manual edit removes the field from here, and imports
+    // only touch fields that are empty or still listed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub imported_fields: Vec<String>,
+    // other names of the product, e.g. titles of duplicates merged into it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    // soft deletion: ms since epoch and username; cleared by restore, purged after the retention period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+// Единица измерения товара; цена за единицу считается за 1 шт / 1 кг / 1 л
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit { Pcs, Kg, L }
+
+impl Product {
+    // sticker price -> price per 1 kg / 1 l / 1 pcs
+    pub fn unit_price(&self, price: Money) -> Option<Money> {
+        self.unit?;
+        match self.pack_size {
+            Some(size) => price.per(size),
+            // weighed goods are already priced per kg/l; pcs without a pack size is one piece
+            None => Some(price),
+        }
+    }
+}
+
+// Old id of a product merged into another one; get_product follows it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProductRedirect {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub to: ObjectId,
+    pub ts_ms: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +74,14 @@ pub struct ProductCreate {
     pub image_url: Option<String>,
     #[serde(default)]
     pub category_ids: Vec<String>,
+    #[serde(default)]
+    pub unit: Option<Unit>,
+    #[serde(default)]
+    pub pack_size: Option<Quantity>,
+    #[serde(default)]
+    pub barcodes: Vec<String>,
+    #[serde(default)]
+    pub brand: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -30,6 +90,11 @@ pub struct ProductUpdate {
     pub desc: Option<String>,
     pub image_url: Option<String>,
     pub category_ids: Option<Vec<String>>, // replace full set if provided
+    pub unit: Option<Unit>,
+    pub pack_size: Option<Quantity>, // 0 clears it (product becomes sold by weight)
+    pub barcodes: Option<Vec<String>>, // replace full set if provided
+    pub brand: Option<String>, // "" clears it
+    pub aliases: Option<Vec<String>>, // replace full set if provided
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -42,6 +107,39 @@ pub struct Store {
     pub desc: String,
     #[serde(default)]
     pub image_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<GeoPoint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    // source object from an OpenStreetMap import, e.g. "node/123456"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osm_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region_id: Option<ObjectId>,
+    // soft deletion, see Product
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+// GeoJSON Point; coordinates are [lon, lat] as required by the 2dsphere index
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeoPoint {
+    #[serde(rename = "type")]
+    pub kind: String, // always "Point"
+    pub coordinates: [f64; 2],
+}
+
+impl GeoPoint {
+    pub fn new(lat: f64, lon: f64) -> Option<Self> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) { return None; }
+        Some(GeoPoint { kind: "Point".into(), coordinates: [lon, lat] })
+    }
+    pub fn lat(&self) -> f64 { self.coordinates[1] }
+    pub fn lon(&self) -> f64 { self.coordinates[0] }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -52,6 +150,16 @@ pub struct StoreCreate {
     pub desc: String,
     #[serde(default)]
     pub image_url: Option<String>,
+    #[serde(default)]
+    pub lat: Option<f64>,
+    #[serde(default)]
+    pub lon: Option<f64>,
+    #[serde(default)]
+    pub brand: Option<String>,
+    #[serde(default)]
+    pub chain_id: Option<String>,
+    #[serde(default)]
+    pub region_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -60,6 +168,62 @@ pub struct StoreUpdate {
     pub addr: Option<String>,
     pub desc: Option<String>,
     pub image_url: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub brand: Option<String>,
+    pub chain_id: Option<String>, // "" — detach from chain
+    pub region_id: Option<String>, // "" — detach from region
+}
+
+// Store chains ("Пятёрочка", "Магнит"): stores reference them via chain_id
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoreChain {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub desc: String,
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreChainCreate {
+    pub name: String,
+    #[serde(default)]
+    pub desc: String,
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct StoreChainUpdate {
+    pub name: Option<String>,
+    pub desc: Option<String>,
+    pub image_url: Option<String>,
+}
+
+// Regions/cities: insight stats (avg/min/max/cheapest) are computed within a region
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Region {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub desc: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegionCreate {
+    pub name: String,
+    #[serde(default)]
+    pub desc: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RegionUpdate {
+    pub name: Option<String>,
+    pub desc: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -71,6 +235,11 @@ pub struct Category {
     pub desc: String,
     #[serde(default)]
     pub parent_ids: Vec<ObjectId>,
+    // soft deletion, see Product
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -95,7 +264,7 @@ pub struct StoreItem {
     pub id: Option<ObjectId>,
     pub store_id: ObjectId,
     pub product_id: ObjectId,
-    pub price: f64,
+    pub price: Money,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -108,7 +277,7 @@ pub struct StoreActivity {
     pub kind: String, // item_added | price_set | price_updated | item_removed
     pub ts_ms: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub price: Option<f64>,
+    pub price: Option<Money>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub product_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -116,10 +285,10 @@ pub struct StoreActivity {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct StoreItemCreate { pub product_id: String, pub price: f64 }
+pub struct StoreItemCreate { pub product_id: String, pub price: Money }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct StoreItemUpdate { pub price: f64 }
+pub struct StoreItemUpdate { pub price: Money }
 
 // Auth
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -214,23 +383,78 @@ pub struct EventDoc {
     pub user: Option<String>,
 }
 
+// Admin write recorded by the audit middleware (handlers::audit)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub ts_ms: i64,
+    pub actor: String,
+    pub action: String, // create | update | delete | restore | merge | add_item | ...
+    // collection of the changed document; none for bulk actions (imports, dev seed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    // top-level fields that changed: {field: {from, to}}, values as stored (money in kopecks)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Document>,
+    // merge: products merged into entity_id (deleted, their ids redirect to it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merged_ids: Option<Vec<String>>,
+}
+
+// Price summary of a product across stores (product_price_stats, _id — product id), kept by handlers::price_stats
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProductPriceStats {
+    #[serde(rename = "_id")]
+    pub product_id: ObjectId,
+    pub store_count: i64,
+    pub min: Money,
+    pub median: Money,
+    pub avg: Money,
+    pub max: Money,
+    pub cheapest_store_id: ObjectId,
+    // latest price history entry of the product in any store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_change: Option<PriceChange>,
+    pub updated_ts_ms: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceChange {
+    pub store_id: ObjectId,
+    pub kind: String, // item_added | price_updated | price_set | item_removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<Money>,
+    pub ts_ms: i64,
+}
+
 // Operations (created from receipts)
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OperationItem {
     pub name: String,
-    pub price: f64,
-    pub quantity: f64,
+    pub price: Money,
+    pub quantity: Quantity,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub product_id: Option<ObjectId>,
 }
 
+impl OperationItem {
+    // весовая позиция чека: дробное количество — это вес в кг (или объём в л)
+    pub fn is_weighed(&self) -> bool { self.quantity.minor() % 1000 != 0 }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Operation {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub date: String,
     pub seller: String,
-    pub amount: f64,
+    pub amount: Money,
     pub items: Vec<OperationItem>,
     pub status: String, // draft | posted | deleted
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -242,3 +466,27 @@ pub struct Operation {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub raw: Option<String>,
 }
+
+// Фоновый импорт (Open Food Facts и т.п.): прогресс и точка продолжения
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportJob {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub kind: String, // off
+    pub file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    pub status: String, // running | done | failed | interrupted
+    // data lines processed; a resumed job skips that many lines
+    pub lines_done: u64,
+    pub bytes_read: u64,
+    pub bytes_total: u64,
+    pub created: u64,
+    pub enriched: u64,
+    pub unchanged: u64,
+    pub skipped: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub started_ms: i64,
+    pub updated_ms: i64,
+}
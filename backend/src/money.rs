@@ -0,0 +1,243 @@
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub};
+
+use serde::{de, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+// Денежные суммы и количества в целых минимальных единицах (копейки, тысячные доли).
+// В MongoDB лежат как Int64, в JSON API — как раньше, числом в рублях (`99.9`).
+// Формат выбирается по is_human_readable(): serde_json — true, BSON драйвера — false.
+// Поэтому документы с этими полями нельзя собирать через bson::to_bson/to_document
+// (они human-readable по умолчанию) — только через to_bson_raw или Bson::from.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Fixed<const SCALE: i64>(i64);
+
+// рубли, хранятся в копейках
+pub type Money = Fixed<100>;
+// количество в чеке (штуки или кг), хранится в тысячных
+pub type Quantity = Fixed<1000>;
+
+impl<const SCALE: i64> Fixed<SCALE> {
+    pub const ZERO: Self = Fixed(0);
+
+    pub fn from_minor(v: i64) -> Self { Fixed(v) }
+    pub fn minor(self) -> i64 { self.0 }
+
+    // 12.345 -> 1235 копеек; используется только на границе с JSON и для старых данных
+    pub fn from_f64(v: f64) -> Self { Fixed((v * SCALE as f64).round() as i64) }
+    pub fn to_f64(self) -> f64 { self.0 as f64 / SCALE as f64 }
+
+    // среднее с округлением до минимальной единицы (половина — от нуля)
+    pub fn avg(values: &[Self]) -> Option<Self> {
+        if values.is_empty() { return None; }
+        let sum: i128 = values.iter().map(|v| v.0 as i128).sum();
+        Some(Fixed(div_round(sum, values.len() as i128) as i64))
+    }
+
+    // сумма / n с тем же округлением, что и в avg
+    pub fn div_count(self, n: u64) -> Option<Self> {
+        if n == 0 { None } else { Some(Fixed(div_round(self.0 as i128, n as i128) as i64)) }
+    }
+
+    // то же, что percentile(.., 0.5): медиана везде считается одинаково
+    pub fn median(values: &mut [Self]) -> Option<Self> {
+        values.sort();
+        Self::percentile(values, 0.5)
+    }
+
+    // p-квантиль (0..=1) отсортированного списка с линейной интерполяцией между соседями;
+    // доля между соседями — в миллионных, округление как в avg
+    pub fn percentile(sorted: &[Self], p: f64) -> Option<Self> {
+        if sorted.is_empty() || !(0.0..=1.0).contains(&p) { return None; }
+        let pos = p * (sorted.len() - 1) as f64;
+        let (lo, hi) = (sorted[pos.floor() as usize].0 as i128, sorted[pos.ceil() as usize].0 as i128);
+        let w = (pos.fract() * 1e6).round() as i128;
+        Some(Fixed(div_round(lo * (1_000_000 - w) + hi * w, 1_000_000) as i64))
+    }
+
+    // отношение двух сумм (индексы цен, отклонения от медианы)
+    pub fn ratio(self, other: Self) -> Option<f64> {
+        if other.0 == 0 { None } else { Some(self.0 as f64 / other.0 as f64) }
+    }
+}
+
+impl Money {
+    // цена за единицу: цена упаковки / размер упаковки (в тысячных)
+    pub fn per(self, qty: Quantity) -> Option<Money> {
+        if qty.0 <= 0 { return None; }
+        Some(Fixed(div_round(self.0 as i128 * 1000, qty.0 as i128) as i64))
+    }
+
+    // сумма позиции чека: цена × количество, округлённая до копейки
+    pub fn times(self, qty: Quantity) -> Money {
+        Fixed(div_round(self.0 as i128 * qty.0 as i128, 1000) as i64)
+    }
+}
+
+fn div_round(n: i128, d: i128) -> i128 {
+    let q = n / d;
+    let r = n % d;
+    if r.abs() * 2 >= d.abs() { q + n.signum() * d.signum() } else { q }
+}
+
+impl<const SCALE: i64> Add for Fixed<SCALE> { type Output = Self; fn add(self, o: Self) -> Self { Fixed(self.0 + o.0) } }
+impl<const SCALE: i64> Sub for Fixed<SCALE> { type Output = Self; fn sub(self, o: Self) -> Self { Fixed(self.0 - o.0) } }
+impl<const SCALE: i64> AddAssign for Fixed<SCALE> { fn add_assign(&mut self, o: Self) { self.0 += o.0; } }
+impl<const SCALE: i64> Sum for Fixed<SCALE> { fn sum<I: Iterator<Item = Self>>(iter: I) -> Self { Fixed(iter.map(|v| v.0).sum()) } }
+impl<'a, const SCALE: i64> Sum<&'a Fixed<SCALE>> for Fixed<SCALE> { fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self { Fixed(iter.map(|v| v.0).sum()) } }
+
+impl<const SCALE: i64> fmt::Display for Fixed<SCALE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = (SCALE as f64).log10() as usize;
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{}{}.{:0width$}", sign, abs / SCALE as u64, abs % SCALE as u64, width = digits)
+    }
+}
+
+impl<const SCALE: i64> From<Fixed<SCALE>> for bson::Bson {
+    fn from(v: Fixed<SCALE>) -> Self { bson::Bson::Int64(v.0) }
+}
+
+impl<const SCALE: i64> Serialize for Fixed<SCALE> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() { serializer.serialize_f64(self.to_f64()) } else { serializer.serialize_i64(self.0) }
+    }
+}
+
+struct FixedVisitor<const SCALE: i64> { human_readable: bool }
+
+impl<'de, const SCALE: i64> de::Visitor<'de> for FixedVisitor<SCALE> {
+    type Value = Fixed<SCALE>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("a number") }
+
+    // JSON: целое — это рубли; BSON: Int32/Int64 — уже минимальные единицы
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        if self.human_readable { v.checked_mul(SCALE).map(Fixed).ok_or_else(|| E::custom("amount overflow")) } else { Ok(Fixed(v)) }
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        let v = i64::try_from(v).map_err(|_| E::custom("amount overflow"))?;
+        self.visit_i64(v)
+    }
+
+    // дробное число всегда в рублях: JSON API или документ, ещё не прошедший миграцию
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        if !v.is_finite() { return Err(E::custom("amount is not finite")); }
+        Ok(Fixed::from_f64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let parsed: f64 = v.trim().replace(',', ".").parse().map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))?;
+        self.visit_f64(parsed)
+    }
+}
+
+impl<'de, const SCALE: i64> Deserialize<'de> for Fixed<SCALE> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let human_readable = deserializer.is_human_readable();
+        deserializer.deserialize_any(FixedVisitor::<SCALE> { human_readable })
+    }
+}
+
+// bson::to_bson в режиме драйвера (через сырой сериализатор): Money/Quantity попадают в документ как Int64
+pub fn to_bson_raw<T: Serialize + ?Sized>(value: &T) -> bson::ser::Result<bson::Bson> {
+    #[derive(Serialize)]
+    struct Wrap<'a, T: ?Sized> { v: &'a T }
+    let raw = bson::to_raw_document_buf(&Wrap { v: value })?;
+    let mut doc = raw.to_document().map_err(|e| bson::ser::Error::custom(e.to_string()))?;
+    Ok(doc.remove("v").unwrap_or(bson::Bson::Null))
+}
+
+// обратное к to_bson_raw: документ из агрегации в структуру с Money/Quantity из Int64
+pub fn from_document_raw<T: serde::de::DeserializeOwned>(doc: &bson::Document) -> bson::de::Result<T> {
+    let bytes = bson::to_vec(doc).map_err(|e| <bson::de::Error as de::Error>::custom(e.to_string()))?;
+    bson::from_slice(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Line {
+        price: Money,
+        quantity: Quantity,
+        old: Option<Money>,
+    }
+
+    fn line() -> Line { Line { price: Money::from_minor(9990), quantity: Quantity::from_minor(1500), old: None } }
+
+    #[test]
+    fn raw_bson_round_trip() {
+        let Bson::Document(d) = to_bson_raw(&line()).unwrap() else { panic!("not a document") };
+        assert_eq!(d, doc!{"price": 9990i64, "quantity": 1500i64, "old": Bson::Null});
+        assert_eq!(from_document_raw::<Line>(&d).unwrap(), line());
+        assert_eq!(Bson::from(Money::from_minor(-5)), Bson::Int64(-5));
+    }
+
+    #[test]
+    fn human_readable_bson_is_in_rubles() {
+        // поэтому документы с деньгами нельзя собирать через to_document
+        let d = bson::to_document(&line()).unwrap();
+        assert_eq!(d.get("price"), Some(&Bson::Double(99.9)));
+        assert_eq!(d.get("quantity"), Some(&Bson::Double(1.5)));
+        assert_eq!(bson::from_document::<Line>(d).unwrap(), line());
+    }
+
+    #[test]
+    fn stored_documents_before_and_after_migration() {
+        // ещё не мигрированный документ: дробные рубли
+        assert_eq!(from_document_raw::<Line>(&doc!{"price": 99.9, "quantity": 1.5}).unwrap(), line());
+        // после migrate_money ($toLong) — Int64, целые из старых данных — Int32: уже минимальные единицы
+        assert_eq!(from_document_raw::<Line>(&doc!{"price": 9990i64, "quantity": 1500i32}).unwrap(), line());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = serde_json::to_string(&line()).unwrap();
+        assert_eq!(json, r#"{"price":99.9,"quantity":1.5,"old":null}"#);
+        assert_eq!(serde_json::from_str::<Line>(&json).unwrap(), line());
+        // целое в JSON — рубли, строка с запятой — тоже
+        let parsed: Line = serde_json::from_str(r#"{"price":100,"quantity":"1,5","old":"0.05"}"#).unwrap();
+        assert_eq!((parsed.price.minor(), parsed.quantity.minor(), parsed.old), (10000, 1500, Some(Money::from_minor(5))));
+        assert!(serde_json::from_str::<Money>("92233720368547759").is_err());
+        assert!(serde_json::from_str::<Money>(r#""abc""#).is_err());
+    }
+
+    #[test]
+    fn rounding_is_half_away_from_zero() {
+        assert_eq!(Money::from_f64(12.345).minor(), 1235);
+        assert_eq!(Money::from_f64(-12.345).minor(), -1235);
+        assert_eq!(Money::avg(&[Money::from_minor(1), Money::from_minor(2)]), Some(Money::from_minor(2)));
+        assert_eq!(Money::avg(&[Money::from_minor(-1), Money::from_minor(-2)]), Some(Money::from_minor(-2)));
+        assert_eq!(Money::avg(&[Money::from_minor(-1), Money::from_minor(-1), Money::from_minor(-2)]), Some(Money::from_minor(-1)));
+        assert_eq!(Money::avg(&[]), None);
+        assert_eq!(Money::from_minor(-7).div_count(2), Some(Money::from_minor(-4)));
+        assert_eq!(Money::from_minor(7).div_count(0), None);
+    }
+
+    #[test]
+    fn times_and_per() {
+        let kg = |v: f64| Quantity::from_f64(v);
+        // 99.99 × 1.5 = 149.985
+        assert_eq!(Money::from_f64(99.99).times(kg(1.5)), Money::from_minor(14999));
+        assert_eq!(Money::from_f64(-0.01).times(kg(0.5)), Money::from_minor(-1));
+        assert_eq!(Money::from_f64(10.0).times(Quantity::ZERO), Money::ZERO);
+        assert_eq!(Money::from_f64(50.0).per(kg(0.5)), Some(Money::from_f64(100.0)));
+        assert_eq!(Money::from_f64(100.0).per(kg(0.3)), Some(Money::from_f64(333.33)));
+        assert_eq!(Money::from_f64(-1.0).per(kg(3.0)), Some(Money::from_minor(-33)));
+        assert_eq!(Money::from_f64(100.0).per(Quantity::ZERO), None);
+        assert_eq!(Money::from_f64(100.0).per(kg(-1.0)), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Money::from_minor(9990).to_string(), "99.90");
+        assert_eq!(Money::from_minor(-5).to_string(), "-0.05");
+        assert_eq!(Quantity::from_minor(1500).to_string(), "1.500");
+    }
+}
@@ -1,7 +1,7 @@
 use axum::{Router, routing::{get, post, put}};
 use tower_http::cors::{Any, CorsLayer};
 use axum::http::HeaderValue;
-use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
 use tower_http::services::ServeDir;
 
 use crate::handlers;
@@ -18,23 +18,30 @@ pub fn build(state: AppState, uploads_dir: String) -> Router {
                 CorsLayer::new()
                     .allow_origin(Any)
                     .allow_methods(Any)
-                    .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT])
+                    .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT, IF_NONE_MATCH])
+                    .expose_headers([ETAG])
             } else {
                 CorsLayer::new()
                     .allow_origin(origins)
                     .allow_methods(Any)
-                    .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT])
+                    .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT, IF_NONE_MATCH])
+                    .expose_headers([ETAG])
             }
         }
         Err(_) => CorsLayer::new()
             .allow_origin(Any)
             .allow_methods(Any)
-            .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT]),
+            .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT, IF_NONE_MATCH])
+            .expose_headers([ETAG]),
     };
 
     let static_service = ServeDir::new(&uploads_dir);
 
     let admin_guard = axum::middleware::from_fn_with_state(state.clone(), handlers::auth::require_admin);
+    // runs inside the guard: needs the principal it sets
+    let audit = axum::middleware::from_fn_with_state(state.clone(), handlers::audit::audit);
+    // wraps both routers: admin writes invalidate cached public reads
+    let cache = axum::middleware::from_fn_with_state(state.clone(), crate::cache::http_cache);
 
     let public = Router::new()
         .route("/healthz", get(handlers::health::health))
@@ -43,16 +50,34 @@ pub fn build(state: AppState, uploads_dir: String) -> Router {
         .route("/users/link_telegram/start", post(handlers::users::start_telegram_link))
         .route("/users/link_telegram/status", get(handlers::users::telegram_link_status))
         .route("/users/link_telegram/unlink", post(handlers::users::unlink_telegram))
+        .route("/suggest", get(handlers::suggest::suggest))
         .route("/products", get(handlers::products::list_products))
+        .route("/products/by-barcode/:code", get(handlers::products::get_product_by_barcode))
+        .route("/products/search", get(handlers::products::search_products))
         .route("/products/:id", get(handlers::products::get_product))
         .route("/products/:id/insights", get(handlers::insights::list_product_insights))
         .route("/categories", get(handlers::categories::list_categories))
+        .route("/categories/tree", get(handlers::categories::get_category_tree))
         .route("/categories/:id", get(handlers::categories::get_category))
+        .route("/categories/:id/ancestors", get(handlers::categories::list_category_ancestors))
+        .route("/categories/:id/descendants", get(handlers::categories::list_category_descendants))
+        .route("/categories/:id/products", get(handlers::categories::list_category_products))
         .route("/stores", get(handlers::stores::list_stores))
+        .route("/stores/nearby", get(handlers::stores::list_nearby_stores))
         .route("/stores/:id", get(handlers::stores::get_store))
         .route("/stores/:id/products", get(handlers::stores::list_store_products))
         .route("/stores/:id/products/insights", get(handlers::insights::list_store_product_insights))
         .route("/stores/:id/activities", get(handlers::activities::list_store_activities))
+        .route("/regions", get(handlers::regions::list_regions))
+        .route("/regions/compare", get(handlers::regions::compare_regions))
+        .route("/regions/:id", get(handlers::regions::get_region))
+        .route("/chains", get(handlers::chains::list_chains))
+        .route("/chains/rank", get(handlers::chains::rank_chains))
+        .route("/chains/:id", get(handlers::chains::get_chain))
+        .route("/chains/:id/stores", get(handlers::chains::list_chain_stores))
+        .route("/chains/:id/prices", get(handlers::chains::list_chain_prices))
+        .route("/chains/:id/uniformity", get(handlers::chains::get_chain_uniformity))
+        .route("/heatmap", get(handlers::heatmap::get_heatmap))
         .route("/activities", get(handlers::activities::list_all_activities))
         .route("/events", get(handlers::events::list_events))
         .route("/ratings/users", get(handlers::ratings::list_user_ratings))
@@ -62,13 +87,30 @@ pub fn build(state: AppState, uploads_dir: String) -> Router {
     let admin = Router::new()
         .route("/products", post(handlers::products::create_product))
         .route("/products/:id", put(handlers::products::update_product).delete(handlers::products::delete_product))
+        .route("/products/duplicates", get(handlers::duplicates::list_duplicate_candidates))
+        .route("/products/:id/merge", post(handlers::duplicates::merge_products))
+        .route("/products/:id/restore", post(handlers::products::restore_product))
         .route("/products/:id/categories/:cat_id", post(handlers::products::add_product_category).delete(handlers::products::remove_product_category))
         .route("/categories", post(handlers::categories::create_category))
         .route("/categories/:id", put(handlers::categories::update_category).delete(handlers::categories::delete_category))
+        .route("/categories/:id/restore", post(handlers::categories::restore_category))
         .route("/stores", post(handlers::stores::create_store))
         .route("/stores/:id", put(handlers::stores::update_store).delete(handlers::stores::delete_store))
+        .route("/stores/:id/restore", post(handlers::stores::restore_store))
         .route("/stores/:id/products", post(handlers::stores::add_store_product))
         .route("/stores/:id/products/:product_id", put(handlers::stores::update_store_product).delete(handlers::stores::remove_store_product))
+        .route("/audit", get(handlers::audit::list_audit))
+        .route("/trash", get(handlers::trash::list_trash))
+        .route("/trash/purge", post(handlers::trash::purge_trash))
+        .route("/integrity", get(handlers::integrity::check_integrity))
+        .route("/integrity/repair", post(handlers::integrity::repair_integrity))
+        .route("/prices/rebuild", get(handlers::prices::preview_rebuild).post(handlers::prices::apply_rebuild))
+        .route("/prices/stats/rebuild", post(handlers::price_stats::rebuild_price_stats))
+        .route("/regions", post(handlers::regions::create_region))
+        .route("/regions/:id", put(handlers::regions::update_region).delete(handlers::regions::delete_region))
+        .route("/chains", post(handlers::chains::create_chain))
+        .route("/chains/assign", post(handlers::chains::assign_chain))
+        .route("/chains/:id", put(handlers::chains::update_chain).delete(handlers::chains::delete_chain))
         .route("/settings/telegram", get(handlers::settings::get_telegram).put(handlers::settings::put_telegram))
         .route("/users", get(handlers::users::list_users).post(handlers::users::create_user))
         .route("/users/:id", put(handlers::users::update_user).delete(handlers::users::delete_user))
@@ -81,9 +123,15 @@ pub fn build(state: AppState, uploads_dir: String) -> Router {
         .route("/operations/:id/status", put(handlers::operations::update_status))
         .route("/export", get(handlers::export::export_all))
         .route("/import", post(handlers::export::import_all))
+        .route("/import/osm", post(handlers::imports::import_osm_stores))
+        .route("/import/off", post(handlers::imports::start_off_import))
+        .route("/import/jobs", get(handlers::imports::list_import_jobs))
+        .route("/import/jobs/:id", get(handlers::imports::get_import_job))
+        .route("/import/jobs/:id/resume", post(handlers::imports::resume_import_job))
         .route("/upload", post(handlers::uploads::upload_file))
         .with_state(state.clone())
+        .layer(audit)
         .layer(admin_guard);
 
-    public.merge(admin).layer(cors)
+    public.merge(admin).layer(cache).layer(cors)
 }
@@ -0,0 +1,508 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::RwLock;
+
+use bson::{doc, oid::ObjectId};
+use futures::stream::StreamExt;
+use mongodb::Collection;
+use rust_stemmers::{Algorithm, Stemmer};
+use tracing::info;
+
+use crate::models::{Category, Product, Store};
+use crate::state::AppState;
+
+// Полнотекстовый поиск по товарам в памяти процесса. Индекс строится при старте из `products`
+// и обновляется обработчиками, которые меняют товары (upsert/remove/refresh).
+// Термы — основы слов (Snowball: русский для кириллицы, английский для латиницы), «ё» = «е».
+// Опечатки: основы из словаря на расстоянии Дамерау—Левенштейна 1 (от 4 букв) или 2 (от 8).
+
+// вес поля: совпадение в названии важнее, чем в описании
+const W_TITLE: f64 = 3.0;
+const W_ALIAS: f64 = 2.0;
+const W_BRAND: f64 = 1.5;
+const W_DESC: f64 = 0.5;
+const W_BARCODE: f64 = 10.0;
+
+// множитель за неточное совпадение терма
+const Q_PREFIX: f64 = 0.8;
+const Q_TYPO_1: f64 = 0.6;
+const Q_TYPO_2: f64 = 0.4;
+
+struct Entry {
+    terms: Vec<String>,
+    category_ids: Vec<ObjectId>,
+    title: String,
+}
+
+#[derive(Default)]
+struct Inner {
+    // терм -> товар -> наибольший вес поля, где он встретился
+    postings: HashMap<String, HashMap<ObjectId, f64>>,
+    docs: HashMap<ObjectId, Entry>,
+}
+
+pub struct ProductIndex {
+    inner: RwLock<Inner>,
+    ru: Stemmer,
+    en: Stemmer,
+}
+
+pub struct Hit {
+    pub id: ObjectId,
+    pub score: f64,
+}
+
+impl Default for ProductIndex {
+    fn default() -> Self {
+        ProductIndex { inner: RwLock::new(Inner::default()), ru: Stemmer::create(Algorithm::Russian), en: Stemmer::create(Algorithm::English) }
+    }
+}
+
+// Слова в нижнем регистре, «ё» -> «е», «3,2» -> «3.2», «930мл» -> «930», «мл»
+pub fn words(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.to_lowercase().replace('ё', "е").chars().collect();
+    let mut out: Vec<String> = Vec::new();
+    let mut cur = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        let decimal_sep = (*c == ',' || *c == '.') && i > 0 && chars[i - 1].is_ascii_digit() && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit());
+        if decimal_sep { cur.push('.'); continue; }
+        if !c.is_alphanumeric() {
+            if !cur.is_empty() { out.push(std::mem::take(&mut cur)); }
+            continue;
+        }
+        if cur.chars().last().is_some_and(|p| p.is_ascii_digit() != c.is_ascii_digit() && !(p == '.' && c.is_ascii_digit())) {
+            out.push(std::mem::take(&mut cur));
+        }
+        cur.push(*c);
+    }
+    if !cur.is_empty() { out.push(cur); }
+    out
+}
+
+impl ProductIndex {
+    pub async fn load(products: &Collection<Product>) -> mongodb::error::Result<Self> {
+        let index = ProductIndex::default();
+        let n = index.rebuild(products).await?;
+        info!(products = n, "search index built");
+        Ok(index)
+    }
+
+    // полная перестройка — после массовых операций (импорт дампа, генерация тестовых данных)
+    pub async fn rebuild(&self, products: &Collection<Product>) -> mongodb::error::Result<usize> {
+        let mut fresh = Inner::default();
+        let mut cursor = products.find(None, None).await?;
+        while let Some(p) = cursor.next().await { self.insert(&mut fresh, &p?); }
+        let n = fresh.docs.len();
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = fresh;
+        Ok(n)
+    }
+
+    // перечитать один товар из базы: удалён — убрать из индекса
+    pub async fn refresh(&self, products: &Collection<Product>, id: ObjectId) -> mongodb::error::Result<()> {
+        match products.find_one(doc!{"_id": id}, None).await? {
+            Some(p) => self.upsert(&p),
+            None => self.remove(&id),
+        }
+        Ok(())
+    }
+
+    pub fn upsert(&self, p: &Product) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(id) = p.id { Self::delete(&mut inner, &id); }
+        self.insert(&mut inner, p);
+    }
+
+    pub fn remove(&self, id: &ObjectId) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        Self::delete(&mut inner, id);
+    }
+
+    fn stem(&self, word: &str) -> String {
+        if word.chars().any(|c| c.is_ascii_digit()) { return word.to_string(); }
+        if word.chars().any(|c| matches!(c, 'а'..='я')) { self.ru.stem(word).into_owned() } else { self.en.stem(word).into_owned() }
+    }
+
+    pub fn terms(&self, text: &str) -> Vec<String> {
+        words(text).iter().map(|w| self.stem(w)).collect()
+    }
+
+    // товары в корзине (deleted_at) не индексируются; upsert такого товара убирает его из индекса
+    fn insert(&self, inner: &mut Inner, p: &Product) {
+        let Some(id) = p.id else { return };
+        if p.deleted_at.is_some() { return; }
+        let mut weights: HashMap<String, f64> = HashMap::new();
+        let mut add = |terms: Vec<String>, w: f64| {
+            for t in terms { let e = weights.entry(t).or_insert(0.0); if *e < w { *e = w; } }
+        };
+        add(self.terms(&p.title), W_TITLE);
+        for a in p.aliases.iter() { add(self.terms(a), W_ALIAS); }
+        if let Some(b) = &p.brand { add(self.terms(b), W_BRAND); }
+        add(self.terms(&p.desc), W_DESC);
+        add(p.barcodes.clone(), W_BARCODE);
+        for (t, w) in weights.iter() { inner.postings.entry(t.clone()).or_default().insert(id, *w); }
+        inner.docs.insert(id, Entry { terms: weights.into_keys().collect(), category_ids: p.category_ids.clone(), title: p.title.clone() });
+    }
+
+    fn delete(inner: &mut Inner, id: &ObjectId) {
+        let Some(entry) = inner.docs.remove(id) else { return };
+        for t in entry.terms.iter() {
+            if let Some(list) = inner.postings.get_mut(t) {
+                list.remove(id);
+                if list.is_empty() { inner.postings.remove(t); }
+            }
+        }
+    }
+
+    // Термы словаря, подходящие под терм запроса, с множителем качества совпадения.
+    // Последнее слово запроса может быть недописанным — для него учитываются и продолжения.
+    fn expand(inner: &Inner, term: &str, is_last: bool) -> Vec<(String, f64)> {
+        let mut out: Vec<(String, f64)> = Vec::new();
+        if inner.postings.contains_key(term) { out.push((term.to_string(), 1.0)); }
+        if term.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            // числа и штрихкоды — только точное совпадение или (для кода) начало
+            if is_last && term.len() >= 4 {
+                out.extend(inner.postings.keys().filter(|k| k.len() > term.len() && k.starts_with(term)).map(|k| (k.clone(), Q_PREFIX)));
+            }
+            return out;
+        }
+        let len = term.chars().count();
+        let max_dist = if len >= 8 { 2 } else if len >= 4 { 1 } else { 0 };
+        for key in inner.postings.keys() {
+            if key == term { continue; }
+            if is_last && len >= 2 && key.starts_with(term) { out.push((key.clone(), Q_PREFIX)); continue; }
+            if max_dist == 0 { continue; }
+            let klen = key.chars().count();
+            if klen.abs_diff(len) > max_dist { continue; }
+            match strsim::osa_distance(term, key) {
+                1 => out.push((key.clone(), Q_TYPO_1)),
+                2 if max_dist >= 2 => out.push((key.clone(), Q_TYPO_2)),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    // Товары, где нашлось каждое слово запроса (точно, с опечаткой или как начало последнего),
+    // по убыванию оценки: Σ по словам max(вес поля × качество совпадения × idf терма).
+    pub fn search(&self, query: &str, category: Option<&HashSet<ObjectId>>) -> Vec<Hit> {
+        let mut terms: Vec<String> = Vec::new();
+        for w in words(query) {
+            // штрихкод ищем в том виде, в каком он хранится (EAN-13)
+            let t = crate::handlers::products::normalize_barcode(&w).unwrap_or_else(|| self.stem(&w));
+            if !terms.contains(&t) { terms.push(t); }
+        }
+        if terms.is_empty() { return Vec::new(); }
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let n = inner.docs.len().max(1) as f64;
+        let mut scores: HashMap<ObjectId, f64> = HashMap::new();
+        for (i, term) in terms.iter().enumerate() {
+            let mut best: HashMap<ObjectId, f64> = HashMap::new();
+            for (key, quality) in Self::expand(&inner, term, i + 1 == terms.len()) {
+                let Some(list) = inner.postings.get(&key) else { continue };
+                let idf = (1.0 + n / list.len() as f64).ln();
+                for (id, w) in list.iter() {
+                    let s = w * quality * idf;
+                    let e = best.entry(*id).or_insert(0.0);
+                    if *e < s { *e = s; }
+                }
+            }
+            if i == 0 {
+                scores = best;
+            } else {
+                scores = scores.into_iter().filter_map(|(id, s)| best.get(&id).map(|b| (id, s + b))).collect();
+            }
+            if scores.is_empty() { break; }
+        }
+        let mut hits: Vec<(Hit, &str)> = scores.into_iter()
+            .filter_map(|(id, score)| {
+                let entry = inner.docs.get(&id)?;
+                if let Some(cats) = category { if !entry.category_ids.iter().any(|c| cats.contains(c)) { return None; } }
+                Some((Hit { id, score }, entry.title.as_str()))
+            })
+            .collect();
+        hits.sort_by(|a, b| b.0.score.total_cmp(&a.0.score).then_with(|| a.1.cmp(b.1)));
+        hits.into_iter().map(|(h, _)| h).collect()
+    }
+}
+
+// --- подсказки при вводе (товары, магазины, категории) ---
+// Отдельный индекс: слова названий без стемминга в BTreeMap (поиск по началу слова)
+// и триграммы — запасной вариант для опечаток и середины слова.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestKind { Product, Category, Store }
+
+type Key = (SuggestKind, ObjectId);
+
+struct Suggestion {
+    label: String,
+    subtitle: Option<String>,
+    // название без регистра и знаков — для бонуса за совпадение с начала
+    norm: String,
+    words: Vec<String>,
+    trigrams: Vec<String>,
+}
+
+#[derive(Default)]
+struct SuggestInner {
+    items: HashMap<Key, Suggestion>,
+    words: BTreeMap<String, HashSet<Key>>,
+    trigrams: HashMap<String, HashSet<Key>>,
+}
+
+#[derive(Default)]
+pub struct SuggestIndex {
+    inner: RwLock<SuggestInner>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SuggestHit {
+    #[serde(rename = "type")]
+    pub kind: SuggestKind,
+    pub id: ObjectId,
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    pub score: f64,
+}
+
+// минимальная доля общих триграмм для нечёткой подсказки
+const TRIGRAM_MIN: f64 = 0.5;
+
+fn trigrams(words: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for w in words {
+        let chars: Vec<char> = format!(" {} ", w).chars().collect();
+        for t in chars.windows(3) {
+            let t: String = t.iter().collect();
+            if !out.contains(&t) { out.push(t); }
+        }
+    }
+    out
+}
+
+impl SuggestIndex {
+    pub async fn load(products: &Collection<Product>, stores: &Collection<Store>, categories: &Collection<Category>) -> mongodb::error::Result<Self> {
+        let index = SuggestIndex::default();
+        let n = index.rebuild(products, stores, categories).await?;
+        info!(items = n, "suggest index built");
+        Ok(index)
+    }
+
+    pub async fn rebuild(&self, products: &Collection<Product>, stores: &Collection<Store>, categories: &Collection<Category>) -> mongodb::error::Result<usize> {
+        let mut fresh = SuggestInner::default();
+        let mut cursor = products.find(None, None).await?;
+        while let Some(p) = cursor.next().await { Self::insert_product(&mut fresh, &p?); }
+        let mut cursor = stores.find(None, None).await?;
+        while let Some(s) = cursor.next().await { Self::insert_store(&mut fresh, &s?); }
+        let mut cursor = categories.find(None, None).await?;
+        while let Some(c) = cursor.next().await { Self::insert_category(&mut fresh, &c?); }
+        let n = fresh.items.len();
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = fresh;
+        Ok(n)
+    }
+
+    pub fn upsert_product(&self, p: &Product) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        Self::insert_product(&mut inner, p);
+    }
+
+    pub fn upsert_store(&self, s: &Store) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        Self::insert_store(&mut inner, s);
+    }
+
+    pub fn upsert_category(&self, c: &Category) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        Self::insert_category(&mut inner, c);
+    }
+
+    pub fn remove(&self, kind: SuggestKind, id: ObjectId) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        Self::delete(&mut inner, &(kind, id));
+    }
+
+    fn insert_product(inner: &mut SuggestInner, p: &Product) {
+        let Some(id) = p.id else { return };
+        if p.deleted_at.is_some() { return Self::delete(inner, &(SuggestKind::Product, id)); }
+        let mut text = vec![p.title.as_str()];
+        text.extend(p.aliases.iter().map(|a| a.as_str()));
+        if let Some(b) = &p.brand { text.push(b); }
+        Self::insert(inner, (SuggestKind::Product, id), &p.title, p.brand.clone(), &text);
+    }
+
+    fn insert_store(inner: &mut SuggestInner, s: &Store) {
+        let Some(id) = s.id else { return };
+        if s.deleted_at.is_some() { return Self::delete(inner, &(SuggestKind::Store, id)); }
+        let mut text = vec![s.name.as_str()];
+        if let Some(b) = &s.brand { text.push(b); }
+        let addr = Some(s.addr.clone()).filter(|a| !a.trim().is_empty());
+        Self::insert(inner, (SuggestKind::Store, id), &s.name, addr, &text);
+    }
+
+    fn insert_category(inner: &mut SuggestInner, c: &Category) {
+        let Some(id) = c.id else { return };
+        if c.deleted_at.is_some() { return Self::delete(inner, &(SuggestKind::Category, id)); }
+        Self::insert(inner, (SuggestKind::Category, id), &c.name, None, &[c.name.as_str()]);
+    }
+
+    fn insert(inner: &mut SuggestInner, key: Key, label: &str, subtitle: Option<String>, text: &[&str]) {
+        Self::delete(inner, &key);
+        let mut all: Vec<String> = Vec::new();
+        for t in text { for w in words(t) { if !all.contains(&w) { all.push(w); } } }
+        let tris = trigrams(&words(label));
+        for w in all.iter() { inner.words.entry(w.clone()).or_default().insert(key); }
+        for t in tris.iter() { inner.trigrams.entry(t.clone()).or_default().insert(key); }
+        inner.items.insert(key, Suggestion { label: label.to_string(), subtitle, norm: words(label).join(" "), words: all, trigrams: tris });
+    }
+
+    fn delete(inner: &mut SuggestInner, key: &Key) {
+        let Some(item) = inner.items.remove(key) else { return };
+        for w in item.words.iter() {
+            if let Some(set) = inner.words.get_mut(w) { set.remove(key); if set.is_empty() { inner.words.remove(w); } }
+        }
+        for t in item.trigrams.iter() {
+            if let Some(set) = inner.trigrams.get_mut(t) { set.remove(key); if set.is_empty() { inner.trigrams.remove(t); } }
+        }
+    }
+
+    // Каждое слово запроса — начало какого-то слова названия. Если таких мало,
+    // добавляются названия с долей общих триграмм от TRIGRAM_MIN (опечатки, середина слова).
+    pub fn suggest(&self, query: &str, kinds: &[SuggestKind], limit: usize) -> Vec<SuggestHit> {
+        let qwords = words(query);
+        if qwords.is_empty() || limit == 0 { return Vec::new(); }
+        let qnorm = qwords.join(" ");
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let allowed = |k: &Key| kinds.is_empty() || kinds.contains(&k.0);
+
+        let mut candidates: Option<HashSet<Key>> = None;
+        for w in qwords.iter() {
+            let mut found: HashSet<Key> = HashSet::new();
+            for (_, keys) in inner.words.range(w.clone()..).take_while(|(k, _)| k.starts_with(w.as_str())) {
+                found.extend(keys.iter().filter(|k| allowed(k)));
+            }
+            candidates = Some(match candidates { None => found, Some(c) => c.intersection(&found).copied().collect() });
+        }
+        let mut scored: HashMap<Key, f64> = HashMap::new();
+        for key in candidates.unwrap_or_default() {
+            let Some(item) = inner.items.get(&key) else { continue };
+            let mut score = 1.0;
+            if item.norm.starts_with(&qnorm) { score += 1.0; }
+            // совпадение в самом названии, а не в бренде или синониме
+            if qwords.iter().all(|w| item.norm.split(' ').any(|lw| lw.starts_with(w.as_str()))) { score += 0.25; }
+            if qwords.iter().all(|w| item.words.contains(w)) { score += 0.5; }
+            scored.insert(key, score);
+        }
+        if scored.len() < limit && qnorm.chars().count() >= 3 {
+            let qtris = trigrams(&qwords);
+            let mut shared: HashMap<Key, usize> = HashMap::new();
+            for t in qtris.iter() {
+                let Some(keys) = inner.trigrams.get(t) else { continue };
+                for k in keys.iter().filter(|k| allowed(k) && !scored.contains_key(k)) { *shared.entry(*k).or_insert(0) += 1; }
+            }
+            for (key, n) in shared {
+                let sim = n as f64 / qtris.len() as f64;
+                if sim >= TRIGRAM_MIN { scored.insert(key, sim); }
+            }
+        }
+        let mut ranked: Vec<(Key, f64, &Suggestion)> = scored.into_iter().filter_map(|(key, score)| Some((key, score, inner.items.get(&key)?))).collect();
+        // при равной оценке короткие названия и категории выше: «Молоко» раньше «Молоко сгущённое»
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.2.norm.len().cmp(&b.2.norm.len())).then(a.0.0.cmp(&b.0.0)).then_with(|| a.2.label.cmp(&b.2.label)));
+        ranked.truncate(limit);
+        ranked.into_iter().map(|(key, score, item)| SuggestHit { kind: key.0, id: key.1, label: item.label.clone(), subtitle: item.subtitle.clone(), score }).collect()
+    }
+}
+
+// после массовых изменений (импорт дампа, тестовые данные, импорт OSM)
+pub async fn rebuild_all(state: &AppState) -> mongodb::error::Result<()> {
+    state.search.rebuild(&state.products).await?;
+    state.suggest.rebuild(&state.products, &state.stores, &state.categories).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product(title: &str) -> Product {
+        Product {
+            id: Some(ObjectId::new()), title: title.into(), desc: String::new(), image_url: None, category_ids: Vec::new(),
+            unit: None, pack_size: None, barcodes: Vec::new(), brand: None, category_hints: Vec::new(),
+            imported_fields: Vec::new(), aliases: Vec::new(), deleted_at: None, deleted_by: None,
+        }
+    }
+
+    fn indexed(products: &[&Product]) -> ProductIndex {
+        let index = ProductIndex::default();
+        for p in products { index.upsert(p); }
+        index
+    }
+
+    fn found(index: &ProductIndex, query: &str) -> Vec<ObjectId> {
+        index.search(query, None).into_iter().map(|h| h.id).collect()
+    }
+
+    #[test]
+    fn splits_words() {
+        assert_eq!(words("Молоко 3,2% 930мл, Ёлка"), vec!["молоко", "3.2", "930", "мл", "елка"]);
+    }
+
+    #[test]
+    fn inflections_share_a_stem() {
+        let milk = product("Молоко Простоквашино");
+        let bread = product("Хлеб бородинский");
+        let index = indexed(&[&milk, &bread]);
+        for q in ["молоко", "молока", "молоку", "молоком"] {
+            assert_eq!(found(&index, q), vec![milk.id.unwrap()], "{}", q);
+        }
+        for q in ["хлеба", "хлебом", "бородинского хлеба"] {
+            assert_eq!(found(&index, q), vec![bread.id.unwrap()], "{}", q);
+        }
+        assert!(found(&index, "молоко бородинское").is_empty());
+    }
+
+    #[test]
+    fn yo_folds_to_ye() {
+        let tree = product("Ёлка искусственная");
+        let honey = product("Мед липовый");
+        let index = indexed(&[&tree, &honey]);
+        assert_eq!(found(&index, "елка"), vec![tree.id.unwrap()]);
+        assert_eq!(found(&index, "ЁЛКА"), vec![tree.id.unwrap()]);
+        assert_eq!(found(&index, "мёд"), vec![honey.id.unwrap()]);
+    }
+
+    #[test]
+    fn one_typo_matches() {
+        let milk = product("Молоко Простоквашино");
+        let kefir = product("Кефир");
+        let cheese = product("Сыр");
+        let index = indexed(&[&milk, &kefir, &cheese]);
+        assert_eq!(found(&index, "простаквашино"), vec![milk.id.unwrap()]);
+        assert_eq!(found(&index, "малоко простоквашино"), vec![milk.id.unwrap()]);
+        assert_eq!(found(&index, "кефри"), vec![kefir.id.unwrap()]);
+        // короткие слова — только точно
+        assert!(found(&index, "сор").is_empty());
+        // точное совпадение выше опечатки
+        let milk2 = product("Малоко");
+        let index = indexed(&[&milk, &milk2]);
+        assert_eq!(found(&index, "молоко").first(), Some(&milk.id.unwrap()));
+    }
+
+    #[test]
+    fn barcode_in_any_spelling() {
+        let mut p = product("Газировка");
+        p.barcodes = vec!["0036000291452".into()];
+        let index = indexed(&[&p]);
+        assert_eq!(found(&index, "036000291452"), vec![p.id.unwrap()]);
+        assert_eq!(found(&index, "0036000291452"), vec![p.id.unwrap()]);
+    }
+
+    #[test]
+    fn trashed_products_are_not_indexed() {
+        let mut p = product("Молоко");
+        let index = indexed(&[&p]);
+        p.deleted_at = Some(1);
+        index.upsert(&p);
+        assert!(found(&index, "молоко").is_empty());
+    }
+}
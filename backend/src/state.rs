@@ -1,14 +1,18 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use mongodb::{Collection, Database};
 use tracing::info;
 use argon2::{Argon2, password_hash::{PasswordHasher, SaltString}};
 
-use crate::models::{Product, Store, Category, StoreItem, StoreActivity, TelegramSettingsDoc, TelegramLink};
+use crate::models::{Product, Store, StoreChain, Region, Category, StoreItem, StoreActivity, TelegramSettingsDoc, TelegramLink};
 
 #[derive(Clone)]
 pub struct AppState {
     pub products: Collection<Product>,
     pub stores: Collection<Store>,
+    pub store_chains: Collection<StoreChain>,
+    pub regions: Collection<Region>,
     pub categories: Collection<Category>,
     pub store_items: Collection<StoreItem>,
     pub store_activities: Collection<StoreActivity>,
@@ -16,6 +20,9 @@ pub struct AppState {
     pub telegram_links: Collection<TelegramLink>,
     pub jwt_secret: String,
     pub db: Database,
+    pub search: Arc<crate::search::ProductIndex>,
+    pub suggest: Arc<crate::search::SuggestIndex>,
+    pub cache: Arc<crate::cache::ResponseCache>,
 }
 
 pub async fn init_from_env() -> Result<AppState> {
@@ -28,16 +35,25 @@ pub async fn init_from_env() -> Result<AppState> {
     let db = crate::db::mongo::get_database(&client, &db_name);
     let products: Collection<Product> = db.collection("products");
     let stores: Collection<Store> = db.collection("stores");
+    let store_chains: Collection<StoreChain> = db.collection("store_chains");
+    let regions: Collection<Region> = db.collection("regions");
     let categories: Collection<Category> = db.collection("categories");
     let store_items: Collection<StoreItem> = db.collection("store_items");
     let store_activities: Collection<StoreActivity> = db.collection("store_activities");
     let telegram_settings: Collection<TelegramSettingsDoc> = db.collection("settings");
     let telegram_links: Collection<TelegramLink> = db.collection("telegram_links");
 
+    // до индексов: уникальный индекс заданий импорта строится только по running
+    crate::handlers::imports::recover_import_jobs(&db).await?;
+    crate::db::indexes::ensure_indexes(&db).await?;
+    crate::db::migrations::migrate_money(&db).await?;
+    let search = Arc::new(crate::search::ProductIndex::load(&products).await?);
+    let suggest = Arc::new(crate::search::SuggestIndex::load(&products, &stores, &categories).await?);
+
     // seed admin if configured
     seed_admin(&db, &jwt_secret).await?;
 
-    Ok(AppState { products, stores, categories, store_items, store_activities, telegram_settings, telegram_links, jwt_secret, db })
+    Ok(AppState { products, stores, store_chains, regions, categories, store_items, store_activities, telegram_settings, telegram_links, jwt_secret, db, search, suggest, cache: Arc::new(crate::cache::ResponseCache::from_env()) })
 }
 
 async fn seed_admin(db: &mongodb::Database, _jwt_secret: &str) -> Result<()> {
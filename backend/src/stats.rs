@@ -0,0 +1,192 @@
+use crate::money::Money;
+
+// Устойчивая статистика цен для аналитики. Одна опечатка (9999 ₽ вместо 99.99) сдвигает среднее,
+// минимум и максимум, поэтому рядом с ними отдаются медиана, p10/p90 и усечённое среднее, а по
+// запросу (?outliers=mad|iqr) выбросы исключаются из всех показателей и помечаются в ответе.
+
+// усечённое среднее: отбрасывается по 10% цен с каждой стороны
+const TRIM: f64 = 0.1;
+// MAD -> σ для нормального распределения
+const MAD_SCALE: f64 = 1.4826;
+// среднее абсолютное отклонение -> σ; нужно, когда больше половины цен совпадают и MAD = 0
+const MEAN_AD_SCALE: f64 = 1.2533;
+// на меньшем числе цен выброс от нормы не отличить
+const MIN_POINTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum Outliers {
+    None,
+    // |x - медиана| > k·σ, σ из MAD
+    Mad { k: f64 },
+    // вне [Q1 - k·IQR, Q3 + k·IQR]
+    Iqr { k: f64 },
+}
+
+impl Outliers {
+    pub fn parse(method: Option<&str>, k: Option<f64>) -> Result<Self, &'static str> {
+        if k.is_some_and(|k| !k.is_finite() || k <= 0.0) { return Err("invalid_outlier_k"); }
+        match method.unwrap_or_default() {
+            "" | "none" => Ok(Outliers::None),
+            "mad" => Ok(Outliers::Mad { k: k.unwrap_or(3.0) }),
+            "iqr" => Ok(Outliers::Iqr { k: k.unwrap_or(1.5) }),
+            _ => Err("invalid_outliers"),
+        }
+    }
+
+    // true — цена исключена; в порядке prices
+    pub fn flag(self, prices: &[Money]) -> Vec<bool> {
+        if prices.len() < MIN_POINTS { return vec![false; prices.len()]; }
+        let mut sorted: Vec<Money> = prices.to_vec();
+        sorted.sort();
+        let to_f = |m: Option<Money>| m.map(|m| m.minor() as f64).unwrap_or_default();
+        let (lo, hi) = match self {
+            Outliers::None => return vec![false; prices.len()],
+            Outliers::Mad { k } => {
+                let med = to_f(Money::percentile(&sorted, 0.5));
+                let mut dev: Vec<f64> = sorted.iter().map(|p| (p.minor() as f64 - med).abs()).collect();
+                dev.sort_by(f64::total_cmp);
+                let n = dev.len();
+                let mad = (dev[(n - 1) / 2] + dev[n / 2]) / 2.0;
+                let sigma = if mad > 0.0 { MAD_SCALE * mad } else { MEAN_AD_SCALE * dev.iter().sum::<f64>() / dev.len() as f64 };
+                (med - k * sigma, med + k * sigma)
+            }
+            Outliers::Iqr { k } => {
+                let (q1, q3) = (to_f(Money::percentile(&sorted, 0.25)), to_f(Money::percentile(&sorted, 0.75)));
+                (q1 - k * (q3 - q1), q3 + k * (q3 - q1))
+            }
+        };
+        prices.iter().map(|p| { let x = p.minor() as f64; x < lo || x > hi }).collect()
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PriceStats {
+    // все цены / оставшиеся после исключения выбросов / исключённые
+    pub count: usize,
+    pub used: usize,
+    pub excluded: usize,
+    pub mean: Option<Money>,
+    pub median: Option<Money>,
+    pub p10: Option<Money>,
+    pub p90: Option<Money>,
+    pub trimmed_mean: Option<Money>,
+    pub min: Option<Money>,
+    pub max: Option<Money>,
+    // самая свежая цена и её возраст относительно as_of (или текущего времени)
+    pub newest_ts_ms: Option<i64>,
+    pub age_ms: Option<i64>,
+    pub outliers: Outliers,
+}
+
+// points — цена и время её последнего наблюдения; возвращает статистику и флаги исключённых точек
+pub fn summarize(points: &[(Money, Option<i64>)], outliers: Outliers, now_ms: i64) -> (PriceStats, Vec<bool>) {
+    let prices: Vec<Money> = points.iter().map(|(p, _)| *p).collect();
+    let flags = outliers.flag(&prices);
+    let kept: Vec<&(Money, Option<i64>)> = points.iter().zip(flags.iter()).filter(|(_, out)| !**out).map(|(p, _)| p).collect();
+    let mut sorted: Vec<Money> = kept.iter().map(|(p, _)| *p).collect();
+    sorted.sort();
+    let cut = (sorted.len() as f64 * TRIM).floor() as usize;
+    let newest = kept.iter().filter_map(|(_, ts)| *ts).max();
+    let stats = PriceStats {
+        count: points.len(),
+        used: sorted.len(),
+        excluded: points.len() - sorted.len(),
+        mean: Money::avg(&sorted),
+        median: Money::percentile(&sorted, 0.5),
+        p10: Money::percentile(&sorted, 0.1),
+        p90: Money::percentile(&sorted, 0.9),
+        trimmed_mean: Money::avg(&sorted[cut..sorted.len() - cut]),
+        min: sorted.first().copied(),
+        max: sorted.last().copied(),
+        newest_ts_ms: newest,
+        age_ms: newest.map(|ts| (now_ms - ts).max(0)),
+        outliers,
+    };
+    (stats, flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rub(v: &[f64]) -> Vec<Money> { v.iter().map(|r| Money::from_f64(*r)).collect() }
+
+    fn points(v: &[f64]) -> Vec<(Money, Option<i64>)> { rub(v).into_iter().map(|p| (p, None)).collect() }
+
+    const TYPO: [f64; 6] = [99.9, 100.0, 101.0, 99.5, 100.5, 9999.0];
+
+    #[test]
+    fn single_typo_is_flagged() {
+        let flagged = [false, false, false, false, false, true];
+        assert_eq!(Outliers::parse(Some("mad"), None).unwrap().flag(&rub(&TYPO)), flagged);
+        assert_eq!(Outliers::parse(Some("iqr"), None).unwrap().flag(&rub(&TYPO)), flagged);
+        assert_eq!(Outliers::None.flag(&rub(&TYPO)), [false; 6]);
+
+        let (stats, _) = summarize(&points(&TYPO), Outliers::Mad { k: 3.0 }, 0);
+        assert_eq!((stats.count, stats.used, stats.excluded), (6, 5, 1));
+        assert_eq!(stats.max, Some(Money::from_f64(101.0)));
+        assert_eq!(stats.median, Some(Money::from_f64(100.0)));
+    }
+
+    #[test]
+    fn all_equal_prices() {
+        let same = rub(&[100.0; 5]);
+        for o in [Outliers::Mad { k: 3.0 }, Outliers::Iqr { k: 1.5 }] { assert_eq!(o.flag(&same), [false; 5]); }
+        // больше половины цен совпадают: MAD = 0, σ берётся из среднего отклонения
+        let mostly_same = rub(&[100.0, 100.0, 100.0, 100.0, 9999.0]);
+        assert_eq!(Outliers::Mad { k: 3.0 }.flag(&mostly_same), [false, false, false, false, true]);
+    }
+
+    #[test]
+    fn too_few_points_are_never_flagged() {
+        for o in [Outliers::Mad { k: 3.0 }, Outliers::Iqr { k: 1.5 }] {
+            assert_eq!(o.flag(&rub(&[100.0, 9999.0])), [false, false]);
+            assert!(o.flag(&[]).is_empty());
+        }
+    }
+
+    #[test]
+    fn small_samples() {
+        let (one, _) = summarize(&points(&[50.0]), Outliers::None, 0);
+        assert_eq!((one.p10, one.median, one.p90, one.trimmed_mean), (Some(Money::from_f64(50.0)), Some(Money::from_f64(50.0)), Some(Money::from_f64(50.0)), Some(Money::from_f64(50.0))));
+
+        let (two, _) = summarize(&points(&[200.0, 100.0]), Outliers::None, 0);
+        assert_eq!((two.p10, two.median, two.p90), (Some(Money::from_f64(110.0)), Some(Money::from_f64(150.0)), Some(Money::from_f64(190.0))));
+
+        // меньше 10 цен — отбрасывать нечего, усечённое среднее равно обычному
+        let (five, _) = summarize(&points(&[1.0, 2.0, 3.0, 4.0, 100.0]), Outliers::None, 0);
+        assert_eq!(five.trimmed_mean, five.mean);
+        assert_eq!(five.mean, Some(Money::from_f64(22.0)));
+
+        // 10 цен — по одной с каждой стороны
+        let ten: Vec<f64> = (1..=9).map(|v| v as f64).chain([1000.0]).collect();
+        let (ten, _) = summarize(&points(&ten), Outliers::None, 0);
+        assert_eq!(ten.trimmed_mean, Some(Money::from_f64(5.5)));
+
+        let (none, flags) = summarize(&[], Outliers::Mad { k: 3.0 }, 0);
+        assert!(flags.is_empty() && none.mean.is_none() && none.median.is_none() && none.trimmed_mean.is_none());
+    }
+
+    #[test]
+    fn freshness() {
+        let pts = [(Money::from_f64(10.0), Some(1_000)), (Money::from_f64(11.0), Some(5_000)), (Money::from_f64(12.0), None)];
+        let (stats, _) = summarize(&pts, Outliers::None, 6_000);
+        assert_eq!((stats.newest_ts_ms, stats.age_ms), (Some(5_000), Some(1_000)));
+        // as_of раньше наблюдения — возраст не отрицательный
+        assert_eq!(summarize(&pts, Outliers::None, 0).0.age_ms, Some(0));
+    }
+
+    #[test]
+    fn median_matches_percentile() {
+        for v in [vec![100.0, 100.01], vec![1.0, 2.0, 3.0], vec![99.99, 0.01, 50.0, 50.01], vec![-0.01, -0.02]] {
+            let mut vals = rub(&v);
+            let median = Money::median(&mut vals);
+            assert_eq!(median, Money::percentile(&vals, 0.5));
+            assert_eq!(median, summarize(&points(&v), Outliers::None, 0).0.median);
+        }
+        // половина копейки — от нуля, как в avg
+        assert_eq!(Money::median(&mut rub(&[100.0, 100.01])), Some(Money::from_minor(10001)));
+        assert_eq!(Money::median(&mut rub(&[-0.01, -0.02])), Some(Money::from_minor(-2)));
+    }
+}
@@ -277,6 +277,7 @@ async fn link_account(state: &AppState, code: &str, chat_id: i64, username: Opti
         let mut set = doc!{"telegram_id": chat_id};
         if let Some(u) = username { set.insert("telegram_username", u); }
         users.update_one(doc!{"username": &link.username}, doc!{"$set": set}, None).await?;
+        state.cache.invalidate(&["users"]);
         // mark used
         if let Some(id) = link.id { let _ = state.telegram_links.update_one(doc!{"_id": id}, doc!{"$set": {"used": true}}, None).await; }
         return Ok(true);